@@ -41,6 +41,8 @@ type Channel struct {
 	Purpose struct {
 		Value string `json:"value"`
 	} `json:"purpose"`
+	Members   []string `json:"members"`
+	IsPrivate bool
 }
 
 type Message struct {
@@ -61,6 +63,9 @@ const (
 	EXTRACTION_DIR    = "/tmp/digester-extract"
 	USERS_FILEPATH    = EXTRACTION_DIR + "/users.json"
 	CHANNELS_FILEPATH = EXTRACTION_DIR + "/channels.json"
+	GROUPS_FILEPATH   = EXTRACTION_DIR + "/groups.json"
+	MPIMS_FILEPATH    = EXTRACTION_DIR + "/mpims.json"
+	DMS_FILEPATH      = EXTRACTION_DIR + "/dms.json"
 	SLACKBOT_ID       = "USLACKBOT"
 	UNKNOWN_USER_ID   = "UNKNOWNUSER"
 )
@@ -171,6 +176,46 @@ func unzipSource(src, dest string) error {
 	return nil
 }
 
+// readChannels reads a list of conversations from the export. Exports only
+// contain private channels (groups.json), group DMs (mpims.json) and DMs
+// (dms.json) if they were included, so missing files yield no conversations.
+func readChannels(path string, isPrivate bool) []Channel {
+	channelsFile, err := os.ReadFile(path)
+	if os.IsNotExist(err) {
+		return nil
+	}
+	CheckError(err)
+
+	var channels []Channel
+	err = json.Unmarshal(channelsFile, &channels)
+	CheckError(err)
+
+	for i := range channels {
+		channels[i].IsPrivate = isPrivate
+		// DMs have no name, their messages are stored in a directory named after their ID
+		if channels[i].Name == "" {
+			channels[i].Name = channels[i].ID
+		}
+	}
+	return channels
+}
+
+// replaceChannelMembers records who may see a channel. Only private channels and
+// DMs are restricted to their members, but members are kept for all channels.
+func replaceChannelMembers(channel Channel) {
+	_, err := db.Exec("DELETE FROM channel_members WHERE channel_id = $1;", channel.ID)
+	CheckError(err)
+
+	for _, member := range channel.Members {
+		if _, userExists := userSet[member]; !userExists {
+			continue
+		}
+		query := "INSERT INTO channel_members (channel_id, user_id) VALUES ($1, $2) ON CONFLICT DO NOTHING;"
+		_, err = db.Exec(query, channel.ID, member)
+		CheckError(err)
+	}
+}
+
 func queryExistingContent() {
 	rows, err := db.Query("SELECT id, name FROM users;")
 	CheckError(err)
@@ -319,12 +364,10 @@ func main() {
 		log.Info().Msg("Digester found no need to re-digest any existing user.")
 	}
 
-	channelsFile, err := os.ReadFile(CHANNELS_FILEPATH)
-	CheckError(err)
-
-	var channels []Channel
-	err = json.Unmarshal(channelsFile, &channels)
-	CheckError(err)
+	channels := readChannels(CHANNELS_FILEPATH, false)
+	channels = append(channels, readChannels(GROUPS_FILEPATH, true)...)
+	channels = append(channels, readChannels(MPIMS_FILEPATH, true)...)
+	channels = append(channels, readChannels(DMS_FILEPATH, true)...)
 
 	bar = getProgressBar(len(channels), "[cyan][2/4][reset] Extracting and digesting channels...          ")
 	newChannelsCount := 0
@@ -333,15 +376,17 @@ func main() {
 		bar.Add(1)
 		_, channelExists := channelSet[channel.ID]
 		if channelExists {
-			query := "UPDATE channels SET name = $1, topic = $2, purpose = $3 WHERE id = $4;"
-			_, err = db.Exec(query, channel.Name, channel.Topic.Value, channel.Purpose.Value, channel.ID)
+			query := "UPDATE channels SET name = $1, topic = $2, purpose = $3, is_private = $4 WHERE id = $5;"
+			_, err = db.Exec(query, channel.Name, channel.Topic.Value, channel.Purpose.Value, channel.IsPrivate, channel.ID)
 			CheckError(err)
+			replaceChannelMembers(channel)
 			existingChannelsUpdatedCount++
 			continue
 		}
-		query := "INSERT INTO channels (id, name, topic, purpose) VALUES ($1, $2, $3, $4)"
-		_, err = db.Exec(query, channel.ID, channel.Name, channel.Topic.Value, channel.Purpose.Value)
+		query := "INSERT INTO channels (id, name, topic, purpose, is_private) VALUES ($1, $2, $3, $4, $5)"
+		_, err = db.Exec(query, channel.ID, channel.Name, channel.Topic.Value, channel.Purpose.Value, channel.IsPrivate)
 		CheckError(err)
+		replaceChannelMembers(channel)
 		newChannelsCount++
 		channelSet[channel.ID] = channel.Name
 	}
@@ -449,6 +494,13 @@ func main() {
 	log.Info().Msg("Digester digested " + fmt.Sprint(newBotsCount) + " new bots and sent to tummy as users.")
 	log.Info().Msg("Digester digested " + fmt.Sprint(newMessagesCount) + " new messages and sent to the tummy.")
 
+	// The new messages are indexed for search here, which also refreshes the
+	// vocabulary behind spelling suggestions.
+	var indexedMessagesCount int
+	err = db.QueryRow("SELECT refresh_search_vocabulary();").Scan(&indexedMessagesCount)
+	CheckError(err)
+	log.Info().Msg("Tummy indexed " + fmt.Sprint(indexedMessagesCount) + " new messages for search.")
+
 	err = os.RemoveAll(EXTRACTION_DIR)
 	CheckError(err)
 	log.Info().Msg("Temporary slack extract cleaned.")
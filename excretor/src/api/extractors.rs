@@ -0,0 +1,187 @@
+//! Extractors for data the middleware attaches to requests.
+
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::Deref;
+
+use axum::async_trait;
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+
+use crate::api::errors::AppError;
+use crate::api::routes::{RouterState, FORBIDDEN_MSG};
+use crate::auth::roles::Role;
+use crate::auth::tokens::Scope;
+use crate::auth::AuthUser;
+
+/// Requires the request to be authenticated.
+/// Responds with `401 Unauthorized` when it is not, e.g. because login is disabled.
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthUser
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<AuthUser>()
+            .cloned()
+            .ok_or_else(|| (StatusCode::UNAUTHORIZED, FORBIDDEN_MSG).into_response())
+    }
+}
+
+/// Requires the authenticated user to be at least a moderator.
+pub struct Moderator(pub AuthUser);
+
+/// Requires the authenticated user to be an admin.
+/// API tokens additionally need the `admin` scope.
+pub struct Admin(pub AuthUser);
+
+impl Deref for Moderator {
+    type Target = AuthUser;
+
+    fn deref(&self) -> &AuthUser {
+        &self.0
+    }
+}
+
+impl Deref for Admin {
+    type Target = AuthUser;
+
+    fn deref(&self) -> &AuthUser {
+        &self.0
+    }
+}
+
+/// Extracts the authenticated user and checks that they hold at least `role`.
+/// Responds with `403 Forbidden` when they do not.
+async fn require_role(
+    parts: &mut Parts,
+    state: &RouterState,
+    role: Role,
+) -> Result<AuthUser, Response> {
+    let auth_user = AuthUser::from_request_parts(parts, state).await?;
+    let held = state
+        .roles
+        .role_of(&auth_user.user_id)
+        .await
+        .map_err(|err| AppError::from(err).into_response())?;
+
+    if held < role || (role == Role::Admin && !auth_user.has_scope(Scope::Admin)) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("This requires the `{}` role.", role),
+        )
+            .into_response());
+    }
+    Ok(auth_user)
+}
+
+#[async_trait]
+impl FromRequestParts<RouterState> for Moderator {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &RouterState,
+    ) -> Result<Self, Self::Rejection> {
+        require_role(parts, state, Role::Moderator).await.map(Moderator)
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<RouterState> for Admin {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &RouterState,
+    ) -> Result<Self, Self::Rejection> {
+        require_role(parts, state, Role::Admin).await.map(Admin)
+    }
+}
+
+/// The client's IP address, if known.
+/// Taken from `X-Forwarded-For` when `TRUST_PROXY_HEADERS` is set, and from the
+/// connection otherwise. Clients can send the header themselves, so only the
+/// entries appended by the `TRUSTED_PROXY_HOPS` proxies are believed: the client
+/// is the one the outermost proxy added, counting from the right.
+pub struct ClientIp(pub Option<IpAddr>);
+
+#[async_trait]
+impl FromRequestParts<RouterState> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &RouterState,
+    ) -> Result<Self, Self::Rejection> {
+        if state.env_vars.trust_proxy_headers {
+            let forwarded = forwarded_ip(&parts.headers, state.env_vars.trusted_proxy_hops);
+            if forwarded.is_some() {
+                return Ok(ClientIp(forwarded));
+            }
+        }
+
+        Ok(ClientIp(
+            parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip()),
+        ))
+    }
+}
+
+/// The `X-Forwarded-For` entry added by the outermost of `hops` trusted
+/// proxies, or `None` if there are fewer entries or it is not an IP address.
+fn forwarded_ip(headers: &HeaderMap, hops: usize) -> Option<IpAddr> {
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .rev()
+        .nth(hops.checked_sub(1)?)
+        .and_then(|ip| ip.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_client_ip_from_trusted_hops() {
+        // (X-Forwarded-For headers, trusted hops, client IP)
+        let cases: &[(&[&str], usize, Option<&str>)] = &[
+            (&["203.0.113.7"], 0, None),
+            (&["203.0.113.7"], 1, Some("203.0.113.7")),
+            // The client prepends a spoofed entry; the proxy appends the real one.
+            (&["198.51.100.1, 203.0.113.7"], 1, Some("203.0.113.7")),
+            (&["198.51.100.1, 203.0.113.7, 10.0.0.2"], 2, Some("203.0.113.7")),
+            (&["198.51.100.1,203.0.113.7", "10.0.0.2"], 2, Some("203.0.113.7")),
+            (&["203.0.113.7, 10.0.0.2"], 3, None),
+            (&["203.0.113.7"], 5, None),
+            (&[], 1, None),
+            (&["2001:db8::1"], 1, Some("2001:db8::1")),
+            (&["198.51.100.1, not-an-ip"], 1, None),
+        ];
+
+        for (values, hops, expected) in cases {
+            let mut headers = HeaderMap::new();
+            for value in *values {
+                headers.append("x-forwarded-for", value.parse().unwrap());
+            }
+            assert_eq!(
+                forwarded_ip(&headers, *hops),
+                expected.map(|ip| ip.parse().unwrap()),
+                "for {:?} with {} hops",
+                values,
+                hops
+            );
+        }
+    }
+}
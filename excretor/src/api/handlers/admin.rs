@@ -0,0 +1,498 @@
+//! Administrative handlers.
+//! Provides endpoints for listing and granting user roles, for querying and
+//! exporting the audit log, for configuring the languages of search, and for
+//! reporting on the searches users make.
+//! Moderators may see who holds which role; everything else requires the admin
+//! role.
+
+use crate::api::errors::AppError;
+use crate::api::extractors::{Admin, ClientIp, Moderator};
+use crate::api::models::{AuditLogResponse, SearchAnalyticsResponse, UserRolesResponse};
+use crate::api::routes::RouterState;
+use crate::audit::AuditAction;
+use crate::auth::roles::Role;
+use crate::search::analytics::SearchQueryReport;
+use crate::types::{AuditEntry, UserRole};
+use axum::extract::{Path, Query, State};
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::{http::StatusCode, response::Response, Json};
+use chrono::{Duration, NaiveDateTime, Utc};
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::Error::RowNotFound;
+
+/// Entries returned per page of the audit log when no limit is given.
+const AUDIT_PAGE_SIZE: i64 = 100;
+/// Most entries returned per page of the audit log.
+const MAX_AUDIT_PAGE_SIZE: i64 = 1000;
+/// Most entries included in one audit log export.
+const MAX_AUDIT_EXPORT_SIZE: i64 = 100_000;
+/// Days reported on by search analytics when no period is given.
+const SEARCH_ANALYTICS_DAYS: i64 = 30;
+/// Queries reported by search analytics when no limit is given.
+const SEARCH_ANALYTICS_SIZE: i64 = 50;
+/// Most queries reported by search analytics.
+const MAX_SEARCH_ANALYTICS_SIZE: i64 = 1000;
+/// Average latency from which queries count as slow when none is given.
+const SLOW_SEARCH_MS: f64 = 500.0;
+
+/// Request payload for changing a user's role.
+#[derive(Deserialize)]
+pub struct SetRoleRequest {
+    /// The new role.
+    role: Role,
+}
+
+/// Lists all users who hold a role other than viewer.
+/// Requires the moderator role.
+///
+/// # Parameters
+/// - `state`: Shared application state.
+///
+/// # Returns
+/// On success, returns a JSON response with the roles and HTTP 200 OK.
+/// On failure, returns an application error.
+pub async fn list_roles(
+    State(state): State<RouterState>,
+    _moderator: Moderator,
+) -> Result<(StatusCode, Response), AppError> {
+    let mut roles: Vec<UserRole> = state
+        .tummy
+        .get_user_roles()
+        .await?
+        .into_iter()
+        .filter(|role| !state.roles.is_bootstrap_admin(&role.user_id))
+        .map(Into::into)
+        .collect();
+
+    roles.extend(state.roles.bootstrap_admins().map(|user_id| UserRole {
+        user_id: user_id.clone(),
+        role: Role::Admin,
+        granted_by: None,
+        granted_at: None,
+    }));
+    roles.sort_by(|a, b| a.user_id.cmp(&b.user_id));
+
+    Ok((StatusCode::OK, Json(UserRolesResponse { roles }).into_response()))
+}
+
+/// Grants a role to a user. Granting `viewer` removes any other role.
+/// Requires the admin role.
+///
+/// # Parameters
+/// - `state`: Shared application state.
+/// - `admin`: The admin making the change.
+/// - `user_id`: The user ID as a path parameter.
+/// - `payload`: JSON body with the new role.
+///
+/// # Returns
+/// On success, returns HTTP 204 No Content.
+/// If the user does not exist, returns HTTP 404 Not Found.
+/// If the user's role is fixed by `ADMIN_USERS`, returns HTTP 409 Conflict.
+/// If admins try to change their own role, returns HTTP 400 Bad Request.
+/// On failure, returns an application error.
+pub async fn set_role(
+    State(state): State<RouterState>,
+    Admin(admin): Admin,
+    Path(user_id): Path<String>,
+    Json(payload): Json<SetRoleRequest>,
+) -> Result<(StatusCode, Response), AppError> {
+    if state.roles.is_bootstrap_admin(&user_id) {
+        return Ok((
+            StatusCode::CONFLICT,
+            "This user is an admin through ADMIN_USERS.".into_response(),
+        ));
+    }
+
+    if user_id == admin.user_id {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            "You cannot change your own role.".into_response(),
+        ));
+    }
+
+    match state.tummy.get_user_info(&user_id).await {
+        Ok(_) => {}
+        Err(RowNotFound) => {
+            return Ok((StatusCode::NOT_FOUND, "No such user.".into_response()));
+        }
+        Err(err) => return Err(err.into()),
+    }
+
+    if payload.role == Role::Viewer {
+        state.tummy.delete_user_role(&user_id).await?;
+    } else {
+        state
+            .tummy
+            .upsert_user_role(
+                &user_id,
+                payload.role.as_str(),
+                &admin.user_id,
+                Utc::now().naive_utc(),
+            )
+            .await?;
+    }
+
+    tracing::info!("{} made {} a {}.", admin.user_id, user_id, payload.role);
+    Ok((StatusCode::NO_CONTENT, ().into_response()))
+}
+
+/// Request payload for changing a channel's search language.
+#[derive(Deserialize)]
+pub struct SetSearchConfigRequest {
+    /// A text search configuration such as `german`, or `null` for the default.
+    config: Option<String>,
+}
+
+/// Query parameters for reindexing search.
+#[derive(Deserialize)]
+pub struct ReindexQuery {
+    /// Only reindex this channel.
+    channel_id: Option<String>,
+}
+
+/// Sets the text search configuration (language) a channel's messages are
+/// indexed and searched with. Existing messages keep their index until search
+/// is reindexed. Requires the admin role.
+///
+/// # Parameters
+/// - `state`: Shared application state.
+/// - `admin`: The admin making the change.
+/// - `channel_id`: The channel ID as a path parameter.
+/// - `payload`: JSON body with the configuration.
+///
+/// # Returns
+/// On success, returns HTTP 204 No Content.
+/// If the configuration does not exist, returns HTTP 400 Bad Request.
+/// If the channel does not exist, returns HTTP 404 Not Found.
+/// On failure, returns an application error.
+pub async fn set_channel_search_config(
+    State(state): State<RouterState>,
+    Admin(admin): Admin,
+    Path(channel_id): Path<String>,
+    Json(payload): Json<SetSearchConfigRequest>,
+) -> Result<(StatusCode, Response), AppError> {
+    if let Some(config) = &payload.config {
+        if !state.tummy.search_config_exists(config).await? {
+            return Ok((
+                StatusCode::BAD_REQUEST,
+                "No such text search configuration.".into_response(),
+            ));
+        }
+    }
+
+    if !state
+        .tummy
+        .set_channel_search_config(&channel_id, payload.config.as_deref())
+        .await?
+    {
+        return Ok((StatusCode::NOT_FOUND, "No such channel.".into_response()));
+    }
+
+    tracing::info!(
+        "{} set the search language of {} to {}.",
+        admin.user_id,
+        channel_id,
+        payload.config.as_deref().unwrap_or("the default")
+    );
+    Ok((StatusCode::NO_CONTENT, ().into_response()))
+}
+
+/// Starts rebuilding the full-text index of the messages, of one channel or
+/// all, that are not indexed with their channel's current language. The index
+/// is rebuilt in the background. Requires the admin role.
+///
+/// # Parameters
+/// - `state`: Shared application state.
+/// - `admin`: The admin starting the reindex.
+/// - `query`: Optionally the channel to reindex.
+///
+/// # Returns
+/// Returns HTTP 202 Accepted once the reindex has started.
+pub async fn reindex_search(
+    State(state): State<RouterState>,
+    Admin(admin): Admin,
+    Query(query): Query<ReindexQuery>,
+) -> Result<(StatusCode, Response), AppError> {
+    tracing::info!(
+        "{} started reindexing search of {}.",
+        admin.user_id,
+        query.channel_id.as_deref().unwrap_or("all channels")
+    );
+
+    tokio::spawn(async move {
+        match state.tummy.reindex_search(query.channel_id.as_deref()).await {
+            Ok(reindexed) => tracing::info!("Reindexed {} messages for search.", reindexed),
+            Err(err) => tracing::warn!("Could not reindex search: {}", err),
+        }
+    });
+
+    Ok((StatusCode::ACCEPTED, "Reindexing search.".into_response()))
+}
+
+/// How the audit log is returned.
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditLogFormat {
+    /// A page of entries as JSON.
+    #[default]
+    Json,
+    /// An export as CSV, with the parameters as a JSON column.
+    Csv,
+    /// An export as JSON Lines.
+    Jsonl,
+}
+
+/// Query parameters for the audit log.
+#[derive(Deserialize, Serialize)]
+pub struct AuditLogQuery {
+    /// Only entries of this user.
+    user_id: Option<String>,
+    /// Only entries of this action.
+    action: Option<AuditAction>,
+    /// Only entries at or after this time.
+    since: Option<NaiveDateTime>,
+    /// Only entries before this time.
+    until: Option<NaiveDateTime>,
+    /// Only entries with a lower ID, to fetch the next page.
+    before_id: Option<i64>,
+    /// The maximum number of entries.
+    limit: Option<i64>,
+    #[serde(default)]
+    format: AuditLogFormat,
+}
+
+/// Renders audit entries as CSV.
+fn audit_entries_to_csv(entries: &[AuditEntry]) -> color_eyre::Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(["id", "occurred_at", "user_id", "token_id", "ip", "action", "params"])?;
+    for entry in entries {
+        writer.write_record([
+            entry.id.to_string(),
+            entry.occurred_at.format("%Y-%m-%dT%H:%M:%S%.f").to_string(),
+            entry.user_id.clone().unwrap_or_default(),
+            entry.token_id.map(|id| id.to_string()).unwrap_or_default(),
+            entry.ip.clone().unwrap_or_default(),
+            entry.action.clone(),
+            entry.params.to_string(),
+        ])?;
+    }
+    writer.into_inner().map_err(|err| eyre!("Could not write CSV: {}", err))
+}
+
+/// Renders audit entries as JSON Lines.
+fn audit_entries_to_jsonl(entries: &[AuditEntry]) -> color_eyre::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    for entry in entries {
+        serde_json::to_writer(&mut body, entry)?;
+        body.push(b'\n');
+    }
+    Ok(body)
+}
+
+/// Queries or exports the audit log, newest entries first.
+/// Requires the admin role. Exports are themselves recorded in the audit log.
+///
+/// # Parameters
+/// - `state`: Shared application state.
+/// - `admin`: The admin querying the log.
+/// - `ip`: The client's IP address, for the audit log.
+/// - `query`: Filters, pagination and the format to return.
+///
+/// # Returns
+/// With `format=json`, returns a page of entries and HTTP 200 OK.
+/// With `format=csv` or `format=jsonl`, returns the matching entries as a file download.
+/// On failure, returns an application error.
+pub async fn get_audit_log(
+    State(state): State<RouterState>,
+    Admin(admin): Admin,
+    ClientIp(ip): ClientIp,
+    Query(query): Query<AuditLogQuery>,
+) -> Result<(StatusCode, Response), AppError> {
+    let limit = match query.format {
+        AuditLogFormat::Json => query.limit.unwrap_or(AUDIT_PAGE_SIZE).clamp(1, MAX_AUDIT_PAGE_SIZE),
+        AuditLogFormat::Csv | AuditLogFormat::Jsonl => query
+            .limit
+            .unwrap_or(MAX_AUDIT_EXPORT_SIZE)
+            .clamp(1, MAX_AUDIT_EXPORT_SIZE),
+    };
+
+    if !matches!(query.format, AuditLogFormat::Json) {
+        state
+            .audit
+            .record(
+                Some(&admin),
+                ip,
+                AuditAction::Export,
+                json!({ "source": "audit_log", "query": query }),
+            )
+            .await?;
+    }
+
+    let entries: Vec<AuditEntry> = state
+        .tummy
+        .get_audit_entries(
+            query.user_id.as_deref(),
+            query.action.map(|action| action.as_str()),
+            query.since,
+            query.until,
+            query.before_id,
+            limit,
+        )
+        .await?
+        .into_iter()
+        .map(Into::into)
+        .collect();
+
+    let (content_type, filename, body) = match query.format {
+        AuditLogFormat::Json => {
+            let next_before_id = (entries.len() as i64 == limit)
+                .then(|| entries.last().map(|entry| entry.id))
+                .flatten();
+            return Ok((
+                StatusCode::OK,
+                Json(AuditLogResponse {
+                    entries,
+                    next_before_id,
+                })
+                .into_response(),
+            ));
+        }
+        AuditLogFormat::Csv => ("text/csv", "audit-log.csv", audit_entries_to_csv(&entries)?),
+        AuditLogFormat::Jsonl => (
+            "application/jsonl",
+            "audit-log.jsonl",
+            audit_entries_to_jsonl(&entries)?,
+        ),
+    };
+
+    Ok((
+        StatusCode::OK,
+        (
+            [
+                (header::CONTENT_TYPE, content_type.to_owned()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"{}\"", filename),
+                ),
+            ],
+            body,
+        )
+            .into_response(),
+    ))
+}
+
+/// Query parameters for search analytics reports.
+#[derive(Deserialize)]
+pub struct SearchAnalyticsQuery {
+    /// Only searches at or after this time. Defaults to 30 days before `until`.
+    since: Option<NaiveDateTime>,
+    /// Only searches before this time. Defaults to now.
+    until: Option<NaiveDateTime>,
+    /// The maximum number of queries.
+    limit: Option<i64>,
+    /// For slow queries, the average latency from which a query is reported.
+    min_latency_ms: Option<f64>,
+}
+
+/// Reports on the searches logged over a period, grouped by query.
+async fn search_analytics_report(
+    state: &RouterState,
+    report: SearchQueryReport,
+    query: &SearchAnalyticsQuery,
+) -> Result<(StatusCode, Response), AppError> {
+    let until = query.until.unwrap_or_else(|| Utc::now().naive_utc());
+    let since = query
+        .since
+        .unwrap_or(until - Duration::days(SEARCH_ANALYTICS_DAYS));
+    if since >= until {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            "since must be before until.".into_response(),
+        ));
+    }
+
+    let queries = state
+        .tummy
+        .search_query_stats(
+            report,
+            since,
+            until,
+            query
+                .limit
+                .unwrap_or(SEARCH_ANALYTICS_SIZE)
+                .clamp(1, MAX_SEARCH_ANALYTICS_SIZE),
+        )
+        .await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(SearchAnalyticsResponse {
+            since,
+            until,
+            queries: queries.into_iter().map(Into::into).collect(),
+        })
+        .into_response(),
+    ))
+}
+
+/// Lists the most searched queries over a period. Requires the admin role.
+///
+/// # Parameters
+/// - `state`: Shared application state.
+/// - `query`: The period and the maximum number of queries.
+///
+/// # Returns
+/// On success, returns a JSON response with the queries and HTTP 200 OK.
+/// If the period is empty, returns HTTP 400 Bad Request.
+/// On failure, returns an application error.
+pub async fn top_search_queries(
+    State(state): State<RouterState>,
+    _admin: Admin,
+    Query(query): Query<SearchAnalyticsQuery>,
+) -> Result<(StatusCode, Response), AppError> {
+    search_analytics_report(&state, SearchQueryReport::Top, &query).await
+}
+
+/// Lists the queries most often searched without results over a period.
+/// Requires the admin role.
+///
+/// # Parameters
+/// - `state`: Shared application state.
+/// - `query`: The period and the maximum number of queries.
+///
+/// # Returns
+/// On success, returns a JSON response with the queries and HTTP 200 OK.
+/// If the period is empty, returns HTTP 400 Bad Request.
+/// On failure, returns an application error.
+pub async fn zero_result_search_queries(
+    State(state): State<RouterState>,
+    _admin: Admin,
+    Query(query): Query<SearchAnalyticsQuery>,
+) -> Result<(StatusCode, Response), AppError> {
+    search_analytics_report(&state, SearchQueryReport::ZeroResults, &query).await
+}
+
+/// Lists the queries slowest on average over a period, from `min_latency_ms`
+/// (500 by default) up. Requires the admin role.
+///
+/// # Parameters
+/// - `state`: Shared application state.
+/// - `query`: The period, the latency threshold and the maximum number of queries.
+///
+/// # Returns
+/// On success, returns a JSON response with the queries and HTTP 200 OK.
+/// If the period is empty, returns HTTP 400 Bad Request.
+/// On failure, returns an application error.
+pub async fn slow_search_queries(
+    State(state): State<RouterState>,
+    _admin: Admin,
+    Query(query): Query<SearchAnalyticsQuery>,
+) -> Result<(StatusCode, Response), AppError> {
+    let report = SearchQueryReport::Slow {
+        min_latency_ms: query.min_latency_ms.unwrap_or(SLOW_SEARCH_MS).max(0.0),
+    };
+    search_analytics_report(&state, report, &query).await
+}
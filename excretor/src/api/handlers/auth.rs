@@ -1,115 +1,238 @@
-//! Authentication handlers for Slack OAuth and JWT token issuance.
-//! Provides endpoints for starting the OAuth flow and handling the callback,
-//! including token creation and cookie management.
+//! Authentication handlers for the configured login provider and session token issuance.
+//! Provides endpoints for starting the login flow, handling the callback and
+//! logging out, including token creation and cookie management.
 
-use std::collections::BTreeMap;
 use axum::body::Body;
 use axum::extract::{Query, State};
 use axum::http::StatusCode;
-use axum::response::{IntoResponse, Response};
+use axum::response::{Html, IntoResponse, Response};
 use axum_extra::extract::CookieJar;
+use chrono::{DateTime, Utc};
 use cookie::Cookie;
 use cookie::time::Duration;
-use hmac::{Hmac, Mac};
-use jwt::SignWithKey;
-use reqwest::Client;
-use serde::Deserialize;
-use sha2::Sha256;
+use serde::{Deserialize, Serialize};
 use crate::api::errors::AppError;
 use crate::api::routes::{RouterState, FORBIDDEN_MSG};
+use crate::auth::login_state::LOGIN_STATE_TTL_MINUTES;
+use crate::auth::providers::{FlowState, LoginError};
+use crate::auth::sessions::CachedSession;
+
+/// Name of the cookie carrying the login flow from `/auth` to `/auth/callback`.
+const AUTH_FLOW_COOKIE: &str = "auth_flow";
+
+/// Claims of the signed login flow cookie.
+#[derive(Serialize, Deserialize)]
+struct FlowClaims {
+    /// The single-use `state` nonce sent to the provider.
+    state: String,
+    /// When the login attempt expires, as a Unix timestamp.
+    exp: i64,
+    /// Provider-specific flow state.
+    flow: FlowState,
+}
 
 /// Query parameters for the OAuth callback.
 #[derive(Deserialize)]
 pub struct AuthCallback {
-    code: String,
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
 }
 
-/// Initiates the Slack OAuth authentication flow.
-/// Redirects the user to Slack's authorization URL.
+/// Renders a page explaining why logging in failed, with a link to try again.
+fn login_error_page(
+    state: &RouterState,
+    status: StatusCode,
+    jar: CookieJar,
+    message: &str,
+) -> (StatusCode, CookieJar, Response) {
+    let page = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>Login failed | {title}</title><link rel="stylesheet" href="/assets/styles.css"></head>
+<body>
+<main>
+<h1>Could not log you in</h1>
+<p>{message}</p>
+<p><a href="/auth">Try again</a></p>
+</main>
+</body>
+</html>"#,
+        title = state.env_vars.title,
+        message = message,
+    );
+
+    (status, jar, Html(page).into_response())
+}
+
+/// Initiates the authentication flow with the configured login provider.
+/// Redirects the user to the provider's authorization URL.
 ///
 /// # Parameters
-/// - `state`: Shared application state containing environment variables.
+/// - `state`: Shared application state containing the login provider.
+/// - `jar`: Cookie jar for storing the login flow state.
 ///
 /// # Returns
-/// A redirect response to Slack's OAuth page.
+/// A redirect response to the provider's login page.
+/// If the provider cannot be reached, returns an error page with HTTP 502 Bad Gateway.
 pub async fn auth(
     State(state): State<RouterState>,
-) -> Result<(StatusCode, Response), AppError> {
-    let scopes = "im:read";
-    let slack_auth_url = format!(
-        "https://slack.com/oauth/v2/authorize?client_id={}&scope={}&redirect_uri={}",
-        state.env_vars.slack_client_id, scopes, state.env_vars.slack_redirect_uri
-    );
+    jar: CookieJar,
+) -> Result<(StatusCode, CookieJar, Response), AppError> {
+    let (nonce, expires_at) = state.login_states.issue();
+
+    let redirect = match state.provider.authorize(&nonce).await {
+        Ok(redirect) => redirect,
+        Err(err) => {
+            tracing::error!("Could not start login with {}: {}", state.provider.name(), err);
+            return Ok(login_error_page(
+                &state,
+                StatusCode::BAD_GATEWAY,
+                jar,
+                "The login provider could not be reached. Please try again later.",
+            ));
+        }
+    };
+
+    let flow = FlowClaims {
+        state: nonce,
+        exp: expires_at.and_utc().timestamp(),
+        flow: redirect.flow,
+    };
+
+    let flow_cookie = Cookie::build((AUTH_FLOW_COOKIE, state.keys.sign(&flow)?))
+        .path("/auth")
+        .secure(true)
+        .http_only(true)
+        .same_site(cookie::SameSite::Lax)
+        .max_age(Duration::minutes(LOGIN_STATE_TTL_MINUTES));
 
     Ok((
         StatusCode::FOUND,
+        jar.add(flow_cookie),
         Response::builder()
-            .header("Location", slack_auth_url)
+            .header("Location", redirect.url)
             .body(Body::empty())
             .unwrap(),
     ))
 }
 
-/// Handles the OAuth callback from Slack.
-/// Exchanges the code for an access token, verifies the user, and sets a JWT cookie.
+/// Handles the callback from the login provider.
+/// Checks the `state` nonce, completes the login, verifies the user, starts a
+/// session and sets the session token cookie.
 ///
 /// # Parameters
 /// - `state`: Shared application state.
-/// - `request`: Query parameters containing the OAuth code.
+/// - `request`: Query parameters containing the OAuth code and state.
 /// - `jar`: Cookie jar for setting authentication cookies.
 ///
 /// # Returns
-/// On success, sets a JWT token cookie and redirects to the home page.
-/// On failure, returns an unauthorized response.
+/// On success, sets the session token cookie and redirects to the home page.
+/// On failure, returns an error page explaining what went wrong.
 pub async fn auth_callback(
     State(state): State<RouterState>,
     Query(request): Query<AuthCallback>,
     jar: CookieJar,
 ) -> Result<(StatusCode, CookieJar, Response), AppError> {
-    let code = request.code;
-    let slack_auth_url = format!(
-        "https://slack.com/api/oauth.v2.access?client_id={}&client_secret={}&code={}&redirect_uri={}",
-        state.env_vars.slack_client_id, state.env_vars.slack_client_secret, code, state.env_vars.slack_redirect_uri
-    );
-    // Request Slack for access token
-    let response = Client::new().get(slack_auth_url).send().await?;
+    let flow: Option<FlowClaims> = jar
+        .get(AUTH_FLOW_COOKIE)
+        .and_then(|cookie| state.keys.verify(cookie.value()).ok());
+    let jar = jar.remove(Cookie::build(AUTH_FLOW_COOKIE).path("/auth"));
 
-    if response.status() != StatusCode::OK {
-        return Ok((
-            StatusCode::UNAUTHORIZED,
-            jar,
-            Body::from(FORBIDDEN_MSG).into_response(),
-        ));
-    }
+    let expired_msg = "Your login attempt expired or was already used. Please try again.";
+    let Some(flow) = flow else {
+        tracing::warn!("Login callback without a valid login flow cookie.");
+        return Ok(login_error_page(&state, StatusCode::BAD_REQUEST, jar, expired_msg));
+    };
 
-    let body = response.text().await?;
-    let json_body: serde_json::Value = serde_json::from_str(&body)?;
+    let Some(expires_at) = DateTime::<Utc>::from_timestamp(flow.exp, 0) else {
+        return Ok(login_error_page(&state, StatusCode::BAD_REQUEST, jar, expired_msg));
+    };
 
-    let access_token = json_body["access_token"].as_str().unwrap();
+    if request.state.as_deref() != Some(flow.state.as_str()) {
+        tracing::warn!("Login callback with a mismatched state parameter.");
+        return Ok(login_error_page(&state, StatusCode::BAD_REQUEST, jar, expired_msg));
+    }
 
-    let user_id = json_body["authed_user"]["id"].as_str().unwrap();
-    let user = state.tummy.get_user_info(user_id).await?;
+    if !state.login_states.consume(&flow.state, expires_at.naive_utc()) {
+        tracing::warn!("Login callback with an expired or reused state parameter.");
+        return Ok(login_error_page(&state, StatusCode::BAD_REQUEST, jar, expired_msg));
+    }
 
-    if user.id.is_empty() || user.is_bot || user.deleted {
-        return Ok((
+    if let Some(error) = request.error {
+        tracing::info!("Login provider returned an error: {}", error);
+        return Ok(login_error_page(
+            &state,
             StatusCode::UNAUTHORIZED,
             jar,
-            Body::from(FORBIDDEN_MSG).into_response(),
+            "The login was cancelled or refused by the login provider.",
         ));
     }
 
-    let key: Hmac<Sha256> =
-        Hmac::new_from_slice(state.env_vars.slack_signing_secret.as_bytes()).unwrap();
-    let mut claims = BTreeMap::new();
-    claims.insert("user_id", user_id);
-    claims.insert("access_token", access_token);
-    let token_str = claims.sign_with_key(&key)?;
+    let Some(code) = request.code else {
+        return Ok(login_error_page(
+            &state,
+            StatusCode::BAD_REQUEST,
+            jar,
+            "The login provider did not send back an authorization code.",
+        ));
+    };
+
+    let identity = match state.provider.callback(&code, &flow.flow).await {
+        Ok(identity) => identity,
+        Err(LoginError::Denied) => {
+            return Ok(login_error_page(
+                &state,
+                StatusCode::UNAUTHORIZED,
+                jar,
+                "The login was cancelled or refused by the login provider.",
+            ));
+        }
+        Err(LoginError::Forbidden(reason)) => {
+            tracing::warn!("Rejected login: {}", reason);
+            return Ok(login_error_page(
+                &state,
+                StatusCode::FORBIDDEN,
+                jar,
+                FORBIDDEN_MSG,
+            ));
+        }
+        Err(LoginError::Provider(err)) => {
+            tracing::error!("Login with {} failed: {}", state.provider.name(), err);
+            return Ok(login_error_page(
+                &state,
+                StatusCode::BAD_GATEWAY,
+                jar,
+                "The login provider sent an unexpected response. Please try again later.",
+            ));
+        }
+        Err(LoginError::Internal(err)) => {
+            tracing::error!("Login failed: {}", err);
+            return Ok(login_error_page(
+                &state,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                jar,
+                "Something went wrong. Please try again later.",
+            ));
+        }
+    };
+
+    let token_str = state
+        .sessions
+        .create(
+            &identity.user.id,
+            identity.team_id.as_deref(),
+            &identity.access_token,
+        )
+        .await?;
 
-    let token_cookie = Cookie::build(("token", token_str))
+    let mut token_cookie = Cookie::build(("token", token_str))
         .path("/")
         .secure(true)
-        .http_only(true)
-        .max_age(Duration::days(state.env_vars.keep_logged_in_for_days));
+        .http_only(true);
+    if state.env_vars.keep_logged_in_for_days > 0 {
+        token_cookie = token_cookie.max_age(Duration::days(state.env_vars.keep_logged_in_for_days));
+    }
 
     Ok((
         StatusCode::PERMANENT_REDIRECT,
@@ -119,4 +242,52 @@ pub async fn auth_callback(
             .body(Body::empty())
             .unwrap(),
     ))
-}
\ No newline at end of file
+}
+
+/// Logs the user out.
+/// Revokes the provider's access token issued to this user at login, ends the
+/// server-side session and clears the cookie.
+/// Only mounted on POST, so that other sites cannot log users out with a link or image.
+///
+/// # Parameters
+/// - `state`: Shared application state.
+/// - `jar`: Cookie jar holding the login token.
+///
+/// # Returns
+/// A redirect to the login page.
+pub async fn logout(
+    State(state): State<RouterState>,
+    jar: CookieJar,
+) -> Result<(StatusCode, CookieJar, Response), AppError> {
+    let claims = jar
+        .get("token")
+        .and_then(|cookie| state.sessions.claims(cookie.value()));
+
+    if let Some(claims) = claims {
+        let session = match state.sessions.lookup(&claims).await? {
+            CachedSession::Fresh(session) | CachedSession::Stale(session) => Some(session),
+            CachedSession::Missing => None,
+        };
+
+        match session.as_ref().map(|session| state.sessions.access_token(session)) {
+            Some(Ok(Some(access_token))) => {
+                if let Err(err) = state.provider.revoke(&access_token).await {
+                    tracing::warn!("Could not revoke {} token: {}", state.provider.name(), err);
+                }
+            }
+            Some(Err(err)) => tracing::info!("Could not decrypt session access token: {}", err),
+            _ => {}
+        }
+
+        state.sessions.revoke(&claims.sid).await?;
+    }
+
+    Ok((
+        StatusCode::SEE_OTHER,
+        jar.remove(Cookie::build("token").path("/")),
+        Response::builder()
+            .header("Location", "/login")
+            .body(Body::empty())
+            .unwrap(),
+    ))
+}
@@ -2,24 +2,31 @@
 //! Provides endpoints for listing all channels and loading details for a specific channel.
 
 use crate::api::errors::AppError;
+use crate::api::extractors::ClientIp;
 use crate::api::routes::RouterState;
+use crate::audit::AuditAction;
+use crate::auth::AuthUser;
 use axum::extract::{Path, State};
 use axum::response::IntoResponse;
 use axum::{http::StatusCode, response::{Response, Json}};
 use crate::api::models::{ChannelsResponse, ChannelDetailsResponse};
+use serde_json::json;
 
 /// Fetches all available channels.
 ///
 /// # Parameters
 /// - `state`: Shared application state.
+/// - `auth_user`: The authenticated user, if login is enabled.
 ///
 /// # Returns
-/// On success, returns a JSON response with a list of channels and HTTP 200 OK.
+/// On success, returns a JSON response with a list of channels the user may see and HTTP 200 OK.
 /// On failure, returns an application error.
 pub async fn get_channels(
     State(state): State<RouterState>,
+    auth_user: Option<AuthUser>,
 ) -> Result<(StatusCode, Response), AppError> {
-    let channels = state.tummy.get_all_channels().await?;
+    let viewer = auth_user.as_ref().map(|user| user.user_id.as_str());
+    let channels = state.tummy.get_all_channels(viewer).await?;
     Ok((
         StatusCode::OK,
         Json(
@@ -34,20 +41,42 @@ pub async fn get_channels(
 ///
 /// # Parameters
 /// - `state`: Shared application state.
+/// - `auth_user`: The authenticated user, if login is enabled.
+/// - `ip`: The client's IP address, for the audit log.
 /// - `channel`: The channel id as a path parameter.
 ///
 /// # Returns
 /// On success, returns a JSON response with channel details, last message timestamp,
 /// messages, and channel ID, with HTTP 200 OK.
+/// If the channel does not exist or the user may not see it, returns HTTP 404 Not Found.
 /// On failure, returns an application error.
 pub async fn load_channel(
     State(state): State<RouterState>,
+    auth_user: Option<AuthUser>,
+    ClientIp(ip): ClientIp,
     Path(channel_id): Path<String>,
 ) -> Result<(StatusCode, Response), AppError> {
-    let channel = state.tummy.get_channel_info(&channel_id).await?;
+    state
+        .audit
+        .record(
+            auth_user.as_ref(),
+            ip,
+            AuditAction::LoadChannel,
+            json!({ "channel_id": channel_id }),
+        )
+        .await?;
+
+    let viewer = auth_user.as_ref().map(|user| user.user_id.as_str());
+    let channel = match state.tummy.get_channel_info(&channel_id, viewer).await {
+        Ok(channel) => channel,
+        Err(sqlx::Error::RowNotFound) => {
+            return Ok((StatusCode::NOT_FOUND, "No such channel.".into_response()));
+        }
+        Err(err) => return Err(err.into()),
+    };
     let messages = state
         .tummy
-        .fetch_msg_page(&channel.id, &None, &50)
+        .fetch_msg_page(&channel.id, &None, &50, viewer)
         .await?;
     let channel_id = channel.id.clone();
     Ok((
@@ -55,11 +84,9 @@ pub async fn load_channel(
         Json(
             ChannelDetailsResponse{
                 channel,
-                before_msg_timestamp: if let Some(last_msg) = messages.first() {
-                    Some(last_msg.timestamp.format("%Y-%m-%dT%H:%M:%S%.f").to_string())
-                } else {
-                    None
-                },
+                before_msg_timestamp: messages
+                    .first()
+                    .map(|last_msg| last_msg.timestamp.format("%Y-%m-%dT%H:%M:%S%.f").to_string()),
                 messages,
                 channel_id,
             }
@@ -4,13 +4,21 @@
 
 use crate::db::tummy::SlackDateTime;
 use crate::api::errors::AppError;
+use crate::api::extractors::ClientIp;
 use crate::api::routes::RouterState;
+use crate::audit::AuditAction;
+use crate::auth::AuthUser;
 use axum::response::IntoResponse;
 use axum::extract::{Form, Path, Query, State};
 use axum::{http::StatusCode, response::Response, Json};
-use chrono::NaiveDateTime;
+use chrono::{Duration, NaiveDateTime};
 use serde::Deserialize;
+use serde_json::json;
 use crate::api::models;
+use crate::db::search::MAX_SEARCH_WINDOW;
+use crate::search::{self, SearchMode, SearchOptions, SearchSort};
+use crate::types::{Suggestion, SuggestionKind};
+use std::time::{Duration as StdDuration, Instant};
 
 /// Request payload for fetching replies to a message.
 #[derive(Deserialize)]
@@ -26,7 +34,7 @@ pub struct ReplyRequest {
 /// Form data for searching messages.
 #[derive(Deserialize)]
 pub struct SearchQuery {
-    /// Search query string.
+    /// Search query in the search query language, e.g. `deploy from:@alice in:#ops`.
     query: String,
     /// Optional channel ID to filter search.
     channel_id: Option<String>,
@@ -36,6 +44,89 @@ pub struct SearchQuery {
     before: Option<String>,
     /// Optional after time parameter
     after: Option<String>,
+    /// Number of results to skip.
+    offset: Option<i64>,
+    /// Number of results per page.
+    per_page: Option<i64>,
+    /// Whether to match single `messages` (the default) or whole `threads`.
+    #[serde(default)]
+    mode: SearchMode,
+    /// Order of the results: `relevance` (the default), `newest` or `oldest`.
+    #[serde(default)]
+    sort: SearchSort,
+    /// Overrides the `k` of the relevance ranking.
+    rrf_k: Option<f64>,
+    /// Overrides the weight of trigram similarity matches.
+    weight_fuzzy: Option<f64>,
+    /// Overrides the weight of full-text matches.
+    weight_full_text: Option<f64>,
+    /// Overrides the weight of prefix matches.
+    weight_prefix: Option<f64>,
+    /// Overrides the weight of semantic matches, if semantic search is enabled.
+    weight_semantic: Option<f64>,
+    /// Whether to return each result's per-signal ranks and score.
+    #[serde(default)]
+    explain: bool,
+}
+
+/// Number of search results per page unless requested otherwise.
+const DEFAULT_SEARCH_PER_PAGE: i64 = 30;
+
+/// Largest page of search results that can be requested.
+const MAX_SEARCH_PER_PAGE: i64 = 100;
+
+/// Number of channels and of users returned as search facets.
+const SEARCH_FACET_LIMIT: i64 = 20;
+
+/// Searches with fewer hits than this get a spelling suggestion.
+const SUGGESTION_MAX_HITS: i64 = 5;
+
+/// Number of corrected queries offered by the spelling suggestions endpoint.
+const MAX_SPELLING_SUGGESTIONS: usize = 3;
+
+/// Query parameters for spelling suggestions.
+#[derive(Deserialize)]
+pub struct SpellingQuery {
+    /// Search query in the search query language.
+    query: String,
+}
+
+/// Number of autocomplete suggestions unless requested otherwise.
+const DEFAULT_SUGGESTIONS: i64 = 10;
+
+/// Most autocomplete suggestions that can be requested.
+const MAX_SUGGESTIONS: i64 = 25;
+
+/// SQLSTATE of a query cancelled by `statement_timeout`.
+const QUERY_CANCELED: &str = "57014";
+
+/// Query parameters for autocompleting the search bar.
+#[derive(Deserialize)]
+pub struct SuggestQuery {
+    /// The text typed so far. A leading `@` or `from:` only suggests users, a
+    /// leading `#` or `in:` only channels.
+    q: String,
+    /// Number of suggestions.
+    limit: Option<i64>,
+}
+
+/// Number of related messages returned unless requested otherwise.
+const DEFAULT_RELATED: i64 = 10;
+
+/// Most related messages that can be requested.
+const MAX_RELATED: i64 = 50;
+
+/// Query parameters for finding related messages.
+#[derive(Deserialize)]
+pub struct RelatedQuery {
+    /// Optional channel ID to restrict the related messages to.
+    channel_id: Option<String>,
+    /// Optional before time parameter
+    before: Option<String>,
+    /// Optional after time parameter
+    after: Option<String>,
+    /// Number of related messages.
+    limit: Option<i64>,
 }
 
 /// Query parameters for paginating messages.
@@ -47,62 +138,428 @@ pub struct Pagination {
     per_page: u32,
 }
 
-/// Query parameters for filtering messages by date.
-#[derive(Deserialize)]
-pub struct DateQuery {
-}
-
-/// Searches messages by text, channel, and user.
+/// Searches messages with the search query language, optionally restricted
+/// further by channel, user and time.
+/// Results are sorted by relevance or date and paginated with `offset` and
+/// `per_page`, up to the first `MAX_SEARCH_WINDOW` results. The relevance
+/// ranking can be tuned per request, and explained with `explain=true`.
+/// With semantic search enabled, messages are also matched by the meaning of
+/// the query's text.
+/// With `mode=threads`, whole threads are matched and returned as their
+/// top-level messages with excerpts of the matching replies.
+/// Searches with few hits suggest a query with misspelled words corrected.
+/// First pages are logged for search analytics with their hits and latency.
 ///
 /// # Parameters
 /// - `state`: Shared application state.
+/// - `auth_user`: The authenticated user, if login is enabled.
+/// - `ip`: The client's IP address, for the audit log.
 /// - `payload`: Form data containing the search query and optional filters.
 ///
 /// # Returns
-/// On success, returns a JSON response with matching messages and HTTP 200 OK.
+/// On success, returns a JSON response with a page of matching messages and hit
+/// counts by channel, user and month over all matches, with HTTP 200 OK.
+/// If the query or the `before` and `after` timestamps cannot be parsed, returns
+/// the parse error as JSON with HTTP 400 Bad Request.
+/// If the ranking parameters are out of range, returns HTTP 400 Bad Request.
 /// On failure, returns an application error.
 pub async fn search(
     State(state): State<RouterState>,
+    auth_user: Option<AuthUser>,
+    ClientIp(ip): ClientIp,
     Form(payload): Form<SearchQuery>,
 ) -> Result<(StatusCode, Response), AppError> {
-    let messages = state
-        .tummy
-        .search_msg_text(
-            &payload.query,
-            payload.channel_id.as_deref(),
-            payload.user_id.as_deref(),
-            30,
-            payload.before.map(|ts| {NaiveDateTime::from_pg_ts(&ts)}),
-            payload.after.map(|ts| {NaiveDateTime::from_pg_ts(&ts)})
+    let started = Instant::now();
+    state
+        .audit
+        .record(
+            auth_user.as_ref(),
+            ip,
+            AuditAction::Search,
+            json!({
+                "query": payload.query,
+                "channel_id": payload.channel_id,
+                "user_id": payload.user_id,
+                "before": payload.before,
+                "after": payload.after,
+                "offset": payload.offset,
+                "per_page": payload.per_page,
+                "mode": payload.mode,
+                "sort": payload.sort,
+            }),
         )
         .await?;
+
+    let mut query = match search::parse(&payload.query) {
+        Ok(query) => query,
+        Err(error) => {
+            return Ok((
+                StatusCode::BAD_REQUEST,
+                Json(models::SearchErrorResponse { error }).into_response(),
+            ));
+        }
+    };
+    let ranking = match state.ranking.with_overrides(
+        payload.rrf_k,
+        payload.weight_fuzzy,
+        payload.weight_full_text,
+        payload.weight_prefix,
+        payload.weight_semantic,
+        state.embeddings.is_some(),
+    ) {
+        Ok(ranking) => ranking,
+        Err(message) => return Ok((StatusCode::BAD_REQUEST, message.into_response())),
+    };
+
+    let parse_ts = |parameter: &str, ts: &Option<String>| {
+        ts.as_deref()
+            .map(|ts| {
+                NaiveDateTime::parse_from_str(ts, "%Y-%m-%dT%H:%M:%S%.f")
+                    .map_err(|_| search::QueryError::invalid_timestamp(parameter, ts))
+            })
+            .transpose()
+    };
+    let (before, after) = match (parse_ts("before", &payload.before), parse_ts("after", &payload.after)) {
+        (Ok(before), Ok(after)) => (before, after),
+        (Err(error), _) | (_, Err(error)) => {
+            return Ok((
+                StatusCode::BAD_REQUEST,
+                Json(models::SearchErrorResponse { error }).into_response(),
+            ));
+        }
+    };
+
+    query.channel_id = payload.channel_id;
+    query.user_id = payload.user_id;
+    if let Some(before) = before {
+        query.limit_until(before);
+    }
+    if let Some(after) = after {
+        // `after` is exclusive; timestamps have microsecond precision.
+        query.limit_since(after + Duration::microseconds(1));
+    }
+
+    // Messages are also matched by meaning if the query can be embedded; if
+    // not, the search goes on with the other signals.
+    if let Some(embeddings) = &state.embeddings {
+        if query.has_text() && payload.mode == SearchMode::Messages && ranking.semantic > 0.0 {
+            match embeddings.semantic_query(&query.fuzzy_text()).await {
+                Ok(semantic) => query.semantic = Some(semantic),
+                Err(err) => tracing::warn!("Could not embed the search query: {}", err),
+            }
+        }
+    }
+
+    let viewer = auth_user.as_ref().map(|user| user.user_id.as_str());
+    let offset = payload.offset.unwrap_or(0).clamp(0, MAX_SEARCH_WINDOW);
+    let per_page = payload
+        .per_page
+        .unwrap_or(DEFAULT_SEARCH_PER_PAGE)
+        .clamp(1, MAX_SEARCH_PER_PAGE)
+        .min(MAX_SEARCH_WINDOW - offset);
+
+    // One extra result tells whether there is a next page.
+    let options = SearchOptions {
+        mode: payload.mode,
+        sort: payload.sort,
+        ranking,
+        explain: payload.explain,
+        offset,
+        limit: per_page + 1,
+        highlighter: &state.highlighter,
+    };
+    let mut messages = if per_page > 0 {
+        state.tummy.search_messages(viewer, &query, &options).await?
+    } else {
+        Vec::new()
+    };
+    let has_more = messages.len() as i64 > per_page && offset + per_page < MAX_SEARCH_WINDOW;
+    messages.truncate(per_page as usize);
+
+    let total_hits = state
+        .tummy
+        .count_search_matches(viewer, &query, payload.mode, MAX_SEARCH_WINDOW + 1)
+        .await?;
+    let facets = state
+        .tummy
+        .search_facets(viewer, &query, payload.mode, SEARCH_FACET_LIMIT)
+        .await?;
+    let suggestion = if total_hits < SUGGESTION_MAX_HITS {
+        state
+            .tummy
+            .suggest_queries(&payload.query, &query, 1)
+            .await?
+            .pop()
+    } else {
+        None
+    };
+
+    // Further pages are the same search, so they are not counted again.
+    if offset == 0 {
+        state.search_analytics.record(
+            auth_user.as_ref(),
+            &payload.query,
+            payload.mode,
+            total_hits.min(MAX_SEARCH_WINDOW),
+            started.elapsed(),
+        );
+    }
+
     Ok((
         StatusCode::OK,
         Json(
             models::SearchResultsResponse {
                 messages,
                 query: payload.query,
+                offset,
+                next_offset: has_more.then_some(offset + per_page),
+                has_more,
+                total_hits: total_hits.min(MAX_SEARCH_WINDOW),
+                total_hits_exact: total_hits <= MAX_SEARCH_WINDOW,
+                facets,
+                ranking: payload.explain.then_some(ranking),
+                suggestion,
             }
         ).into_response()
     ))
 }
 
+/// Finds other messages and threads about the same topic as a message ("more
+/// like this"), ranked by trigram similarity and shared words. At most one
+/// message per thread is returned, and none from the message's own thread.
+///
+/// # Parameters
+/// - `state`: Shared application state.
+/// - `auth_user`: The authenticated user, if login is enabled.
+/// - `ip`: The client's IP address, for the audit log.
+/// - `channel_id`, `ts`: The channel and timestamp of the message, as path parameters.
+/// - `params`: Query parameters with optional channel and time filters.
+///
+/// # Returns
+/// On success, returns a JSON response with the related messages in the shape of
+/// search results, most related first, with HTTP 200 OK.
+/// If a timestamp cannot be parsed, returns HTTP 400 Bad Request.
+/// If the message does not exist or is not visible, returns HTTP 404 Not Found.
+/// On failure, returns an application error.
+pub async fn related_messages(
+    State(state): State<RouterState>,
+    auth_user: Option<AuthUser>,
+    ClientIp(ip): ClientIp,
+    Path((channel_id, ts)): Path<(String, String)>,
+    Query(params): Query<RelatedQuery>,
+) -> Result<(StatusCode, Response), AppError> {
+    state
+        .audit
+        .record(
+            auth_user.as_ref(),
+            ip,
+            AuditAction::Related,
+            json!({
+                "channel_id": channel_id,
+                "ts": ts,
+                "filter_channel_id": params.channel_id,
+                "before": params.before,
+                "after": params.after,
+            }),
+        )
+        .await?;
+
+    let parse = |ts: &str| NaiveDateTime::parse_from_str(ts, "%Y-%m-%dT%H:%M:%S%.f");
+    let (Ok(message_ts), Ok(before), Ok(after)) = (
+        parse(&ts),
+        params.before.as_deref().map(parse).transpose(),
+        params.after.as_deref().map(parse).transpose(),
+    ) else {
+        return Ok((StatusCode::BAD_REQUEST, "Invalid timestamp.".into_response()));
+    };
+
+    let mut filters = search::ParsedQuery {
+        channel_id: params.channel_id,
+        ..Default::default()
+    };
+    if let Some(before) = before {
+        filters.limit_until(before);
+    }
+    if let Some(after) = after {
+        // `after` is exclusive; timestamps have microsecond precision.
+        filters.limit_since(after + Duration::microseconds(1));
+    }
+    let limit = params.limit.unwrap_or(DEFAULT_RELATED).clamp(1, MAX_RELATED);
+
+    let Some(messages) = state
+        .tummy
+        .related_messages(
+            auth_user.as_ref().map(|user| user.user_id.as_str()),
+            &channel_id,
+            message_ts,
+            &filters,
+            &state.ranking,
+            limit,
+            &state.highlighter,
+        )
+        .await?
+    else {
+        return Ok((StatusCode::NOT_FOUND, "No such message.".into_response()));
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(models::RelatedMessagesResponse {
+            channel_id,
+            ts,
+            messages,
+        })
+        .into_response(),
+    ))
+}
+
+/// Suggests corrections of the misspelled words of a search query, based on
+/// the words in public channels.
+///
+/// # Parameters
+/// - `state`: Shared application state.
+/// - `params`: Query parameters containing the search query.
+///
+/// # Returns
+/// On success, returns a JSON response with up to `MAX_SPELLING_SUGGESTIONS`
+/// corrected queries, none if every word is known, with HTTP 200 OK.
+/// If the query cannot be parsed, returns the parse error as JSON with HTTP 400 Bad Request.
+/// On failure, returns an application error.
+pub async fn spelling_suggestions(
+    State(state): State<RouterState>,
+    Query(params): Query<SpellingQuery>,
+) -> Result<(StatusCode, Response), AppError> {
+    let query = match search::parse(&params.query) {
+        Ok(query) => query,
+        Err(error) => {
+            return Ok((
+                StatusCode::BAD_REQUEST,
+                Json(models::SearchErrorResponse { error }).into_response(),
+            ));
+        }
+    };
+
+    let suggestions = state
+        .tummy
+        .suggest_queries(&params.query, &query, MAX_SPELLING_SUGGESTIONS)
+        .await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(models::SpellingSuggestionsResponse {
+            query: params.query,
+            suggestions,
+        })
+        .into_response(),
+    ))
+}
+
+/// Splits what is being typed into what kinds of suggestions it asks for and
+/// the text to complete.
+fn suggestion_kinds(text: &str) -> (&'static [SuggestionKind], &str) {
+    let text = text.trim().trim_start_matches('-');
+    let lowercase = text.to_lowercase();
+    if let Some(rest) = lowercase.strip_prefix("from:") {
+        let offset = text.len() - rest.len();
+        (&[SuggestionKind::User], text[offset..].trim_start_matches('@'))
+    } else if let Some(rest) = lowercase.strip_prefix("in:") {
+        let offset = text.len() - rest.len();
+        (&[SuggestionKind::Channel], text[offset..].trim_start_matches('#'))
+    } else if let Some(rest) = text.strip_prefix('@') {
+        (&[SuggestionKind::User], rest)
+    } else if let Some(rest) = text.strip_prefix('#') {
+        (&[SuggestionKind::Channel], rest)
+    } else {
+        (&[SuggestionKind::User, SuggestionKind::Channel, SuggestionKind::Term], text)
+    }
+}
+
+/// Autocompletes users, channels and search terms for the search bar.
+/// Suggestions are ranked by how well they match, prefix matches first. The
+/// lookup has `SUGGEST_TIMEOUT_MS` to finish, otherwise nothing is suggested.
+///
+/// # Parameters
+/// - `state`: Shared application state.
+/// - `auth_user`: The authenticated user, if login is enabled.
+/// - `params`: Query parameters containing the text typed so far.
+///
+/// # Returns
+/// On success, returns a JSON response with the suggestions, best first, with HTTP 200 OK.
+/// On failure, returns an application error.
+pub async fn suggest(
+    State(state): State<RouterState>,
+    auth_user: Option<AuthUser>,
+    Query(params): Query<SuggestQuery>,
+) -> Result<(StatusCode, Response), AppError> {
+    let (kinds, text) = suggestion_kinds(&params.q);
+    let limit = params.limit.unwrap_or(DEFAULT_SUGGESTIONS).clamp(1, MAX_SUGGESTIONS);
+
+    let suggestions = if text.is_empty() {
+        Vec::new()
+    } else {
+        match state
+            .tummy
+            .suggest(
+                auth_user.as_ref().map(|user| user.user_id.as_str()),
+                text,
+                kinds,
+                limit,
+                StdDuration::from_millis(state.env_vars.suggest_timeout_ms),
+            )
+            .await
+        {
+            Ok(suggestions) => suggestions.into_iter().map(Suggestion::from).collect(),
+            Err(sqlx::Error::Database(err)) if err.code().as_deref() == Some(QUERY_CANCELED) => {
+                tracing::warn!("Autocompleting {:?} took too long.", text);
+                Vec::new()
+            }
+            Err(err) => return Err(err.into()),
+        }
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(models::SuggestionsResponse {
+            query: params.q,
+            suggestions,
+        })
+        .into_response(),
+    ))
+}
+
 /// Fetches messages for a specific channel, with pagination and optional date filter.
 ///
 /// # Parameters
 /// - `state`: Shared application state.
+/// - `auth_user`: The authenticated user, if login is enabled.
+/// - `ip`: The client's IP address, for the audit log.
 /// - `channel_id`: The channel ID as a path parameter.
 /// - `pagination`: Query parameters for pagination.
-/// - `date_query`: Query parameters for date filtering.
 ///
 /// # Returns
 /// On success, returns a JSON response with messages, last message timestamp, and channel ID, with HTTP 200 OK.
 /// On failure, returns an application error.
 pub async fn get_messages(
     State(state): State<RouterState>,
+    auth_user: Option<AuthUser>,
+    ClientIp(ip): ClientIp,
     Path(channel_id): Path<String>,
     pagination: Query<Pagination>,
 ) -> Result<(StatusCode, Response), AppError> {
+    state
+        .audit
+        .record(
+            auth_user.as_ref(),
+            ip,
+            AuditAction::GetMessages,
+            json!({
+                "channel_id": channel_id,
+                "before_msg_timestamp": pagination.before_msg_timestamp,
+                "per_page": pagination.per_page,
+            }),
+        )
+        .await?;
+
     let messages = state
         .tummy
         .fetch_msg_page(
@@ -112,6 +569,7 @@ pub async fn get_messages(
                 .as_ref()
                 .map(|ts| sqlx::types::chrono::NaiveDateTime::from_pg_ts(ts)),
             &pagination.per_page,
+            auth_user.as_ref().map(|user| user.user_id.as_str()),
         )
         .await?;
 
@@ -135,6 +593,8 @@ pub async fn get_messages(
 ///
 /// # Parameters
 /// - `state`: Shared application state.
+/// - `auth_user`: The authenticated user, if login is enabled.
+/// - `ip`: The client's IP address, for the audit log.
 /// - `message_data`: Query parameters containing the parent message's channel ID, timestamp, and user ID.
 ///
 /// # Returns
@@ -142,14 +602,31 @@ pub async fn get_messages(
 /// On failure, returns an application error.
 pub async fn get_replies(
     State(state): State<RouterState>,
+    auth_user: Option<AuthUser>,
+    ClientIp(ip): ClientIp,
     message_data: Query<ReplyRequest>,
 ) -> Result<(StatusCode, Response), AppError> {
+    state
+        .audit
+        .record(
+            auth_user.as_ref(),
+            ip,
+            AuditAction::GetReplies,
+            json!({
+                "channel_id": message_data.channel_id,
+                "ts": message_data.ts,
+                "user_id": message_data.user_id,
+            }),
+        )
+        .await?;
+
     let messages = state
         .tummy
         .fetch_replies(
             &message_data.ts,
             &message_data.channel_id,
             &message_data.user_id,
+            auth_user.as_ref().map(|user| user.user_id.as_str()),
         )
         .await?;
     Ok((
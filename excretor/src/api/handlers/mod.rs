@@ -1,9 +1,15 @@
+pub mod admin;
 pub mod channels;
 pub mod messages;
 pub mod auth;
 pub mod misc;
+pub mod saved_searches;
+pub mod tokens;
 
+pub use admin::*;
 pub use misc::*;
 pub use channels::*;
 pub use messages::*;
 pub use auth::*;
+pub use saved_searches::*;
+pub use tokens::*;
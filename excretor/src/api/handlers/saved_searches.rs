@@ -0,0 +1,295 @@
+//! Saved search handlers.
+//! Provides endpoints for managing the caller's saved searches and reading the
+//! feed of new messages matching the ones that are alerts.
+
+use crate::api::errors::AppError;
+use crate::api::extractors::ClientIp;
+use crate::api::models::{SavedSearchesResponse, SearchAlertsResponse, SearchErrorResponse};
+use crate::api::routes::RouterState;
+use crate::audit::AuditAction;
+use crate::auth::AuthUser;
+use crate::search;
+use crate::types::SavedSearch;
+use axum::extract::{Path, Query, State};
+use axum::response::IntoResponse;
+use axum::{http::StatusCode, response::Response, Json};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::json;
+
+/// Most saved searches a user can have.
+const MAX_SAVED_SEARCHES: i64 = 100;
+
+/// Number of alert matches returned unless requested otherwise.
+const DEFAULT_SEARCH_ALERTS: i64 = 50;
+
+/// Most alert matches that can be requested.
+const MAX_SEARCH_ALERTS: i64 = 200;
+
+/// Request payload for saving a search or changing a saved search.
+#[derive(Deserialize)]
+pub struct SavedSearchRequest {
+    /// A name to recognise the search by.
+    name: String,
+    /// Search query in the search query language.
+    query: String,
+    /// Whether new messages matching the query are added to the alert feed.
+    #[serde(default)]
+    alert: bool,
+    /// Where new matches are also POSTed, for alerts.
+    webhook_url: Option<String>,
+}
+
+impl SavedSearchRequest {
+    /// The webhook URL, if one is given.
+    fn webhook_url(&self) -> Option<&str> {
+        self.webhook_url
+            .as_deref()
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+    }
+}
+
+/// Query parameters for reading the alert feed.
+#[derive(Deserialize)]
+pub struct SearchAlertsQuery {
+    /// Only the matches of this saved search.
+    saved_search_id: Option<i64>,
+    /// Only matches older than the one with this ID, for paging back.
+    before: Option<i64>,
+    /// Only matches newer than the one with this ID, for polling.
+    after: Option<i64>,
+    /// Number of matches.
+    limit: Option<i64>,
+}
+
+/// Checks a saved search before it is stored.
+///
+/// # Returns
+/// The response rejecting the saved search, if it is not valid.
+fn validate(state: &RouterState, payload: &SavedSearchRequest) -> Option<(StatusCode, Response)> {
+    if payload.name.trim().is_empty() || payload.query.trim().is_empty() {
+        return Some((
+            StatusCode::BAD_REQUEST,
+            "A saved search needs a name and a query.".into_response(),
+        ));
+    }
+    if let Err(error) = search::parse(&payload.query) {
+        return Some((
+            StatusCode::BAD_REQUEST,
+            Json(SearchErrorResponse { error }).into_response(),
+        ));
+    }
+    if let Some(webhook_url) = payload.webhook_url() {
+        if !payload.alert {
+            return Some((
+                StatusCode::BAD_REQUEST,
+                "Only alerts can have a webhook.".into_response(),
+            ));
+        }
+        if let Err(reason) = state.search_alerts.check_webhook_url(webhook_url) {
+            return Some((StatusCode::BAD_REQUEST, reason.into_response()));
+        }
+    }
+    None
+}
+
+/// Lists the authenticated user's saved searches by name.
+///
+/// # Parameters
+/// - `state`: Shared application state.
+/// - `auth_user`: The authenticated user.
+///
+/// # Returns
+/// On success, returns a JSON response with the saved searches and HTTP 200 OK.
+/// On failure, returns an application error.
+pub async fn list_saved_searches(
+    State(state): State<RouterState>,
+    auth_user: AuthUser,
+) -> Result<(StatusCode, Response), AppError> {
+    let saved_searches = state.tummy.get_saved_searches(&auth_user.user_id).await?;
+    Ok((
+        StatusCode::OK,
+        Json(SavedSearchesResponse {
+            saved_searches: saved_searches.into_iter().map(Into::into).collect(),
+        })
+        .into_response(),
+    ))
+}
+
+/// Saves a search for the authenticated user.
+///
+/// # Parameters
+/// - `state`: Shared application state.
+/// - `auth_user`: The authenticated user.
+/// - `payload`: JSON body with the name, query, whether it is an alert and an optional webhook URL.
+///
+/// # Returns
+/// On success, returns the saved search with HTTP 201 Created.
+/// If the query cannot be parsed, returns the parse error as JSON with HTTP 400 Bad Request.
+/// If the search is otherwise invalid, the webhook is not allowed or the user
+/// has too many saved searches, returns HTTP 400 Bad Request.
+/// On failure, returns an application error.
+pub async fn create_saved_search(
+    State(state): State<RouterState>,
+    auth_user: AuthUser,
+    Json(payload): Json<SavedSearchRequest>,
+) -> Result<(StatusCode, Response), AppError> {
+    if let Some(rejection) = validate(&state, &payload) {
+        return Ok(rejection);
+    }
+    if state.tummy.count_saved_searches(&auth_user.user_id).await? >= MAX_SAVED_SEARCHES {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            format!("A user can have at most {} saved searches.", MAX_SAVED_SEARCHES).into_response(),
+        ));
+    }
+
+    let saved_search = state
+        .tummy
+        .insert_saved_search(
+            &auth_user.user_id,
+            payload.name.trim(),
+            payload.query.trim(),
+            payload.alert,
+            payload.webhook_url(),
+            Utc::now().naive_utc(),
+        )
+        .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(SavedSearch::from(saved_search)).into_response(),
+    ))
+}
+
+/// Changes one of the authenticated user's saved searches. Matches it already
+/// found stay in the alert feed.
+///
+/// # Parameters
+/// - `state`: Shared application state.
+/// - `auth_user`: The authenticated user.
+/// - `saved_search_id`: The saved search ID as a path parameter.
+/// - `payload`: JSON body with the new name, query, whether it is an alert and an optional webhook URL.
+///
+/// # Returns
+/// On success, returns the changed saved search with HTTP 200 OK.
+/// If the query cannot be parsed, returns the parse error as JSON with HTTP 400 Bad Request.
+/// If the search is otherwise invalid or the webhook is not allowed, returns HTTP 400 Bad Request.
+/// If the user has no such saved search, returns HTTP 404 Not Found.
+/// On failure, returns an application error.
+pub async fn update_saved_search(
+    State(state): State<RouterState>,
+    auth_user: AuthUser,
+    Path(saved_search_id): Path<i64>,
+    Json(payload): Json<SavedSearchRequest>,
+) -> Result<(StatusCode, Response), AppError> {
+    if let Some(rejection) = validate(&state, &payload) {
+        return Ok(rejection);
+    }
+
+    let saved_search = state
+        .tummy
+        .update_saved_search(
+            saved_search_id,
+            &auth_user.user_id,
+            payload.name.trim(),
+            payload.query.trim(),
+            payload.alert,
+            payload.webhook_url(),
+            Utc::now().naive_utc(),
+        )
+        .await?;
+
+    match saved_search {
+        Some(saved_search) => Ok((
+            StatusCode::OK,
+            Json(SavedSearch::from(saved_search)).into_response(),
+        )),
+        None => Ok((StatusCode::NOT_FOUND, "No such saved search.".into_response())),
+    }
+}
+
+/// Deletes one of the authenticated user's saved searches and its alert matches.
+///
+/// # Parameters
+/// - `state`: Shared application state.
+/// - `auth_user`: The authenticated user.
+/// - `saved_search_id`: The saved search ID as a path parameter.
+///
+/// # Returns
+/// On success, returns HTTP 204 No Content.
+/// If the user has no such saved search, returns HTTP 404 Not Found.
+/// On failure, returns an application error.
+pub async fn delete_saved_search(
+    State(state): State<RouterState>,
+    auth_user: AuthUser,
+    Path(saved_search_id): Path<i64>,
+) -> Result<(StatusCode, Response), AppError> {
+    if state
+        .tummy
+        .delete_saved_search(saved_search_id, &auth_user.user_id)
+        .await?
+    {
+        Ok((StatusCode::NO_CONTENT, ().into_response()))
+    } else {
+        Ok((StatusCode::NOT_FOUND, "No such saved search.".into_response()))
+    }
+}
+
+/// Lists new messages matching the authenticated user's search alerts, newest
+/// first. Clients poll for new matches with `after` set to the highest ID they
+/// have seen, and page back with `before`.
+///
+/// # Parameters
+/// - `state`: Shared application state.
+/// - `auth_user`: The authenticated user.
+/// - `ip`: The client's IP address, for the audit log.
+/// - `params`: Query parameters with an optional saved search, ID range and limit.
+///
+/// # Returns
+/// On success, returns a JSON response with the matches and HTTP 200 OK.
+/// On failure, returns an application error.
+pub async fn get_search_alerts(
+    State(state): State<RouterState>,
+    auth_user: AuthUser,
+    ClientIp(ip): ClientIp,
+    Query(params): Query<SearchAlertsQuery>,
+) -> Result<(StatusCode, Response), AppError> {
+    state
+        .audit
+        .record(
+            Some(&auth_user),
+            ip,
+            AuditAction::SearchAlerts,
+            json!({
+                "saved_search_id": params.saved_search_id,
+                "before": params.before,
+                "after": params.after,
+                "limit": params.limit,
+            }),
+        )
+        .await?;
+
+    let matches = state
+        .tummy
+        .get_search_alert_feed(
+            &auth_user.user_id,
+            params.saved_search_id,
+            params.before,
+            params.after,
+            params
+                .limit
+                .unwrap_or(DEFAULT_SEARCH_ALERTS)
+                .clamp(1, MAX_SEARCH_ALERTS),
+        )
+        .await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(SearchAlertsResponse {
+            matches: matches.into_iter().map(Into::into).collect(),
+        })
+        .into_response(),
+    ))
+}
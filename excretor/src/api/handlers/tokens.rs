@@ -0,0 +1,167 @@
+//! Personal API token handlers.
+//! Provides endpoints for creating, listing and revoking the caller's API tokens.
+
+use crate::api::errors::AppError;
+use crate::api::models::{ApiTokensResponse, CreatedApiTokenResponse};
+use crate::api::routes::RouterState;
+use crate::auth::tokens::{self, Scope};
+use crate::auth::AuthUser;
+use axum::extract::{Path, State};
+use axum::response::IntoResponse;
+use axum::{http::StatusCode, response::Response, Json};
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+
+/// Longest lifetime of an expiring token, about ten years.
+const MAX_EXPIRES_IN_DAYS: i64 = 3650;
+
+/// Request payload for creating an API token.
+#[derive(Deserialize)]
+pub struct CreateTokenRequest {
+    /// A name to recognise the token by.
+    name: String,
+    /// The scopes to grant to the token.
+    scopes: Vec<Scope>,
+    /// Number of days until the token expires, from 1 to `MAX_EXPIRES_IN_DAYS`.
+    /// Never expires if omitted.
+    expires_in_days: Option<i64>,
+}
+
+/// Managing tokens needs a browser session or a token with the `admin` scope,
+/// so that a leaked read-only token cannot mint more tokens.
+fn can_manage_tokens(auth_user: &AuthUser) -> bool {
+    auth_user.token_id.is_none() || auth_user.has_scope(Scope::Admin)
+}
+
+/// Creates a new API token for the authenticated user.
+///
+/// # Parameters
+/// - `state`: Shared application state.
+/// - `auth_user`: The authenticated user.
+/// - `payload`: JSON body with the token name, scopes and optional expiry.
+///
+/// # Returns
+/// On success, returns the token (shown only this once) and its details with HTTP 201 Created.
+/// If the name, scopes or expiry are invalid, returns HTTP 400 Bad Request.
+/// If the caller may not grant the requested scopes, returns HTTP 403 Forbidden.
+/// On failure, returns an application error.
+pub async fn create_token(
+    State(state): State<RouterState>,
+    auth_user: AuthUser,
+    Json(payload): Json<CreateTokenRequest>,
+) -> Result<(StatusCode, Response), AppError> {
+    if !can_manage_tokens(&auth_user)
+        || payload.scopes.iter().any(|scope| !auth_user.has_scope(*scope))
+    {
+        return Ok((
+            StatusCode::FORBIDDEN,
+            "Cannot grant the requested scopes.".into_response(),
+        ));
+    }
+
+    let name = payload.name.trim();
+    if name.is_empty() || payload.scopes.is_empty() {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            "A token needs a name and at least one scope.".into_response(),
+        ));
+    }
+
+    if payload
+        .expires_in_days
+        .is_some_and(|days| !(1..=MAX_EXPIRES_IN_DAYS).contains(&days))
+    {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            format!("expires_in_days must be between 1 and {}.", MAX_EXPIRES_IN_DAYS).into_response(),
+        ));
+    }
+
+    let now = Utc::now().naive_utc();
+    let new_token = tokens::generate();
+    let mut scopes: Vec<String> = payload.scopes.iter().map(|scope| scope.to_string()).collect();
+    scopes.sort();
+    scopes.dedup();
+
+    let token = state
+        .tummy
+        .insert_api_token(
+            &auth_user.user_id,
+            auth_user.team_id.as_deref(),
+            name,
+            &new_token.hash,
+            &new_token.display_prefix,
+            &scopes,
+            now,
+            payload.expires_in_days.map(|days| now + Duration::days(days)),
+        )
+        .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedApiTokenResponse {
+            token: new_token.token,
+            details: token.into(),
+        })
+        .into_response(),
+    ))
+}
+
+/// Lists the authenticated user's API tokens, including revoked and expired ones.
+///
+/// # Parameters
+/// - `state`: Shared application state.
+/// - `auth_user`: The authenticated user.
+///
+/// # Returns
+/// On success, returns a JSON response with the tokens and HTTP 200 OK.
+/// On failure, returns an application error.
+pub async fn list_tokens(
+    State(state): State<RouterState>,
+    auth_user: AuthUser,
+) -> Result<(StatusCode, Response), AppError> {
+    if !can_manage_tokens(&auth_user) {
+        return Ok((StatusCode::FORBIDDEN, "Cannot manage tokens.".into_response()));
+    }
+
+    let tokens = state.tummy.get_api_tokens(&auth_user.user_id).await?;
+    Ok((
+        StatusCode::OK,
+        Json(ApiTokensResponse {
+            tokens: tokens.into_iter().map(Into::into).collect(),
+        })
+        .into_response(),
+    ))
+}
+
+/// Revokes one of the authenticated user's API tokens.
+///
+/// # Parameters
+/// - `state`: Shared application state.
+/// - `auth_user`: The authenticated user.
+/// - `token_id`: The token ID as a path parameter.
+///
+/// # Returns
+/// On success, returns HTTP 204 No Content.
+/// If the user has no such active token, returns HTTP 404 Not Found.
+/// On failure, returns an application error.
+pub async fn revoke_token(
+    State(state): State<RouterState>,
+    auth_user: AuthUser,
+    Path(token_id): Path<i64>,
+) -> Result<(StatusCode, Response), AppError> {
+    if !can_manage_tokens(&auth_user) {
+        return Ok((StatusCode::FORBIDDEN, "Cannot manage tokens.".into_response()));
+    }
+
+    let revoked = state
+        .tummy
+        .revoke_api_token(token_id, &auth_user.user_id, Utc::now().naive_utc())
+        .await?;
+
+    if revoked {
+        Ok((StatusCode::NO_CONTENT, ().into_response()))
+    } else {
+        Ok((StatusCode::NOT_FOUND, "No such token.".into_response()))
+    }
+}
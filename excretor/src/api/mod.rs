@@ -2,3 +2,5 @@ mod handlers;
 pub mod routes;
 mod models;
 mod errors;
+mod extractors;
+mod rate_limit;
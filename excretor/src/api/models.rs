@@ -1,5 +1,10 @@
-use crate::types::{Channel, Message, SearchResult, User};
+use crate::types::{
+    ApiToken, AuditEntry, Channel, Message, SavedSearch, SearchAlertMatch, SearchFacets, SearchQueryStats,
+    SearchResult, Suggestion, User, UserRole,
+};
+use crate::search::{QueryError, Ranking};
 use serde::{Serialize};
+use chrono::NaiveDateTime;
 
 #[derive(Serialize)]
 pub struct ChannelsResponse {
@@ -23,6 +28,58 @@ pub struct ChannelDetailsResponse {
 pub struct SearchResultsResponse {
     pub messages: Vec<SearchResult>,
     pub query: String,
+    pub offset: i64,
+    pub next_offset: Option<i64>,
+    pub has_more: bool,
+    /// Number of matching messages, counted up to `MAX_SEARCH_WINDOW`.
+    pub total_hits: i64,
+    /// Whether `total_hits` is exact or only a lower bound.
+    pub total_hits_exact: bool,
+    /// Hit counts over all matches, not just this page.
+    pub facets: SearchFacets,
+    /// The ranking parameters used, when explaining results.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ranking: Option<Ranking>,
+    /// The query with misspelled words corrected, when it has few results.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggestion: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct RelatedMessagesResponse {
+    pub channel_id: String,
+    pub ts: String,
+    /// Related messages, one per thread, most related first.
+    pub messages: Vec<SearchResult>,
+}
+
+#[derive(Serialize)]
+pub struct SavedSearchesResponse {
+    pub saved_searches: Vec<SavedSearch>,
+}
+
+#[derive(Serialize)]
+pub struct SearchAlertsResponse {
+    /// New messages matching the user's alerts, newest first.
+    pub matches: Vec<SearchAlertMatch>,
+}
+
+#[derive(Serialize)]
+pub struct SpellingSuggestionsResponse {
+    pub query: String,
+    /// Corrected versions of the query, best first.
+    pub suggestions: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct SuggestionsResponse {
+    pub query: String,
+    pub suggestions: Vec<Suggestion>,
+}
+
+#[derive(Serialize)]
+pub struct SearchErrorResponse {
+    pub error: QueryError,
 }
 
 #[derive(Serialize)]
@@ -39,3 +96,37 @@ pub struct ThreadResponse {
     pub channel_id: String,
     pub parent_user_id: String,
 }
+
+#[derive(Serialize)]
+pub struct ApiTokensResponse {
+    pub tokens: Vec<ApiToken>,
+}
+
+#[derive(Serialize)]
+pub struct CreatedApiTokenResponse {
+    /// The plaintext token. It is only ever returned here.
+    pub token: String,
+    #[serde(flatten)]
+    pub details: ApiToken,
+}
+
+#[derive(Serialize)]
+pub struct UserRolesResponse {
+    pub roles: Vec<UserRole>,
+}
+
+#[derive(Serialize)]
+pub struct AuditLogResponse {
+    pub entries: Vec<AuditEntry>,
+    /// Pass as `before_id` to fetch the next page, if there is one.
+    pub next_before_id: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct SearchAnalyticsResponse {
+    /// The start of the period, inclusive.
+    pub since: NaiveDateTime,
+    /// The end of the period, exclusive.
+    pub until: NaiveDateTime,
+    pub queries: Vec<SearchQueryStats>,
+}
@@ -0,0 +1,224 @@
+//! Per-user and per-IP rate limiting.
+//! Each budget is a token bucket per client: it holds up to `burst` requests and
+//! refills at `per_minute` requests a minute. Authenticated requests are keyed
+//! by user, anonymous ones by client IP. Throttled requests get
+//! `429 Too Many Requests` with a `Retry-After` header.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+
+use crate::api::routes::RouterState;
+use crate::auth::AuthUser;
+use crate::env::EnvVars;
+
+/// Number of tracked clients above which idle buckets are dropped.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Which budget a route draws from.
+#[derive(Clone, Copy, Debug)]
+pub enum Budget {
+    /// Full-text searches.
+    Search,
+    /// Opening channels and threads, and paging through messages.
+    Pagination,
+    /// Autocompleting the search bar.
+    Suggest,
+    /// Starting a login and the login provider's callback.
+    Auth,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// A token bucket per client.
+#[derive(Clone)]
+pub struct RateLimiter {
+    burst: f64,
+    /// Tokens added per second.
+    refill_rate: f64,
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+impl RateLimiter {
+    /// A limiter allowing bursts of `burst` requests and `per_minute` requests a
+    /// minute on average. A `per_minute` of 0 disables the limit.
+    pub fn new(per_minute: u32, burst: u32) -> Self {
+        Self {
+            burst: burst.max(1) as f64,
+            refill_rate: per_minute as f64 / 60.0,
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Takes a token from the client's bucket.
+    ///
+    /// # Returns
+    /// `Err` with the time until a token is available if the bucket is empty.
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        if self.refill_rate <= 0.0 {
+            return Ok(());
+        }
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > PRUNE_THRESHOLD {
+            // Buckets that have refilled completely are the same as new ones.
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated_at).as_secs_f64() * self.refill_rate
+                    < self.burst
+            });
+        }
+
+        let bucket = buckets.entry(key.to_owned()).or_insert(Bucket {
+            tokens: self.burst,
+            updated_at: now,
+        });
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_rate).min(self.burst);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.refill_rate))
+        }
+    }
+}
+
+/// The rate limiters of all budgets.
+#[derive(Clone)]
+pub struct RateLimits {
+    search: RateLimiter,
+    pagination: RateLimiter,
+    suggest: RateLimiter,
+    auth: RateLimiter,
+}
+
+impl RateLimits {
+    pub fn from_env(env_vars: &EnvVars) -> Self {
+        Self {
+            search: RateLimiter::new(
+                env_vars.rate_limit_search_per_minute,
+                env_vars.rate_limit_search_burst,
+            ),
+            pagination: RateLimiter::new(
+                env_vars.rate_limit_pagination_per_minute,
+                env_vars.rate_limit_pagination_burst,
+            ),
+            suggest: RateLimiter::new(
+                env_vars.rate_limit_suggest_per_minute,
+                env_vars.rate_limit_suggest_burst,
+            ),
+            auth: RateLimiter::new(
+                env_vars.rate_limit_auth_per_minute,
+                env_vars.rate_limit_auth_burst,
+            ),
+        }
+    }
+
+    fn limiter(&self, budget: Budget) -> &RateLimiter {
+        match budget {
+            Budget::Search => &self.search,
+            Budget::Pagination => &self.pagination,
+            Budget::Suggest => &self.suggest,
+            Budget::Auth => &self.auth,
+        }
+    }
+}
+
+/// A middleware that charges the request to the client's `budget`.
+/// Must run after the authentication middleware to key requests by user.
+pub(super) async fn rate_limit(
+    budget: Budget,
+    state: RouterState,
+    ip: Option<IpAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let key = match (request.extensions().get::<AuthUser>(), ip) {
+        (Some(auth_user), _) => format!("user:{}", auth_user.user_id),
+        (None, Some(ip)) => format!("ip:{}", ip),
+        (None, None) => "unknown".to_owned(),
+    };
+
+    match state.rate_limits.limiter(budget).check(&key) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            let retry_after = retry_after.as_secs() + 1;
+            tracing::info!("Rate limited {} on the {:?} budget.", key, budget);
+            Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .header(header::RETRY_AFTER, retry_after.to_string())
+                .body(Body::from(format!(
+                    "Too many requests. Please try again in {} seconds.",
+                    retry_after
+                )))
+                .unwrap()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_bursts_then_throttles() {
+        let limiter = RateLimiter::new(60, 3);
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check_at("user:U1", now).is_ok());
+        }
+        let retry_after = limiter.check_at("user:U1", now).unwrap_err();
+        assert_eq!(retry_after, Duration::from_secs(1));
+        // Other clients have their own bucket.
+        assert!(limiter.check_at("user:U2", now).is_ok());
+    }
+
+    #[test]
+    fn refills_over_time() {
+        let limiter = RateLimiter::new(30, 2);
+        let now = Instant::now();
+        limiter.check_at("ip:203.0.113.7", now).unwrap();
+        limiter.check_at("ip:203.0.113.7", now).unwrap();
+        assert!(limiter.check_at("ip:203.0.113.7", now).is_err());
+
+        // One token every two seconds.
+        let later = now + Duration::from_secs(1);
+        assert_eq!(limiter.check_at("ip:203.0.113.7", later), Err(Duration::from_secs(1)));
+        let later = now + Duration::from_secs(2);
+        assert!(limiter.check_at("ip:203.0.113.7", later).is_ok());
+        assert!(limiter.check_at("ip:203.0.113.7", later).is_err());
+
+        // A long pause refills the bucket up to the burst only.
+        let much_later = later + Duration::from_secs(3600);
+        for _ in 0..2 {
+            assert!(limiter.check_at("ip:203.0.113.7", much_later).is_ok());
+        }
+        assert!(limiter.check_at("ip:203.0.113.7", much_later).is_err());
+    }
+
+    #[test]
+    fn zero_per_minute_disables_the_limit() {
+        let limiter = RateLimiter::new(0, 1);
+        let now = Instant::now();
+        for _ in 0..100 {
+            assert!(limiter.check_at("user:U1", now).is_ok());
+        }
+    }
+}
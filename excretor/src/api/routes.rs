@@ -1,98 +1,359 @@
+use crate::audit::AuditLog;
+use crate::auth::allowlist::LoginAllowlist;
+use crate::auth::sessions::{CachedSession, Sessions};
+use crate::auth::tokens::{self, Scope};
+use crate::auth::AuthUser;
+use crate::auth::keys::SessionKeys;
+use crate::auth::roles::Roles;
+use crate::auth::login_state::LoginStates;
+use crate::auth::providers::{self, AuthProvider};
+use crate::search::{Embeddings, Highlighter, Ranking, SearchAlerts, SearchAnalytics};
 use crate::{db::tummy::Tummy, env::EnvVars};
 use axum::{
     body::Body,
     extract::{Request, State},
-    http::StatusCode,
+    http::{header, StatusCode},
     middleware::{self, Next},
-    response::Response,
-    routing::{get, post},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
     Router,
 };
 use axum_extra::extract::cookie::CookieJar;
-use hmac::{Hmac, Mac};
-use jwt::VerifyWithKey;
-use reqwest::Client;
-use sha2::Sha256;
-use std::collections::BTreeMap;
+use axum_extra::extract::cookie::Cookie;
+use chrono::Utc;
+use std::sync::Arc;
 
 use crate::api::errors;
+use crate::api::extractors::ClientIp;
 use crate::api::handlers;
+use crate::api::rate_limit::{rate_limit, Budget, RateLimits};
 
 pub(super) const FORBIDDEN_MSG: &str = "Mortals are forbidden from accessing the site";
 
-/// Verifies a JWT token by checking its validity and then using the
-/// embedded access token to call Slack's `auth.test` API.
-async fn verify_token(token: &String, state: &RouterState) -> Result<bool, errors::AppError> {
-    // verify the jwt token and accessing slack auth test api
-    let key: Hmac<Sha256> =
-        Hmac::new_from_slice(state.env_vars.slack_signing_secret.as_bytes()).unwrap();
-    let claims: BTreeMap<String, String> = token.verify_with_key(&key)?;
-    let user_id = claims.get("user_id").unwrap();
-    let access_token = claims.get("access_token").unwrap();
-
-    let slack_auth_test_url = "https://slack.com/api/auth.test";
-    let req = Client::new()
-        .get(slack_auth_test_url)
-        .header("Authorization", format!("Bearer {}", access_token))
-        .build()?;
-    let response = Client::new().execute(req).await?;
-
-    if response.status() != StatusCode::OK {
-        return Ok(false);
+/// Verifies a session token by checking its signature and expiry and then
+/// consulting the server-side session. The Slack team of the session is checked
+/// against the allowlist every time. Only when the session is due for
+/// re-validation is the login provider's access token checked with the provider
+/// (e.g. Slack's `auth.test` API) and the user looked up in tummy and checked
+/// against the allowlist.
+///
+/// # Returns
+/// The logged in user, or `None` if the user has to log in again.
+async fn verify_token(token: &str, state: &RouterState) -> Result<Option<AuthUser>, errors::AppError> {
+    let Some(claims) = state.sessions.claims(token) else {
+        return Ok(None);
+    };
+
+    let (session, fresh) = match state.sessions.lookup(&claims).await? {
+        CachedSession::Fresh(session) => (session, true),
+        CachedSession::Stale(session) => (session, false),
+        CachedSession::Missing => return Ok(None),
+    };
+
+    if let Err(reason) = state.allowlist.check_team(session.team_id.as_deref()) {
+        tracing::info!("Ending the session of user `{}`: {}", claims.sub, reason);
+        state.sessions.revoke(&session.id).await?;
+        return Ok(None);
+    }
+    let auth_user = AuthUser::from_session(claims.sub, session.team_id.clone());
+    if fresh {
+        return Ok(Some(auth_user));
     }
 
-    let user = state.tummy.get_user_info(user_id).await?;
-    if user.id.is_empty() || user.is_bot || user.deleted {
-        return Ok(false);
+    let access_token = match state.sessions.access_token(&session) {
+        Ok(Some(access_token)) => access_token,
+        Ok(None) => {
+            state.sessions.revoke(&session.id).await?;
+            return Ok(None);
+        }
+        Err(err) => {
+            // E.g. the key it was encrypted with has been retired.
+            tracing::info!("Could not decrypt session access token: {}", err);
+            state.sessions.revoke(&session.id).await?;
+            return Ok(None);
+        }
+    };
+
+    match state.provider.revalidate(&access_token).await {
+        Ok(true) => {}
+        Ok(false) => {
+            state.sessions.revoke(&session.id).await?;
+            return Ok(None);
+        }
+        Err(err) => {
+            // The provider being slow or down should not log out users whose
+            // session was verified before.
+            tracing::warn!(
+                "Could not re-validate session with {}: {}",
+                state.provider.name(),
+                err
+            );
+            return Ok(Some(auth_user));
+        }
+    }
+
+    let user = state.tummy.get_user_info(&auth_user.user_id).await?;
+    if !providers::is_allowed(&user) {
+        state.sessions.revoke(&session.id).await?;
+        return Ok(None);
+    }
+    if let Err(reason) = state.allowlist.check_email(&user.email) {
+        tracing::info!("Ending the session of user `{}`: {}", user.id, reason);
+        state.sessions.revoke(&session.id).await?;
+        return Ok(None);
     }
 
-    Ok(true)
+    if !state.sessions.mark_validated(&session.id).await? {
+        return Ok(None);
+    }
+    Ok(Some(auth_user))
 }
 
-/// A middleware that checks for a valid "token" cookie and verifies it.
+/// Verifies a personal API token sent as `Authorization: Bearer <token>`.
+/// Like sessions being re-validated, tokens of users who may no longer log in
+/// are rejected, including those whose team or email domain the allowlist no
+/// longer allows.
+///
+/// # Returns
+/// The token's owner and scopes, or `None` if the token is not valid.
+async fn verify_api_token(
+    token: &str,
+    state: &RouterState,
+) -> Result<Option<AuthUser>, errors::AppError> {
+    let Some(api_token) = state
+        .tummy
+        .use_api_token(&tokens::hash(token), Utc::now().naive_utc())
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    let user = state.tummy.get_user_info(&api_token.user_id).await?;
+    if !providers::is_allowed(&user) {
+        return Ok(None);
+    }
+    if let Err(reason) = state
+        .allowlist
+        .check_team(api_token.team_id.as_deref())
+        .and_then(|_| state.allowlist.check_email(&user.email))
+    {
+        tracing::info!("Rejected API token {}: {}", api_token.id, reason);
+        return Ok(None);
+    }
+
+    Ok(Some(AuthUser {
+        user_id: api_token.user_id,
+        team_id: api_token.team_id,
+        token_id: Some(api_token.id),
+        scopes: api_token
+            .scopes
+            .iter()
+            .filter_map(|scope| scope.parse().ok())
+            .collect(),
+    }))
+}
+
+/// A middleware that authenticates the request with either a personal API token
+/// in the `Authorization` header or a valid "token" cookie. The authenticated
+/// user is added to the request extensions as an `AuthUser`. Browsers with a
+/// missing, expired or revoked session are sent back to the login page.
 async fn verify_token_middleware(
     State(state): State<RouterState>,
     jar: CookieJar,
-    request: Request,
+    mut request: Request,
     next: Next,
 ) -> Result<Response, errors::AppError> {
     if state.env_vars.slack_auth_enable {
-        if let Some(token) = jar.get("token").map(|cookie| cookie.value().to_owned()) {
-            let is_verified = verify_token(&token, &state).await?;
-            if !is_verified {
-                return Ok(Response::builder()
-                    .status(StatusCode::UNAUTHORIZED)
-                    .body(Body::from(FORBIDDEN_MSG))
-                    .unwrap());
-            }
+        let bearer_token = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|token| token.trim().to_owned());
+
+        let auth_user = if let Some(token) = bearer_token {
+            verify_api_token(&token, &state).await?
         } else {
+            let auth_user = match jar.get("token").map(|cookie| cookie.value().to_owned()) {
+                Some(token) => verify_token(&token, &state).await?,
+                None => None,
+            };
+            let Some(auth_user) = auth_user else {
+                return Ok((
+                    jar.remove(Cookie::build("token").path("/")),
+                    Response::builder()
+                        .status(StatusCode::TEMPORARY_REDIRECT)
+                        .header("Location", "/login")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                    .into_response());
+            };
+            Some(auth_user)
+        };
+
+        let Some(auth_user) = auth_user else {
             return Ok(Response::builder()
-                .status(StatusCode::TEMPORARY_REDIRECT)
-                .header("Location", "/login")
-                .body(Body::empty())
+                .status(StatusCode::UNAUTHORIZED)
+                .body(Body::from(FORBIDDEN_MSG))
                 .unwrap());
-        }
+        };
+        request.extensions_mut().insert(auth_user);
     }
 
     Ok(next.run(request).await)
 }
 
+/// A middleware that rejects requests whose API token lacks `scope`.
+/// Requests without an `AuthUser` (login disabled) are let through.
+async fn require_scope(scope: Scope, request: Request, next: Next) -> Response {
+    match request.extensions().get::<AuthUser>() {
+        Some(auth_user) if !auth_user.has_scope(scope) => Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body(Body::from(format!("This token lacks the `{}` scope.", scope)))
+            .unwrap(),
+        _ => next.run(request).await,
+    }
+}
+
 #[derive(Clone)]
 pub(super) struct RouterState {
     pub tummy: Tummy,
+    pub sessions: Sessions,
+    pub provider: Arc<dyn AuthProvider>,
+    pub login_states: LoginStates,
+    pub allowlist: LoginAllowlist,
+    pub keys: SessionKeys,
+    pub roles: Roles,
+    pub audit: AuditLog,
+    pub rate_limits: RateLimits,
+    pub highlighter: Highlighter,
+    pub ranking: Ranking,
+    pub embeddings: Option<Embeddings>,
+    pub search_alerts: SearchAlerts,
+    pub search_analytics: SearchAnalytics,
     pub env_vars: EnvVars,
 }
 
-pub fn get_excretor_router(tummy: Tummy, env_vars: EnvVars) -> Router {
-    let state = RouterState { tummy, env_vars };
+#[allow(clippy::too_many_arguments)]
+pub fn get_excretor_router(
+    tummy: Tummy,
+    sessions: Sessions,
+    keys: SessionKeys,
+    audit: AuditLog,
+    embeddings: Option<Embeddings>,
+    search_alerts: SearchAlerts,
+    search_analytics: SearchAnalytics,
+    env_vars: EnvVars,
+) -> Router {
+    let state = RouterState {
+        provider: providers::from_env(&env_vars, &tummy),
+        login_states: LoginStates::default(),
+        allowlist: LoginAllowlist::from_env(&env_vars),
+        roles: Roles::from_env(&env_vars, tummy.clone()),
+        rate_limits: RateLimits::from_env(&env_vars),
+        highlighter: Highlighter::from_env(&env_vars),
+        ranking: Ranking::from_env(&env_vars),
+        embeddings,
+        search_alerts,
+        search_analytics,
+        tummy,
+        sessions,
+        keys,
+        audit,
+        env_vars,
+    };
 
-    let api_router = Router::new()
-        .route("/channels", get(handlers::get_channels))
-        .route("/users", get(handlers::get_users))
+    let pagination_router = Router::new()
         .route("/channels/:channel_id", get(handlers::load_channel))
         .route("/messages/:channel_id", get(handlers::get_messages))
         .route("/replies", get(handlers::get_replies))
-        .route("/search", post(handlers::search));
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            |State(state): State<RouterState>, ClientIp(ip): ClientIp, request: Request, next: Next| {
+                rate_limit(Budget::Pagination, state, ip, request, next)
+            },
+        ));
+
+    let read_router = Router::new()
+        .route("/channels", get(handlers::get_channels))
+        .route("/users", get(handlers::get_users))
+        .merge(pagination_router)
+        .route_layer(middleware::from_fn(|request: Request, next: Next| {
+            require_scope(Scope::Read, request, next)
+        }));
+
+    let search_router = Router::new()
+        .route("/search", post(handlers::search))
+        .route("/search/spelling", get(handlers::spelling_suggestions))
+        .route("/messages/:channel_id/:ts/related", get(handlers::related_messages))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            |State(state): State<RouterState>, ClientIp(ip): ClientIp, request: Request, next: Next| {
+                rate_limit(Budget::Search, state, ip, request, next)
+            },
+        ))
+        .route_layer(middleware::from_fn(|request: Request, next: Next| {
+            require_scope(Scope::Search, request, next)
+        }));
+
+    let suggest_router = Router::new()
+        .route("/suggest", get(handlers::suggest))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            |State(state): State<RouterState>, ClientIp(ip): ClientIp, request: Request, next: Next| {
+                rate_limit(Budget::Suggest, state, ip, request, next)
+            },
+        ))
+        .route_layer(middleware::from_fn(|request: Request, next: Next| {
+            require_scope(Scope::Search, request, next)
+        }));
+
+    let saved_search_router = Router::new()
+        .route(
+            "/saved-searches",
+            get(handlers::list_saved_searches).post(handlers::create_saved_search),
+        )
+        .route(
+            "/saved-searches/:saved_search_id",
+            put(handlers::update_saved_search).delete(handlers::delete_saved_search),
+        )
+        .route("/search-alerts", get(handlers::get_search_alerts))
+        .route_layer(middleware::from_fn(|request: Request, next: Next| {
+            require_scope(Scope::Search, request, next)
+        }));
+
+    let auth_router = Router::new()
+        .route("/auth", get(handlers::auth))
+        .route("/auth/callback", get(handlers::auth_callback))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            |State(state): State<RouterState>, ClientIp(ip): ClientIp, request: Request, next: Next| {
+                rate_limit(Budget::Auth, state, ip, request, next)
+            },
+        ));
+
+    let api_router = Router::new()
+        .merge(read_router)
+        .merge(search_router)
+        .merge(suggest_router)
+        .merge(saved_search_router)
+        .route("/tokens", get(handlers::list_tokens).post(handlers::create_token))
+        .route("/tokens/:token_id", delete(handlers::revoke_token))
+        .route("/admin/roles", get(handlers::list_roles))
+        .route("/admin/roles/:user_id", put(handlers::set_role))
+        .route("/admin/audit", get(handlers::get_audit_log))
+        .route(
+            "/admin/channels/:channel_id/search-config",
+            put(handlers::set_channel_search_config),
+        )
+        .route("/admin/search/reindex", post(handlers::reindex_search))
+        .route("/admin/search-analytics/top-queries", get(handlers::top_search_queries))
+        .route(
+            "/admin/search-analytics/zero-result-queries",
+            get(handlers::zero_result_search_queries),
+        )
+        .route("/admin/search-analytics/slow-queries", get(handlers::slow_search_queries));
 
     Router::new()
         .nest("/api", api_router)
@@ -102,8 +363,8 @@ pub fn get_excretor_router(tummy: Tummy, env_vars: EnvVars) -> Router {
             state.clone(),
             verify_token_middleware,
         ))
-        .route("/auth", get(handlers::auth))
-        .route("/auth/callback", get(handlers::auth_callback))
+        .merge(auth_router)
+        .route("/auth/logout", post(handlers::logout))
         .route("/assets/*file", get(handlers::assets))
         .with_state(state)
 }
@@ -0,0 +1,135 @@
+//! Audit log of archive access.
+//! Every read of a channel, message page, thread, search or alert feed, and
+//! every export, is recorded in the append-only `audit_log` table in tummy
+//! together with the user, API token, client IP and request parameters.
+//! Entries older than `audit_retention_days` are purged periodically.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::auth::AuthUser;
+use crate::db::tummy::Tummy;
+use crate::env::EnvVars;
+
+/// What kind of access an audit entry records.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    /// A channel was opened.
+    LoadChannel,
+    /// A page of a channel's messages was fetched.
+    GetMessages,
+    /// A thread was opened.
+    GetReplies,
+    /// A search was run.
+    Search,
+    /// Messages related to a message were looked up.
+    Related,
+    /// The feed of search alert matches was read.
+    SearchAlerts,
+    /// Data was exported in bulk.
+    Export,
+}
+
+impl AuditAction {
+    pub const ALL: [AuditAction; 7] = [
+        AuditAction::LoadChannel,
+        AuditAction::GetMessages,
+        AuditAction::GetReplies,
+        AuditAction::Search,
+        AuditAction::Related,
+        AuditAction::SearchAlerts,
+        AuditAction::Export,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::LoadChannel => "load_channel",
+            AuditAction::GetMessages => "get_messages",
+            AuditAction::GetReplies => "get_replies",
+            AuditAction::Search => "search",
+            AuditAction::Related => "related",
+            AuditAction::SearchAlerts => "search_alerts",
+            AuditAction::Export => "export",
+        }
+    }
+}
+
+impl fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AuditAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AuditAction::ALL
+            .into_iter()
+            .find(|action| action.as_str() == s)
+            .ok_or_else(|| format!("Unknown audit action `{}`.", s))
+    }
+}
+
+/// Writes and expires audit entries.
+#[derive(Clone)]
+pub struct AuditLog {
+    tummy: Tummy,
+    /// How long entries are kept. `None` keeps them forever.
+    retention: Option<Duration>,
+}
+
+impl AuditLog {
+    pub fn from_env(env_vars: &EnvVars, tummy: Tummy) -> Self {
+        Self {
+            tummy,
+            retention: (env_vars.audit_retention_days > 0)
+                .then(|| Duration::days(env_vars.audit_retention_days)),
+        }
+    }
+
+    /// Records an access. Fails if the entry cannot be written, so that no
+    /// access goes unrecorded.
+    ///
+    /// # Parameters
+    /// - `auth_user`: The authenticated user, if login is enabled.
+    /// - `ip`: The client's IP address, if known.
+    /// - `action`: What was accessed.
+    /// - `params`: The request parameters, as a JSON object.
+    pub async fn record(
+        &self,
+        auth_user: Option<&AuthUser>,
+        ip: Option<IpAddr>,
+        action: AuditAction,
+        params: Value,
+    ) -> color_eyre::Result<()> {
+        self.tummy
+            .insert_audit_entry(
+                Utc::now().naive_utc(),
+                auth_user.map(|user| user.user_id.as_str()),
+                auth_user.and_then(|user| user.token_id),
+                ip.map(|ip| ip.to_string()).as_deref(),
+                action.as_str(),
+                &params,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Removes entries older than the retention period, returning how many were removed.
+    pub async fn purge_expired(&self) -> color_eyre::Result<u64> {
+        let Some(retention) = self.retention else {
+            return Ok(0);
+        };
+        Ok(self
+            .tummy
+            .delete_audit_entries_before(Utc::now().naive_utc() - retention)
+            .await?)
+    }
+}
@@ -0,0 +1,151 @@
+//! Restricts who may log in by Slack workspace and email domain.
+//! The Slack app can be installed by other workspaces, so a user existing in
+//! tummy is not enough. Empty lists allow everyone.
+//! The lists are checked at login, and again for sessions and API tokens in
+//! use, so that taking a workspace or domain off them locks its users out.
+
+use crate::env::{AuthProviderKind, EnvVars};
+
+/// Splits a comma-separated list, lowercasing and dropping empty entries.
+fn parse_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|entry| entry.trim().to_lowercase())
+        .filter(|entry| !entry.is_empty())
+        .collect()
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct LoginAllowlist {
+    /// Slack team IDs whose members may log in.
+    team_ids: Vec<String>,
+    /// Email domains whose users may log in.
+    email_domains: Vec<String>,
+}
+
+impl LoginAllowlist {
+    pub fn from_env(env_vars: &EnvVars) -> Self {
+        Self {
+            // Only Slack logins have a team.
+            team_ids: match env_vars.auth_provider {
+                AuthProviderKind::Slack => parse_list(&env_vars.allowed_slack_team_ids),
+                AuthProviderKind::Oidc => Vec::new(),
+            },
+            email_domains: parse_list(&env_vars.allowed_email_domains),
+        }
+    }
+
+    /// Checks the Slack team the user logged in with.
+    ///
+    /// # Returns
+    /// The reason for rejecting the team, if it is not allowed.
+    pub fn check_team(&self, team_id: Option<&str>) -> Result<(), String> {
+        if self.team_ids.is_empty() {
+            return Ok(());
+        }
+
+        match team_id {
+            Some(team_id) if self.team_ids.contains(&team_id.to_lowercase()) => Ok(()),
+            Some(team_id) => Err(format!("Slack team `{}` is not allowed", team_id)),
+            None => Err("the Slack team could not be determined".to_owned()),
+        }
+    }
+
+    /// Checks the domain of the user's email address.
+    ///
+    /// # Returns
+    /// The reason for rejecting the address, if its domain is not allowed.
+    pub fn check_email(&self, email: &str) -> Result<(), String> {
+        if self.email_domains.is_empty() {
+            return Ok(());
+        }
+
+        match email.rsplit_once('@') {
+            Some((_, domain)) if self.email_domains.contains(&domain.to_lowercase()) => Ok(()),
+            _ => Err(format!("email address `{}` is not in an allowed domain", email)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowlist(team_ids: &str, email_domains: &str) -> LoginAllowlist {
+        LoginAllowlist {
+            team_ids: parse_list(team_ids),
+            email_domains: parse_list(email_domains),
+        }
+    }
+
+    #[test]
+    fn parses_lists() {
+        assert_eq!(parse_list(" T01ABC ,, t02def,"), ["t01abc", "t02def"]);
+        assert!(parse_list("").is_empty());
+        assert!(parse_list(" , ").is_empty());
+    }
+
+    #[test]
+    fn checks_teams() {
+        // (allowed teams, team logged in with, allowed)
+        let cases = [
+            ("", None, true),
+            ("", Some("T01ABC"), true),
+            (" , ", Some("T01ABC"), true),
+            ("T01ABC", Some("T01ABC"), true),
+            ("t01abc", Some("T01ABC"), true),
+            ("T01ABC,T02DEF", Some("T02DEF"), true),
+            ("T01ABC", Some("T02DEF"), false),
+            ("T01ABC", Some("T01AB"), false),
+            ("T01ABC", None, false),
+        ];
+
+        for (team_ids, team_id, allowed) in cases {
+            assert_eq!(
+                allowlist(team_ids, "").check_team(team_id).is_ok(),
+                allowed,
+                "for {:?} in {:?}",
+                team_id,
+                team_ids
+            );
+        }
+    }
+
+    #[test]
+    fn checks_email_domains() {
+        // (allowed domains, email address, allowed)
+        let cases = [
+            ("", "alice@example.com", true),
+            ("", "", true),
+            ("example.com", "alice@example.com", true),
+            ("Example.COM", "alice@EXAMPLE.com", true),
+            ("example.org, example.com", "alice@example.com", true),
+            ("example.com", "alice@example.org", false),
+            ("example.com", "alice@mail.example.com", false),
+            ("example.com", "alice@example.com.evil.org", false),
+            ("example.com", "example.com", false),
+            ("example.com", "", false),
+            // Only the part after the last `@` is the domain.
+            ("example.com", "alice@example.com@evil.org", false),
+            ("evil.org", "\"a@b\"@evil.org", true),
+        ];
+
+        for (email_domains, email, allowed) in cases {
+            assert_eq!(
+                allowlist("", email_domains).check_email(email).is_ok(),
+                allowed,
+                "for {:?} in {:?}",
+                email,
+                email_domains
+            );
+        }
+    }
+
+    #[test]
+    fn checks_lists_independently() {
+        let allowlist = allowlist("T01ABC", "example.com");
+        assert!(allowlist.check_team(Some("T01ABC")).is_ok());
+        assert!(allowlist.check_email("alice@example.org").is_err());
+        assert!(LoginAllowlist::default().check_team(None).is_ok());
+        assert!(LoginAllowlist::default().check_email("anyone").is_ok());
+    }
+}
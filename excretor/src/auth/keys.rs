@@ -0,0 +1,286 @@
+//! Keys for signing session tokens and encrypting provider tokens at rest.
+//! Keys are configured as `kid:secret` pairs in `SESSION_SIGNING_KEYS`. The
+//! first key signs and encrypts; all keys are accepted when verifying and
+//! decrypting, so a new key can be put first while the old one is phased out.
+
+use std::sync::Arc;
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use color_eyre::eyre::eyre;
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rand::RngCore;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::env::EnvVars;
+
+/// Shortest secret accepted for a key.
+const MIN_SECRET_LEN: usize = 32;
+
+struct Key {
+    kid: String,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    cipher: Aes256Gcm,
+}
+
+impl Key {
+    fn new(kid: &str, secret: &str) -> Self {
+        // The encryption key is derived from, but never equal to, the signing secret.
+        let mut hasher = Sha256::new();
+        hasher.update(b"opsa-session-encryption:");
+        hasher.update(secret.as_bytes());
+
+        Self {
+            kid: kid.to_owned(),
+            encoding: EncodingKey::from_secret(secret.as_bytes()),
+            decoding: DecodingKey::from_secret(secret.as_bytes()),
+            cipher: Aes256Gcm::new(&hasher.finalize()),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SessionKeys {
+    /// The configured keys, the active one first.
+    keys: Arc<Vec<Key>>,
+}
+
+impl SessionKeys {
+    /// Reads the key set from the environment.
+    /// Keys are required when login is enabled.
+    pub fn from_env(env_vars: &EnvVars) -> Result<Self, String> {
+        Self::parse(&env_vars.session_signing_keys, env_vars.slack_auth_enable)
+    }
+
+    /// Parses comma-separated `kid:secret` pairs, the active key first.
+    fn parse(session_signing_keys: &str, required: bool) -> Result<Self, String> {
+        let mut keys = Vec::new();
+        let entries = session_signing_keys
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty());
+        for entry in entries {
+            let Some((kid, secret)) = entry.split_once(':') else {
+                return Err("SESSION_SIGNING_KEYS entries must look like `kid:secret`.".into());
+            };
+            let valid_kid = !kid.is_empty()
+                && kid
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid_kid || secret.len() < MIN_SECRET_LEN {
+                return Err(format!(
+                    "Session signing key `{}` needs an alphanumeric kid and a secret of at least {} characters.",
+                    kid, MIN_SECRET_LEN
+                ));
+            }
+            if keys.iter().any(|key: &Key| key.kid == kid) {
+                return Err(format!("Session signing key `{}` is configured twice.", kid));
+            }
+            keys.push(Key::new(kid, secret));
+        }
+
+        if keys.is_empty() && required {
+            return Err("SESSION_SIGNING_KEYS is required when SLACK_AUTH_ENABLE=true.".into());
+        }
+
+        Ok(Self {
+            keys: Arc::new(keys),
+        })
+    }
+
+    fn active(&self) -> color_eyre::Result<&Key> {
+        self.keys
+            .first()
+            .ok_or_else(|| eyre!("No session signing key is configured."))
+    }
+
+    fn find(&self, kid: &str) -> color_eyre::Result<&Key> {
+        self.keys
+            .iter()
+            .find(|key| key.kid == kid)
+            .ok_or_else(|| eyre!("Unknown session signing key `{}`.", kid))
+    }
+
+    /// Signs `claims` as an HS256 JWT with the active key's `kid` in the header.
+    pub fn sign<T: Serialize>(&self, claims: &T) -> color_eyre::Result<String> {
+        let key = self.active()?;
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some(key.kid.clone());
+        Ok(encode(&header, claims, &key.encoding)?)
+    }
+
+    /// Verifies a JWT signed by any configured key. The `exp` claim is required.
+    pub fn verify<T: DeserializeOwned>(&self, token: &str) -> color_eyre::Result<T> {
+        let header = decode_header(token)?;
+        let kid = header.kid.ok_or_else(|| eyre!("Token has no `kid`."))?;
+        let key = self.find(&kid)?;
+        Ok(decode::<T>(token, &key.decoding, &Validation::new(Algorithm::HS256))?.claims)
+    }
+
+    /// Encrypts a secret with the active key.
+    pub fn encrypt(&self, plaintext: &str) -> color_eyre::Result<String> {
+        let key = self.active()?;
+        let mut nonce = [0u8; 12];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = key
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+            .map_err(|_| eyre!("Could not encrypt secret."))?;
+
+        Ok(format!(
+            "{}.{}.{}",
+            key.kid,
+            URL_SAFE_NO_PAD.encode(nonce),
+            URL_SAFE_NO_PAD.encode(ciphertext)
+        ))
+    }
+
+    /// Decrypts a secret encrypted by any configured key.
+    pub fn decrypt(&self, sealed: &str) -> color_eyre::Result<String> {
+        let mut parts = sealed.splitn(3, '.');
+        let (Some(kid), Some(nonce), Some(ciphertext)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(eyre!("Malformed encrypted secret."));
+        };
+
+        let key = self.find(kid)?;
+        let nonce = URL_SAFE_NO_PAD.decode(nonce)?;
+        if nonce.len() != 12 {
+            return Err(eyre!("Malformed encrypted secret."));
+        }
+        let plaintext = key
+            .cipher
+            .decrypt(Nonce::from_slice(&nonce), URL_SAFE_NO_PAD.decode(ciphertext)?.as_slice())
+            .map_err(|_| eyre!("Could not decrypt secret."))?;
+        Ok(String::from_utf8(plaintext)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    const OLD: &str = "old:0123456789abcdefghijklmnopqrstuv";
+    const NEW: &str = "new:vutsrqponmlkjihgfedcba9876543210";
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Claims {
+        sub: String,
+        exp: i64,
+    }
+
+    fn key_set(config: &str) -> SessionKeys {
+        SessionKeys::parse(config, true).unwrap()
+    }
+
+    fn claims() -> Claims {
+        Claims {
+            sub: "U1".to_owned(),
+            exp: chrono::Utc::now().timestamp() + 60,
+        }
+    }
+
+    #[test]
+    fn parses_key_sets() {
+        let cases = [
+            ("", false, true),
+            ("", true, false),
+            (OLD, true, true),
+            (&format!(" {} , {} ", NEW, OLD), true, true),
+            ("old", true, false),
+            ("old:short", true, false),
+            (":0123456789abcdefghijklmnopqrstuv", true, false),
+            ("o/d:0123456789abcdefghijklmnopqrstuv", true, false),
+            (&format!("{},{}", OLD, OLD), true, false),
+        ];
+
+        for (config, required, valid) in cases {
+            assert_eq!(SessionKeys::parse(config, required).is_ok(), valid, "for {:?}", config);
+        }
+    }
+
+    #[test]
+    fn signs_and_verifies() {
+        let keys = key_set(OLD);
+        let token = keys.sign(&claims()).unwrap();
+        assert_eq!(decode_header(&token).unwrap().kid.as_deref(), Some("old"));
+        assert_eq!(keys.verify::<Claims>(&token).unwrap(), claims());
+    }
+
+    #[test]
+    fn verifies_with_rotated_keys() {
+        let old_token = key_set(OLD).sign(&claims()).unwrap();
+        let rotated = key_set(&format!("{},{}", NEW, OLD));
+
+        assert_eq!(rotated.verify::<Claims>(&old_token).unwrap(), claims());
+        let new_token = rotated.sign(&claims()).unwrap();
+        assert_eq!(decode_header(&new_token).unwrap().kid.as_deref(), Some("new"));
+        assert!(key_set(OLD).verify::<Claims>(&new_token).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_tokens() {
+        let keys = key_set(OLD);
+        let token = keys.sign(&claims()).unwrap();
+
+        // The same kid with another secret.
+        let forged = SessionKeys::parse("old:another-secret-of-thirty-two-chars", true)
+            .unwrap()
+            .sign(&claims())
+            .unwrap();
+        let expired = keys
+            .sign(&Claims {
+                sub: "U1".to_owned(),
+                exp: chrono::Utc::now().timestamp() - 3600,
+            })
+            .unwrap();
+        let without_kid = encode(
+            &Header::new(Algorithm::HS256),
+            &claims(),
+            &EncodingKey::from_secret(b"0123456789abcdefghijklmnopqrstuv"),
+        )
+        .unwrap();
+        let (payload, signature) = token.rsplit_once('.').unwrap();
+        let tampered = format!("{}.{}", payload, signature.chars().rev().collect::<String>());
+
+        for token in [forged, expired, without_kid, tampered, "not a token".to_owned()] {
+            assert!(keys.verify::<Claims>(&token).is_err(), "accepted {:?}", token);
+        }
+        assert!(key_set(NEW).verify::<Claims>(&keys.sign(&claims()).unwrap()).is_err());
+    }
+
+    #[test]
+    fn encrypts_and_decrypts() {
+        let keys = key_set(OLD);
+        let sealed = keys.encrypt("xoxp-secret").unwrap();
+        assert!(sealed.starts_with("old."));
+        assert!(!sealed.contains("xoxp-secret"));
+        assert_ne!(sealed, keys.encrypt("xoxp-secret").unwrap());
+        assert_eq!(keys.decrypt(&sealed).unwrap(), "xoxp-secret");
+
+        let rotated = key_set(&format!("{},{}", NEW, OLD));
+        assert_eq!(rotated.decrypt(&sealed).unwrap(), "xoxp-secret");
+        assert!(key_set(NEW).decrypt(&sealed).is_err());
+    }
+
+    #[test]
+    fn rejects_tampered_secrets() {
+        let keys = key_set(OLD);
+        let sealed = keys.encrypt("xoxp-secret").unwrap();
+        let (prefix, ciphertext) = sealed.rsplit_once('.').unwrap();
+        let mut bytes = URL_SAFE_NO_PAD.decode(ciphertext).unwrap();
+        bytes[0] ^= 1;
+        let tampered = format!("{}.{}", prefix, URL_SAFE_NO_PAD.encode(bytes));
+
+        let malformed = ["old.AAAA", "old.AAAAAAAAAAAAAAAA.AAAA", "xoxp-secret"];
+        for sealed in malformed.map(str::to_owned).into_iter().chain([tampered]) {
+            assert!(keys.decrypt(&sealed).is_err(), "decrypted {:?}", sealed);
+        }
+    }
+}
@@ -0,0 +1,45 @@
+//! Single-use OAuth `state` nonces.
+//! A nonce is generated when a login starts and stored in a short-lived signed
+//! cookie. The callback must present the same nonce, and each nonce is accepted
+//! at most once so that a captured callback URL cannot be replayed.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use chrono::{Duration, NaiveDateTime, Utc};
+
+use super::providers::random_token;
+
+/// How long a user has to complete a login.
+pub const LOGIN_STATE_TTL_MINUTES: i64 = 10;
+
+#[derive(Clone, Default)]
+pub struct LoginStates {
+    /// Nonces that were already used, with the time they expire anyway.
+    consumed: Arc<Mutex<HashMap<String, NaiveDateTime>>>,
+}
+
+impl LoginStates {
+    /// Generates a new nonce and the time it expires.
+    pub fn issue(&self) -> (String, NaiveDateTime) {
+        (
+            random_token(16),
+            Utc::now().naive_utc() + Duration::minutes(LOGIN_STATE_TTL_MINUTES),
+        )
+    }
+
+    /// Marks a nonce as used.
+    ///
+    /// # Returns
+    /// `false` if the nonce has expired or was already used.
+    pub fn consume(&self, nonce: &str, expires_at: NaiveDateTime) -> bool {
+        let now = Utc::now().naive_utc();
+        if expires_at <= now {
+            return false;
+        }
+
+        let mut consumed = self.consumed.lock().unwrap();
+        consumed.retain(|_, expires_at| *expires_at > now);
+        consumed.insert(nonce.to_owned(), expires_at).is_none()
+    }
+}
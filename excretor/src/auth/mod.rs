@@ -0,0 +1,45 @@
+//! Authentication building blocks shared by the API layer.
+//! Holds the login providers, the Slack Web API client, the server-side
+//! sessions, user roles and personal API tokens.
+
+pub mod allowlist;
+pub mod keys;
+pub mod login_state;
+pub mod providers;
+pub mod roles;
+pub mod sessions;
+pub mod slack;
+pub mod tokens;
+
+use tokens::Scope;
+
+/// The user a request was authenticated as.
+/// Inserted into the request extensions by the authentication middleware.
+#[derive(Clone, Debug)]
+pub struct AuthUser {
+    /// The ID of the authenticated user.
+    pub user_id: String,
+    /// The Slack team of the user's login, if known. API tokens carry the
+    /// team of the session they were created from.
+    pub team_id: Option<String>,
+    /// The API token used, if the request did not come from a browser session.
+    pub token_id: Option<i64>,
+    /// What the request is allowed to do.
+    pub scopes: Vec<Scope>,
+}
+
+impl AuthUser {
+    /// A user logged in through the browser, who may do everything.
+    pub fn from_session(user_id: String, team_id: Option<String>) -> Self {
+        Self {
+            user_id,
+            team_id,
+            token_id: None,
+            scopes: Scope::ALL.to_vec(),
+        }
+    }
+
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+}
@@ -0,0 +1,140 @@
+//! Pluggable login providers.
+//! A provider knows how to send a user off to log in, how to turn the
+//! callback into a user from tummy, and how to re-check or revoke a stored
+//! access token.
+//! The provider in use is selected with the `AUTH_PROVIDER` environment variable.
+
+mod oidc;
+mod slack;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::RngCore;
+
+use crate::auth::allowlist::LoginAllowlist;
+use crate::db::tummy::Tummy;
+use crate::env::{AuthProviderKind, EnvVars};
+use crate::types::User;
+
+pub use oidc::OidcProvider;
+pub use slack::SlackProvider;
+
+/// Values a provider needs to carry from the login redirect to the callback.
+/// They are kept in a short-lived signed cookie in between.
+pub type FlowState = BTreeMap<String, String>;
+
+/// Where to send the user to log in.
+pub struct AuthRedirect {
+    /// The provider's authorization URL.
+    pub url: String,
+    /// State to hand back to `AuthProvider::callback`.
+    pub flow: FlowState,
+}
+
+/// A user who completed the login flow.
+pub struct Identity {
+    /// The matching user in tummy.
+    pub user: User,
+    /// The Slack team the user logged in with, for providers that have teams.
+    pub team_id: Option<String>,
+    /// The provider's access token, used to re-validate the session later.
+    pub access_token: String,
+}
+
+/// Why a login attempt failed.
+pub enum LoginError {
+    /// The user cancelled, or the provider refused to log them in.
+    Denied,
+    /// The user logged in with the provider but may not use the archive.
+    Forbidden(String),
+    /// The provider could not be reached or sent back something unusable.
+    Provider(color_eyre::Report),
+    /// Something went wrong on our side.
+    Internal(color_eyre::Report),
+}
+
+impl From<reqwest::Error> for LoginError {
+    fn from(err: reqwest::Error) -> Self {
+        LoginError::Provider(err.into())
+    }
+}
+
+impl From<serde_json::Error> for LoginError {
+    fn from(err: serde_json::Error) -> Self {
+        LoginError::Provider(err.into())
+    }
+}
+
+impl From<sqlx::Error> for LoginError {
+    fn from(err: sqlx::Error) -> Self {
+        LoginError::Internal(err.into())
+    }
+}
+
+/// A login provider.
+#[async_trait]
+pub trait AuthProvider: Send + Sync {
+    /// A short name for logs.
+    fn name(&self) -> &'static str;
+
+    /// Builds the URL the user is redirected to in order to log in.
+    /// `state` must be passed on to the provider, which sends it back to the callback.
+    async fn authorize(&self, state: &str) -> color_eyre::Result<AuthRedirect>;
+
+    /// Completes the login flow with the authorization code from the callback.
+    /// The `state` parameter has already been checked by the caller.
+    async fn callback(&self, code: &str, flow: &FlowState) -> Result<Identity, LoginError>;
+
+    /// Checks whether an access token issued at login is still accepted.
+    async fn revalidate(&self, access_token: &str) -> color_eyre::Result<bool>;
+
+    /// Revokes an access token issued at login, if the provider supports it.
+    async fn revoke(&self, access_token: &str) -> color_eyre::Result<()>;
+}
+
+/// Builds the provider configured by the environment.
+pub fn from_env(env_vars: &EnvVars, tummy: &Tummy) -> Arc<dyn AuthProvider> {
+    match env_vars.auth_provider {
+        AuthProviderKind::Slack => Arc::new(SlackProvider::new(env_vars, tummy.clone())),
+        AuthProviderKind::Oidc => Arc::new(OidcProvider::new(env_vars, tummy.clone())),
+    }
+}
+
+/// Generates a URL-safe random string from `bytes` random bytes.
+pub(crate) fn random_token(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+    rand::thread_rng().fill_bytes(&mut buf);
+    URL_SAFE_NO_PAD.encode(buf)
+}
+
+/// Whether a user from tummy may log in at all.
+pub(crate) fn is_allowed(user: &User) -> bool {
+    !(user.id.is_empty() || user.is_bot || user.deleted)
+}
+
+/// Checks that a user from tummy may log in, including the email domain allowlist.
+fn allowed_identity(
+    user: User,
+    team_id: Option<String>,
+    access_token: String,
+    allowlist: &LoginAllowlist,
+) -> Result<Identity, LoginError> {
+    if !is_allowed(&user) {
+        return Err(LoginError::Forbidden(format!(
+            "user `{}` is deleted or a bot",
+            user.id
+        )));
+    }
+    allowlist
+        .check_email(&user.email)
+        .map_err(|reason| LoginError::Forbidden(format!("user `{}`: {}", user.id, reason)))?;
+    Ok(Identity {
+        user,
+        team_id,
+        access_token,
+    })
+}
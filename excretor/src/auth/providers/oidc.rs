@@ -0,0 +1,340 @@
+//! Login with a generic OpenID Connect identity provider (e.g. Keycloak, Dex).
+//! Uses the authorization code flow with PKCE. The provider's endpoints are
+//! discovered from `<issuer>/.well-known/openid-configuration`, and the ID token
+//! is validated against the provider's JWKS, with the algorithm its key declares
+//! (RS256 or ES256 for keys that declare none). The configured email claim is then
+//! matched against `users.email` in tummy.
+
+use async_trait::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use color_eyre::eyre::eyre;
+use jsonwebtoken::jwk::{AlgorithmParameters, Jwk, JwkSet};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::Error::RowNotFound;
+use std::collections::HashMap;
+use std::str::FromStr;
+use tokio::sync::{OnceCell, RwLock};
+
+use super::{
+    allowed_identity, random_token, AuthProvider, AuthRedirect, FlowState, Identity, LoginError,
+};
+use crate::auth::allowlist::LoginAllowlist;
+use crate::db::tummy::Tummy;
+use crate::env::EnvVars;
+
+const FLOW_PKCE_VERIFIER: &str = "pkce_verifier";
+const FLOW_NONCE: &str = "nonce";
+
+/// The algorithm an ID token signed with `jwk` must use: the one the key
+/// declares, or RS256 or ES256 for RSA and EC keys that declare none. The
+/// token's header is not trusted for this, or it could have the public key
+/// used as an HMAC secret.
+fn id_token_algorithm(jwk: &Jwk) -> color_eyre::Result<Algorithm> {
+    let Some(key_algorithm) = jwk.common.key_algorithm else {
+        return match jwk.algorithm {
+            AlgorithmParameters::RSA(_) => Ok(Algorithm::RS256),
+            AlgorithmParameters::EllipticCurve(_) => Ok(Algorithm::ES256),
+            _ => Err(eyre!("The ID token key declares no algorithm.")),
+        };
+    };
+    match Algorithm::from_str(&key_algorithm.to_string()) {
+        Ok(Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) | Err(_) => Err(eyre!(
+            "The ID token key declares the unsupported algorithm {}.",
+            key_algorithm
+        )),
+        Ok(algorithm) => Ok(algorithm),
+    }
+}
+
+/// The subset of the discovery document that is used.
+#[derive(Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+    userinfo_endpoint: Option<String>,
+    revocation_endpoint: Option<String>,
+}
+
+/// The subset of the token endpoint response that is used.
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    id_token: String,
+}
+
+pub struct OidcProvider {
+    http: Client,
+    tummy: Tummy,
+    issuer_url: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_uri: String,
+    scopes: String,
+    email_claim: String,
+    allowlist: LoginAllowlist,
+    metadata: OnceCell<ProviderMetadata>,
+    jwks: RwLock<Option<JwkSet>>,
+}
+
+impl OidcProvider {
+    pub fn new(env_vars: &EnvVars, tummy: Tummy) -> Self {
+        Self {
+            http: Client::new(),
+            tummy,
+            issuer_url: env_vars.oidc_issuer_url.trim_end_matches('/').to_owned(),
+            client_id: env_vars.oidc_client_id.clone(),
+            client_secret: env_vars
+                .oidc_client_secret
+                .clone()
+                .filter(|secret| !secret.is_empty()),
+            redirect_uri: env_vars.oidc_redirect_uri.clone(),
+            scopes: env_vars.oidc_scopes.clone(),
+            email_claim: env_vars.oidc_email_claim.clone(),
+            allowlist: LoginAllowlist::from_env(env_vars),
+            metadata: OnceCell::new(),
+            jwks: RwLock::new(None),
+        }
+    }
+
+    /// Fetches the discovery document on first use.
+    async fn metadata(&self) -> color_eyre::Result<&ProviderMetadata> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let url = format!("{}/.well-known/openid-configuration", self.issuer_url);
+                let body = self.http.get(url).send().await?.error_for_status()?.text().await?;
+                let metadata: ProviderMetadata = serde_json::from_str(&body)?;
+                if metadata.issuer.trim_end_matches('/') != self.issuer_url {
+                    return Err(eyre!(
+                        "OIDC discovery returned issuer `{}`, expected `{}`.",
+                        metadata.issuer,
+                        self.issuer_url
+                    ));
+                }
+                Ok(metadata)
+            })
+            .await
+    }
+
+    /// Returns the key set, refetching it if `kid` is not known yet
+    /// (the provider may have rotated its keys).
+    async fn jwks(&self, kid: Option<&str>) -> color_eyre::Result<JwkSet> {
+        if let Some(jwks) = self.jwks.read().await.as_ref() {
+            if kid.is_none_or(|kid| jwks.find(kid).is_some()) {
+                return Ok(jwks.clone());
+            }
+        }
+
+        let metadata = self.metadata().await?;
+        let body = self
+            .http
+            .get(&metadata.jwks_uri)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let jwks: JwkSet = serde_json::from_str(&body)?;
+        *self.jwks.write().await = Some(jwks.clone());
+        Ok(jwks)
+    }
+
+    /// Validates the ID token's signature, issuer, audience, expiry and nonce.
+    ///
+    /// # Returns
+    /// The token's claims.
+    async fn validate_id_token(
+        &self,
+        id_token: &str,
+        nonce: &str,
+    ) -> color_eyre::Result<HashMap<String, serde_json::Value>> {
+        let metadata = self.metadata().await?;
+        let header = decode_header(id_token)?;
+        let jwks = self.jwks(header.kid.as_deref()).await?;
+
+        let jwk = match header.kid.as_deref() {
+            Some(kid) => jwks.find(kid),
+            None => jwks.keys.first(),
+        }
+        .ok_or_else(|| eyre!("No matching key found for the ID token."))?;
+
+        let algorithm = id_token_algorithm(jwk)?;
+        if header.alg != algorithm {
+            return Err(eyre!(
+                "ID token is signed with {:?}, expected {:?}.",
+                header.alg,
+                algorithm
+            ));
+        }
+        let mut validation = Validation::new(algorithm);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&self.client_id]);
+
+        let claims = decode::<HashMap<String, serde_json::Value>>(
+            id_token,
+            &DecodingKey::from_jwk(jwk)?,
+            &validation,
+        )?
+        .claims;
+
+        if claims.get("nonce").and_then(|nonce| nonce.as_str()) != Some(nonce) {
+            return Err(eyre!("ID token nonce does not match."));
+        }
+
+        Ok(claims)
+    }
+}
+
+#[async_trait]
+impl AuthProvider for OidcProvider {
+    fn name(&self) -> &'static str {
+        "oidc"
+    }
+
+    async fn authorize(&self, state: &str) -> color_eyre::Result<AuthRedirect> {
+        let metadata = self.metadata().await?;
+
+        let verifier = random_token(32);
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+        let nonce = random_token(16);
+
+        let mut url = reqwest::Url::parse(&metadata.authorization_endpoint)?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.client_id)
+            .append_pair("redirect_uri", &self.redirect_uri)
+            .append_pair("scope", &self.scopes)
+            .append_pair("state", state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        let mut flow = FlowState::new();
+        flow.insert(FLOW_PKCE_VERIFIER.to_owned(), verifier);
+        flow.insert(FLOW_NONCE.to_owned(), nonce);
+
+        Ok(AuthRedirect {
+            url: url.to_string(),
+            flow,
+        })
+    }
+
+    async fn callback(&self, code: &str, flow: &FlowState) -> Result<Identity, LoginError> {
+        let (Some(verifier), Some(nonce)) = (flow.get(FLOW_PKCE_VERIFIER), flow.get(FLOW_NONCE))
+        else {
+            return Err(LoginError::Provider(eyre!(
+                "OIDC callback without a PKCE verifier and nonce."
+            )));
+        };
+
+        let metadata = self.metadata().await.map_err(LoginError::Provider)?;
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.redirect_uri.as_str()),
+            ("client_id", self.client_id.as_str()),
+            ("code_verifier", verifier.as_str()),
+        ];
+        if let Some(secret) = &self.client_secret {
+            form.push(("client_secret", secret.as_str()));
+        }
+
+        let response = self
+            .http
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::OK => {}
+            StatusCode::BAD_REQUEST | StatusCode::UNAUTHORIZED => {
+                tracing::warn!("OIDC token endpoint refused the code: {}", response.text().await?);
+                return Err(LoginError::Denied);
+            }
+            status => {
+                return Err(LoginError::Provider(eyre!(
+                    "OIDC token endpoint returned {}.",
+                    status
+                )));
+            }
+        }
+
+        let tokens: TokenResponse = serde_json::from_str(&response.text().await?)?;
+        let claims = self
+            .validate_id_token(&tokens.id_token, nonce)
+            .await
+            .map_err(LoginError::Provider)?;
+
+        if claims.get("email_verified").and_then(|v| v.as_bool()) == Some(false) {
+            return Err(LoginError::Forbidden("email address is not verified".to_owned()));
+        }
+
+        let Some(email) = claims.get(&self.email_claim).and_then(|v| v.as_str()) else {
+            return Err(LoginError::Forbidden(format!(
+                "ID token has no `{}` claim",
+                self.email_claim
+            )));
+        };
+
+        let user = match self.tummy.get_user_by_email(email).await {
+            Ok(user) => user,
+            Err(RowNotFound) => {
+                return Err(LoginError::Forbidden(format!("no user with email `{}`", email)));
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        allowed_identity(user, None, tokens.access_token, &self.allowlist)
+    }
+
+    async fn revalidate(&self, access_token: &str) -> color_eyre::Result<bool> {
+        let metadata = self.metadata().await?;
+        let Some(userinfo_endpoint) = &metadata.userinfo_endpoint else {
+            // Nothing to check against; rely on the session TTL instead.
+            return Ok(true);
+        };
+
+        let response = self
+            .http
+            .get(userinfo_endpoint)
+            .bearer_auth(access_token)
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(true),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Ok(false),
+            status => Err(eyre!("OIDC userinfo endpoint returned {}.", status)),
+        }
+    }
+
+    async fn revoke(&self, access_token: &str) -> color_eyre::Result<()> {
+        let metadata = self.metadata().await?;
+        let Some(revocation_endpoint) = &metadata.revocation_endpoint else {
+            return Ok(());
+        };
+
+        let mut form = vec![
+            ("token", access_token),
+            ("token_type_hint", "access_token"),
+            ("client_id", self.client_id.as_str()),
+        ];
+        if let Some(secret) = &self.client_secret {
+            form.push(("client_secret", secret.as_str()));
+        }
+
+        self.http
+            .post(revocation_endpoint)
+            .form(&form)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
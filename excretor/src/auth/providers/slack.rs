@@ -0,0 +1,115 @@
+//! Login with Slack's OAuth v2 flow.
+
+use async_trait::async_trait;
+use color_eyre::eyre::eyre;
+use sqlx::Error::RowNotFound;
+
+use super::{allowed_identity, AuthProvider, AuthRedirect, FlowState, Identity, LoginError};
+use crate::auth::allowlist::LoginAllowlist;
+use crate::auth::slack::SlackClient;
+use crate::db::tummy::Tummy;
+use crate::env::EnvVars;
+
+pub struct SlackProvider {
+    slack: SlackClient,
+    tummy: Tummy,
+    client_id: String,
+    client_secret: String,
+    redirect_uri: String,
+    allowlist: LoginAllowlist,
+}
+
+impl SlackProvider {
+    pub fn new(env_vars: &EnvVars, tummy: Tummy) -> Self {
+        Self {
+            slack: SlackClient::new(env_vars),
+            tummy,
+            client_id: env_vars.slack_client_id.clone(),
+            client_secret: env_vars.slack_client_secret.clone(),
+            redirect_uri: env_vars.slack_redirect_uri.clone(),
+            allowlist: LoginAllowlist::from_env(env_vars),
+        }
+    }
+}
+
+#[async_trait]
+impl AuthProvider for SlackProvider {
+    fn name(&self) -> &'static str {
+        "slack"
+    }
+
+    async fn authorize(&self, state: &str) -> color_eyre::Result<AuthRedirect> {
+        // Only a user token is requested, so that re-validating and revoking
+        // it on logout affect the user's own login and not the workspace's
+        // bot token.
+        let user_scopes = "users:read";
+        let mut url = reqwest::Url::parse("https://slack.com/oauth/v2/authorize")?;
+        url.query_pairs_mut()
+            .append_pair("client_id", &self.client_id)
+            .append_pair("user_scope", user_scopes)
+            .append_pair("redirect_uri", &self.redirect_uri)
+            .append_pair("state", state);
+
+        Ok(AuthRedirect {
+            url: url.to_string(),
+            flow: FlowState::new(),
+        })
+    }
+
+    async fn callback(&self, code: &str, _flow: &FlowState) -> Result<Identity, LoginError> {
+        // Request Slack for access token
+        let Some(json_body) = self
+            .slack
+            .oauth_access(&self.client_id, &self.client_secret, code, &self.redirect_uri)
+            .await
+            .map_err(LoginError::Provider)?
+        else {
+            return Err(LoginError::Provider(eyre!("oauth.v2.access did not return 200 OK.")));
+        };
+
+        if json_body["ok"].as_bool() != Some(true) {
+            tracing::warn!(
+                "Slack refused the OAuth code: {}",
+                json_body["error"].as_str().unwrap_or("unknown error")
+            );
+            return Err(LoginError::Denied);
+        }
+
+        let (Some(access_token), Some(user_id)) = (
+            json_body["authed_user"]["access_token"].as_str(),
+            json_body["authed_user"]["id"].as_str(),
+        ) else {
+            return Err(LoginError::Provider(eyre!(
+                "Malformed oauth.v2.access response from Slack."
+            )));
+        };
+
+        let team_id = json_body["team"]["id"].as_str();
+        self.allowlist
+            .check_team(team_id)
+            .map_err(|reason| LoginError::Forbidden(format!("user `{}`: {}", user_id, reason)))?;
+
+        let user = match self.tummy.get_user_info(user_id).await {
+            Ok(user) => user,
+            Err(RowNotFound) => {
+                return Err(LoginError::Forbidden(format!("user `{}` is not in tummy", user_id)));
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        allowed_identity(
+            user,
+            team_id.map(str::to_owned),
+            access_token.to_owned(),
+            &self.allowlist,
+        )
+    }
+
+    async fn revalidate(&self, access_token: &str) -> color_eyre::Result<bool> {
+        self.slack.auth_test(access_token).await
+    }
+
+    async fn revoke(&self, access_token: &str) -> color_eyre::Result<()> {
+        self.slack.auth_revoke(access_token).await
+    }
+}
@@ -0,0 +1,101 @@
+//! Roles of logged in users.
+//! Roles are stored in the `user_roles` table in tummy; users without a role
+//! are viewers. Users listed in `ADMIN_USERS` are always admins, so that a
+//! fresh deployment has someone who can grant roles.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::db::tummy::Tummy;
+use crate::env::EnvVars;
+
+/// What a user may do. Each role includes the ones before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Read the archive.
+    Viewer,
+    /// Curate the archive, e.g. hide messages.
+    Moderator,
+    /// Manage users, roles and the archive itself.
+    Admin,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Viewer, Role::Moderator, Role::Admin];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Role::ALL
+            .into_iter()
+            .find(|role| role.as_str() == s)
+            .ok_or_else(|| format!("Unknown role `{}`.", s))
+    }
+}
+
+/// Looks up users' roles.
+#[derive(Clone)]
+pub struct Roles {
+    tummy: Tummy,
+    bootstrap_admins: Arc<HashSet<String>>,
+}
+
+impl Roles {
+    pub fn from_env(env_vars: &EnvVars, tummy: Tummy) -> Self {
+        Self {
+            tummy,
+            bootstrap_admins: Arc::new(
+                env_vars
+                    .admin_users
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|user_id| !user_id.is_empty())
+                    .map(str::to_owned)
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Whether the user is an admin through `ADMIN_USERS`.
+    pub fn is_bootstrap_admin(&self, user_id: &str) -> bool {
+        self.bootstrap_admins.contains(user_id)
+    }
+
+    /// The IDs of the users listed in `ADMIN_USERS`.
+    pub fn bootstrap_admins(&self) -> impl Iterator<Item = &String> {
+        self.bootstrap_admins.iter()
+    }
+
+    /// Returns the role of a user.
+    pub async fn role_of(&self, user_id: &str) -> color_eyre::Result<Role> {
+        if self.is_bootstrap_admin(user_id) {
+            return Ok(Role::Admin);
+        }
+
+        match self.tummy.get_user_role(user_id).await? {
+            Some(role) => Ok(role.parse().map_err(color_eyre::eyre::Error::msg)?),
+            None => Ok(Role::Viewer),
+        }
+    }
+}
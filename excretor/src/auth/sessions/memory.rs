@@ -0,0 +1,51 @@
+//! In-memory session store. Sessions are lost when excretor restarts.
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+
+use super::{Session, SessionStore};
+
+#[derive(Default)]
+pub struct MemorySessionStore {
+    sessions: RwLock<HashMap<String, Session>>,
+}
+
+#[async_trait]
+impl SessionStore for MemorySessionStore {
+    async fn load(&self, id: &str) -> color_eyre::Result<Option<Session>> {
+        Ok(self.sessions.read().unwrap().get(id).cloned())
+    }
+
+    async fn save(&self, session: &Session) -> color_eyre::Result<()> {
+        self.sessions
+            .write()
+            .unwrap()
+            .insert(session.id.clone(), session.clone());
+        Ok(())
+    }
+
+    async fn mark_validated(&self, id: &str, validated_at: NaiveDateTime) -> color_eyre::Result<bool> {
+        match self.sessions.write().unwrap().get_mut(id) {
+            Some(session) => {
+                session.validated_at = validated_at;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn delete(&self, id: &str) -> color_eyre::Result<()> {
+        self.sessions.write().unwrap().remove(id);
+        Ok(())
+    }
+
+    async fn purge_expired(&self, now: NaiveDateTime) -> color_eyre::Result<u64> {
+        let mut sessions = self.sessions.write().unwrap();
+        let before = sessions.len();
+        sessions.retain(|_, session| session.expires_at > now);
+        Ok((before - sessions.len()) as u64)
+    }
+}
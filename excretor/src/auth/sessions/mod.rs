@@ -0,0 +1,218 @@
+//! Server-side login sessions.
+//! A session is created when a user logs in and is referenced by the `sid` claim
+//! of the signed session token. It holds the login provider's access token,
+//! encrypted with the session keys, so that the token never reaches the browser.
+//! Sessions are re-validated with the provider every `session_revalidate_secs`
+//! and expire together with the session token.
+
+mod memory;
+mod postgres;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::keys::SessionKeys;
+use crate::auth::providers::random_token;
+use crate::db::tummy::Tummy;
+use crate::env::{EnvVars, SessionBackend};
+
+pub use memory::MemorySessionStore;
+pub use postgres::PgSessionStore;
+
+/// Claims of the session token stored in the `token` cookie.
+#[derive(Serialize, Deserialize)]
+pub struct SessionClaims {
+    /// The ID of the logged in user.
+    pub sub: String,
+    /// The ID of the server-side session.
+    pub sid: String,
+    /// When the token was issued, as a Unix timestamp.
+    pub iat: i64,
+    /// When the token expires, as a Unix timestamp.
+    pub exp: i64,
+}
+
+/// A login session.
+#[derive(Clone, Debug)]
+pub struct Session {
+    /// The random session ID.
+    pub id: String,
+    /// The ID of the logged in user.
+    pub user_id: String,
+    /// The Slack team the user logged in with, if the provider has teams.
+    pub team_id: Option<String>,
+    /// The login provider's access token, encrypted with the session keys.
+    pub access_token: Option<String>,
+    /// When the user logged in.
+    pub created_at: NaiveDateTime,
+    /// When the session was last verified with the login provider.
+    pub validated_at: NaiveDateTime,
+    /// When the session expires.
+    pub expires_at: NaiveDateTime,
+}
+
+/// A backend that stores sessions.
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    /// Loads a session by ID, regardless of whether it has expired.
+    async fn load(&self, id: &str) -> color_eyre::Result<Option<Session>>;
+    /// Inserts or replaces a session.
+    async fn save(&self, session: &Session) -> color_eyre::Result<()>;
+    /// Updates when a session was last verified, without recreating it if it
+    /// was removed in the meantime. Returns whether the session still exists.
+    async fn mark_validated(&self, id: &str, validated_at: NaiveDateTime) -> color_eyre::Result<bool>;
+    /// Removes a session, if present.
+    async fn delete(&self, id: &str) -> color_eyre::Result<()>;
+    /// Removes all sessions that expired before `now`, returning how many were removed.
+    async fn purge_expired(&self, now: NaiveDateTime) -> color_eyre::Result<u64>;
+}
+
+/// What the store knows about a session token.
+pub enum CachedSession {
+    /// The session is valid and was verified recently enough.
+    Fresh(Session),
+    /// The session is valid but should be re-checked with the login provider.
+    Stale(Session),
+    /// The session has ended or never existed.
+    Missing,
+}
+
+/// Session store with lifetime and re-validation policy.
+#[derive(Clone)]
+pub struct Sessions {
+    store: Arc<dyn SessionStore>,
+    keys: SessionKeys,
+    lifetime: Duration,
+    revalidate_after: Duration,
+}
+
+impl Sessions {
+    pub fn new(
+        store: Arc<dyn SessionStore>,
+        keys: SessionKeys,
+        lifetime: Duration,
+        revalidate_after: Duration,
+    ) -> Self {
+        Self {
+            store,
+            keys,
+            lifetime,
+            revalidate_after,
+        }
+    }
+
+    /// Builds the sessions configured by the environment.
+    /// Sessions last `keep_logged_in_for_days`, or a day for browser-session cookies.
+    pub fn from_env(env_vars: &EnvVars, tummy: &Tummy, keys: SessionKeys) -> Self {
+        let store: Arc<dyn SessionStore> = match env_vars.session_backend {
+            SessionBackend::Memory => Arc::new(MemorySessionStore::default()),
+            SessionBackend::Postgres => Arc::new(PgSessionStore::new(tummy.clone())),
+        };
+
+        Self::new(
+            store,
+            keys,
+            Duration::days(env_vars.keep_logged_in_for_days.max(1)),
+            Duration::seconds(env_vars.session_revalidate_secs),
+        )
+    }
+
+    /// Starts a session for a user who just logged in and signs its session token.
+    ///
+    /// # Returns
+    /// The session token.
+    pub async fn create(
+        &self,
+        user_id: &str,
+        team_id: Option<&str>,
+        access_token: &str,
+    ) -> color_eyre::Result<String> {
+        let now = Utc::now().naive_utc();
+        let session = Session {
+            id: random_token(32),
+            user_id: user_id.to_owned(),
+            team_id: team_id.map(str::to_owned),
+            access_token: Some(self.keys.encrypt(access_token)?),
+            created_at: now,
+            validated_at: now,
+            expires_at: now + self.lifetime,
+        };
+        self.store.save(&session).await?;
+
+        self.keys.sign(&SessionClaims {
+            sub: session.user_id,
+            sid: session.id,
+            iat: now.and_utc().timestamp(),
+            exp: session.expires_at.and_utc().timestamp(),
+        })
+    }
+
+    /// Verifies a session token's signature and expiry.
+    ///
+    /// # Returns
+    /// The token's claims, or `None` if the token is not valid.
+    pub fn claims(&self, token: &str) -> Option<SessionClaims> {
+        match self.keys.verify(token) {
+            Ok(claims) => Some(claims),
+            Err(err) => {
+                tracing::debug!("Rejected session token: {}", err);
+                None
+            }
+        }
+    }
+
+    /// Looks up the session a token's claims refer to.
+    /// Expired sessions are removed from the store.
+    pub async fn lookup(&self, claims: &SessionClaims) -> color_eyre::Result<CachedSession> {
+        let now = Utc::now().naive_utc();
+
+        let session = match self.store.load(&claims.sid).await? {
+            Some(session) if session.user_id == claims.sub => session,
+            _ => return Ok(CachedSession::Missing),
+        };
+
+        if session.expires_at <= now {
+            self.store.delete(&session.id).await?;
+            return Ok(CachedSession::Missing);
+        }
+
+        if session.validated_at + self.revalidate_after <= now {
+            Ok(CachedSession::Stale(session))
+        } else {
+            Ok(CachedSession::Fresh(session))
+        }
+    }
+
+    /// Decrypts the login provider's access token of a session.
+    pub fn access_token(&self, session: &Session) -> color_eyre::Result<Option<String>> {
+        session
+            .access_token
+            .as_deref()
+            .map(|sealed| self.keys.decrypt(sealed))
+            .transpose()
+    }
+
+    /// Records that a session was just re-validated with the login provider.
+    ///
+    /// # Returns
+    /// Whether the session still exists, as it may have been revoked while it
+    /// was being re-validated.
+    pub async fn mark_validated(&self, session_id: &str) -> color_eyre::Result<bool> {
+        self.store
+            .mark_validated(session_id, Utc::now().naive_utc())
+            .await
+    }
+
+    /// Ends a session.
+    pub async fn revoke(&self, session_id: &str) -> color_eyre::Result<()> {
+        self.store.delete(session_id).await
+    }
+
+    /// Removes expired sessions from the store.
+    pub async fn purge_expired(&self) -> color_eyre::Result<u64> {
+        self.store.purge_expired(Utc::now().naive_utc()).await
+    }
+}
@@ -0,0 +1,69 @@
+//! Session store backed by the `sessions` table in tummy.
+//! Lets several excretor instances share sessions and survives restarts.
+
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+
+use super::{Session, SessionStore};
+use crate::db::dbmodels::DBSession;
+use crate::db::tummy::Tummy;
+
+pub struct PgSessionStore {
+    tummy: Tummy,
+}
+
+impl PgSessionStore {
+    pub fn new(tummy: Tummy) -> Self {
+        Self { tummy }
+    }
+}
+
+#[async_trait]
+impl SessionStore for PgSessionStore {
+    async fn load(&self, id: &str) -> color_eyre::Result<Option<Session>> {
+        Ok(self.tummy.get_session(id).await?.map(Session::from))
+    }
+
+    async fn save(&self, session: &Session) -> color_eyre::Result<()> {
+        self.tummy
+            .upsert_session(
+                &session.id,
+                &session.user_id,
+                session.team_id.as_deref(),
+                session.access_token.as_deref(),
+                session.created_at,
+                session.validated_at,
+                session.expires_at,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn mark_validated(&self, id: &str, validated_at: NaiveDateTime) -> color_eyre::Result<bool> {
+        Ok(self.tummy.set_session_validated_at(id, validated_at).await?)
+    }
+
+    async fn delete(&self, id: &str) -> color_eyre::Result<()> {
+        self.tummy.delete_session(id).await?;
+        Ok(())
+    }
+
+    async fn purge_expired(&self, now: NaiveDateTime) -> color_eyre::Result<u64> {
+        Ok(self.tummy.delete_expired_sessions(now).await?)
+    }
+}
+
+/// Converts a `DBSession` database model into a `Session`.
+impl From<DBSession> for Session {
+    fn from(value: DBSession) -> Self {
+        Session {
+            id: value.id,
+            user_id: value.user_id,
+            team_id: value.team_id,
+            access_token: value.access_token,
+            created_at: value.created_at,
+            validated_at: value.validated_at,
+            expires_at: value.expires_at,
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! A thin client for the parts of the Slack Web API used during authentication.
+//! The base URL is configurable so that a local stub can stand in for Slack.
+
+use color_eyre::eyre::eyre;
+use reqwest::{Client, StatusCode};
+
+use crate::env::EnvVars;
+
+#[derive(Clone)]
+pub struct SlackClient {
+    http: Client,
+    api_base_url: String,
+}
+
+impl SlackClient {
+    pub fn new(env_vars: &EnvVars) -> Self {
+        Self {
+            http: Client::new(),
+            api_base_url: env_vars.slack_api_base_url.clone(),
+        }
+    }
+
+    fn url(&self, method: &str) -> String {
+        format!("{}/{}", self.api_base_url, method)
+    }
+
+    /// Calls `auth.test` with the given user token.
+    ///
+    /// # Returns
+    /// `Ok(true)` if Slack still accepts the token, `Ok(false)` if it was rejected.
+    /// Network and decoding failures are returned as errors so that callers can
+    /// tell "Slack said no" apart from "Slack did not answer".
+    pub async fn auth_test(&self, access_token: &str) -> color_eyre::Result<bool> {
+        let response = self
+            .http
+            .get(self.url("auth.test"))
+            .bearer_auth(access_token)
+            .send()
+            .await?;
+
+        if response.status() != StatusCode::OK {
+            return Ok(false);
+        }
+
+        let json_body: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+        match json_body["ok"].as_bool() {
+            Some(ok) => Ok(ok),
+            None => Err(eyre!("Malformed auth.test response from Slack.")),
+        }
+    }
+
+    /// Exchanges an OAuth `code` for a user token using `oauth.v2.access`.
+    ///
+    /// # Returns
+    /// The raw JSON response, or `None` if Slack answered with a non-200 status.
+    pub async fn oauth_access(
+        &self,
+        client_id: &str,
+        client_secret: &str,
+        code: &str,
+        redirect_uri: &str,
+    ) -> color_eyre::Result<Option<serde_json::Value>> {
+        let response = self
+            .http
+            .get(self.url("oauth.v2.access"))
+            .query(&[
+                ("client_id", client_id),
+                ("client_secret", client_secret),
+                ("code", code),
+                ("redirect_uri", redirect_uri),
+            ])
+            .send()
+            .await?;
+
+        if response.status() != StatusCode::OK {
+            return Ok(None);
+        }
+
+        let body = response.text().await?;
+        Ok(Some(serde_json::from_str(&body)?))
+    }
+
+    /// Revokes a user token with `auth.revoke`.
+    pub async fn auth_revoke(&self, access_token: &str) -> color_eyre::Result<()> {
+        let response = self
+            .http
+            .get(self.url("auth.revoke"))
+            .bearer_auth(access_token)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let json_body: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+        if json_body["ok"].as_bool() != Some(true) {
+            return Err(eyre!(
+                "auth.revoke failed: {}",
+                json_body["error"].as_str().unwrap_or("unknown error")
+            ));
+        }
+        Ok(())
+    }
+}
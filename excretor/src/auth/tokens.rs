@@ -0,0 +1,85 @@
+//! Personal API tokens for scripts and bots.
+//! Tokens are sent as `Authorization: Bearer <token>` and only their SHA-256
+//! digest is stored in tummy.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::providers::random_token;
+
+/// Prefix of every personal API token, so that leaked tokens are easy to spot.
+const TOKEN_PREFIX: &str = "opsa_";
+/// Number of characters of a token kept in plaintext to tell tokens apart.
+const DISPLAY_PREFIX_LEN: usize = 12;
+
+/// What an API token is allowed to do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Read channels, users, messages and threads.
+    Read,
+    /// Run searches.
+    Search,
+    /// Export data in bulk.
+    Export,
+    /// Use administrative endpoints, including managing tokens.
+    Admin,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 4] = [Scope::Read, Scope::Search, Scope::Export, Scope::Admin];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Search => "search",
+            Scope::Export => "export",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Scope::ALL
+            .into_iter()
+            .find(|scope| scope.as_str() == s)
+            .ok_or_else(|| format!("Unknown scope `{}`.", s))
+    }
+}
+
+/// A freshly generated token.
+pub struct NewToken {
+    /// The token to hand to the user. It is never stored.
+    pub token: String,
+    /// The digest stored in tummy.
+    pub hash: String,
+    /// The leading characters of the token, stored for display.
+    pub display_prefix: String,
+}
+
+/// Generates a new random API token.
+pub fn generate() -> NewToken {
+    let token = format!("{}{}", TOKEN_PREFIX, random_token(32));
+    NewToken {
+        hash: hash(&token),
+        display_prefix: token[..DISPLAY_PREFIX_LEN].to_owned(),
+        token,
+    }
+}
+
+/// Computes the digest under which a token is stored.
+pub fn hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
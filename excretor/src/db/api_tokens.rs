@@ -0,0 +1,100 @@
+//! Queries for the `api_tokens` table.
+
+use super::dbmodels::DBApiToken;
+use super::tummy::Tummy;
+use sqlx::{query, query_as, types::chrono::NaiveDateTime};
+
+impl Tummy {
+    #[allow(clippy::too_many_arguments)]
+    pub async fn insert_api_token(
+        &self,
+        user_id: &str,
+        team_id: Option<&str>,
+        name: &str,
+        token_hash: &str,
+        token_prefix: &str,
+        scopes: &[String],
+        created_at: NaiveDateTime,
+        expires_at: Option<NaiveDateTime>,
+    ) -> Result<DBApiToken, sqlx::Error> {
+        query_as!(
+            DBApiToken,
+            r#"
+            INSERT INTO api_tokens (user_id, team_id, name, token_hash, token_prefix, scopes, created_at, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING id, user_id, team_id, name, token_prefix, scopes, created_at, expires_at, last_used_at, revoked_at
+            "#,
+            user_id,
+            team_id,
+            name,
+            token_hash,
+            token_prefix,
+            scopes,
+            created_at,
+            expires_at
+        )
+            .fetch_one(&self.tummy_conn_pool)
+            .await
+    }
+
+    pub async fn get_api_tokens(&self, user_id: &str) -> Result<Vec<DBApiToken>, sqlx::Error> {
+        query_as!(
+            DBApiToken,
+            r#"
+            SELECT id, user_id, team_id, name, token_prefix, scopes, created_at, expires_at, last_used_at, revoked_at
+            FROM api_tokens
+            WHERE user_id = $1
+            ORDER BY created_at DESC
+            "#,
+            user_id
+        )
+            .fetch_all(&self.tummy_conn_pool)
+            .await
+    }
+
+    /// Revokes one of a user's tokens.
+    ///
+    /// # Returns
+    /// Whether a token was revoked.
+    pub async fn revoke_api_token(
+        &self,
+        token_id: i64,
+        user_id: &str,
+        now: NaiveDateTime,
+    ) -> Result<bool, sqlx::Error> {
+        let result = query!(
+            "UPDATE api_tokens SET revoked_at = $3 WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL",
+            token_id,
+            user_id,
+            now
+        )
+            .execute(&self.tummy_conn_pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Looks up a usable token by its digest and records that it was used.
+    /// Revoked and expired tokens are not returned.
+    pub async fn use_api_token(
+        &self,
+        token_hash: &str,
+        now: NaiveDateTime,
+    ) -> Result<Option<DBApiToken>, sqlx::Error> {
+        query_as!(
+            DBApiToken,
+            r#"
+            UPDATE api_tokens
+            SET last_used_at = $2
+            WHERE
+                token_hash = $1
+                AND revoked_at IS NULL
+                AND (expires_at IS NULL OR expires_at > $2)
+            RETURNING id, user_id, team_id, name, token_prefix, scopes, created_at, expires_at, last_used_at, revoked_at
+            "#,
+            token_hash,
+            now
+        )
+            .fetch_optional(&self.tummy_conn_pool)
+            .await
+    }
+}
@@ -0,0 +1,83 @@
+//! Queries for the append-only `audit_log` table.
+
+use super::dbmodels::DBAuditEntry;
+use super::tummy::Tummy;
+use sqlx::{query, query_as, types::chrono::NaiveDateTime, types::JsonValue};
+
+impl Tummy {
+    pub async fn insert_audit_entry(
+        &self,
+        occurred_at: NaiveDateTime,
+        user_id: Option<&str>,
+        token_id: Option<i64>,
+        ip: Option<&str>,
+        action: &str,
+        params: &JsonValue,
+    ) -> Result<(), sqlx::Error> {
+        query!(
+            r#"
+            INSERT INTO audit_log (occurred_at, user_id, token_id, ip, action, params)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            occurred_at,
+            user_id,
+            token_id,
+            ip,
+            action,
+            params
+        )
+            .execute(&self.tummy_conn_pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Fetches audit entries matching the filters, newest first.
+    /// Only entries with an ID below `before_id` are returned, for pagination.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn get_audit_entries(
+        &self,
+        user_id: Option<&str>,
+        action: Option<&str>,
+        since: Option<NaiveDateTime>,
+        until: Option<NaiveDateTime>,
+        before_id: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<DBAuditEntry>, sqlx::Error> {
+        query_as!(
+            DBAuditEntry,
+            r#"
+            SELECT id, occurred_at, user_id, token_id, ip, action, params
+            FROM audit_log
+            WHERE ($1::TEXT IS NULL OR user_id = $1)
+                AND ($2::TEXT IS NULL OR action = $2)
+                AND ($3::TIMESTAMP IS NULL OR occurred_at >= $3)
+                AND ($4::TIMESTAMP IS NULL OR occurred_at < $4)
+                AND ($5::BIGINT IS NULL OR id < $5)
+            ORDER BY id DESC
+            LIMIT $6
+            "#,
+            user_id,
+            action,
+            since,
+            until,
+            before_id,
+            limit
+        )
+            .fetch_all(&self.tummy_conn_pool)
+            .await
+    }
+
+    /// Deletes audit entries older than `cutoff`, returning how many were removed.
+    /// This is the only way entries are ever removed from the log.
+    pub async fn delete_audit_entries_before(&self, cutoff: NaiveDateTime) -> Result<u64, sqlx::Error> {
+        let mut tx = self.tummy_conn_pool.begin().await?;
+        query!("SELECT set_config('opsa.audit_purge', 'on', true)")
+            .fetch_one(&mut *tx)
+            .await?;
+        let result = query!("DELETE FROM audit_log WHERE occurred_at < $1", cutoff)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(result.rows_affected())
+    }
+}
@@ -15,6 +15,10 @@ pub struct DBChannel {
     pub topic: Option<String>,
     /// The channel purpose, if set.
     pub purpose: Option<String>,
+    /// Whether the channel is private (or a DM) and only visible to its members.
+    pub is_private: bool,
+    /// The text search configuration of the channel's messages, if not the default.
+    pub search_config: Option<String>,
 }
 
 /// Represents a user record in the database.
@@ -139,4 +143,208 @@ pub struct DBSearchResult {
     pub parent_email: Option<String>,
     pub parent_deleted: Option<bool>,
     pub parent_is_bot: Option<bool>,
+
+    // `ts_headline` of the message text, marked with the highlight sentinels
+    pub headline: Option<String>,
+
+    // ranks in the fused candidate sets, for relevance sorted searches
+    pub fuzzy_rank: Option<i64>,
+    pub full_text_rank: Option<i64>,
+    pub prefix_rank: Option<i64>,
+    pub semantic_rank: Option<i64>,
+}
+
+/// Represents a reply matching a thread search.
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct DBReplyExcerpt {
+    pub channel_id: String,
+    pub thread_ts: chrono::NaiveDateTime,
+    pub ts: chrono::NaiveDateTime,
+    pub user_id: String,
+    pub user_name: String,
+    // `ts_headline` of the reply text, marked with the highlight sentinels
+    pub headline: Option<String>,
+}
+
+/// Represents an autocomplete suggestion.
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct DBSuggestion {
+    /// `user`, `channel` or `term`.
+    pub kind: String,
+    pub value: String,
+    pub label: String,
+    pub detail: Option<String>,
+    pub score: f64,
+}
+
+/// Represents the number of search hits for one value of a facet.
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct DBSearchFacet {
+    pub facet: String,
+    pub value: String,
+    pub label: Option<String>,
+    pub count: i64,
+}
+
+/// Represents a cached login session in the database.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DBSession {
+    /// The session ID.
+    pub id: String,
+    /// The ID of the logged in user.
+    pub user_id: String,
+    /// The Slack team the user logged in with, if the provider has teams.
+    pub team_id: Option<String>,
+    /// The login provider's access token, encrypted.
+    pub access_token: Option<String>,
+    /// When the session was first verified.
+    pub created_at: chrono::NaiveDateTime,
+    /// When the session was last verified with the login provider.
+    pub validated_at: chrono::NaiveDateTime,
+    /// When the session expires.
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+/// Represents a personal API token in the database.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DBApiToken {
+    /// The unique token ID.
+    pub id: i64,
+    /// The ID of the user who owns the token.
+    pub user_id: String,
+    /// The Slack team of the session the token was created from, if known.
+    pub team_id: Option<String>,
+    /// A name chosen by the user.
+    pub name: String,
+    /// The leading characters of the token.
+    pub token_prefix: String,
+    /// The scopes granted to the token.
+    pub scopes: Vec<String>,
+    /// When the token was created.
+    pub created_at: chrono::NaiveDateTime,
+    /// When the token expires, if ever.
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    /// When the token was last used, if ever.
+    pub last_used_at: Option<chrono::NaiveDateTime>,
+    /// When the token was revoked, if it was.
+    pub revoked_at: Option<chrono::NaiveDateTime>,
+}
+
+/// Represents a role granted to a user in the database.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DBUserRole {
+    /// The ID of the user.
+    pub user_id: String,
+    /// The granted role.
+    pub role: String,
+    /// The ID of the admin who granted the role, if they still exist.
+    pub granted_by: Option<String>,
+    /// When the role was granted.
+    pub granted_at: chrono::NaiveDateTime,
+}
+
+/// Represents an audit log entry in the database.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DBAuditEntry {
+    /// The unique entry ID.
+    pub id: i64,
+    /// When the access happened.
+    pub occurred_at: chrono::NaiveDateTime,
+    /// The ID of the user, if login is enabled.
+    pub user_id: Option<String>,
+    /// The API token used, if any.
+    pub token_id: Option<i64>,
+    /// The client's IP address, if known.
+    pub ip: Option<String>,
+    /// What was accessed.
+    pub action: String,
+    /// The parameters of the access, e.g. the channel or search query.
+    pub params: serde_json::Value,
+}
+
+/// Represents a possible correction of a misspelled search term.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DBSpellingCorrection {
+    /// The term as typed.
+    pub term: String,
+    /// A similar word from the archive.
+    pub word: String,
+}
+
+/// Represents a message whose embedding is to be computed.
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct DBEmbeddingSource {
+    pub channel_id: String,
+    pub user_id: String,
+    pub ts: chrono::NaiveDateTime,
+    /// The message text without HTML tags.
+    pub text: String,
+}
+
+/// Represents a saved search in the database.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DBSavedSearch {
+    /// The unique saved search ID.
+    pub id: i64,
+    /// The ID of the user who saved the search.
+    pub user_id: String,
+    /// A name chosen by the user.
+    pub name: String,
+    /// The query in the search query language.
+    pub query: String,
+    /// Whether new messages matching the query are added to the user's alert feed.
+    pub alert: bool,
+    /// Where new matches are POSTed, if anywhere.
+    pub webhook_url: Option<String>,
+    /// When the search was saved.
+    pub created_at: chrono::NaiveDateTime,
+    /// When the search was last changed.
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+/// Represents a new message matching a search alert, with the message and its sender.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DBSearchAlertMatch {
+    /// The unique match ID.
+    pub match_id: i64,
+    /// The ID of the saved search that matched.
+    pub saved_search_id: i64,
+    /// The name of the saved search that matched.
+    pub saved_search_name: String,
+    /// Fragments of the message text with the matches marked.
+    pub highlights: Vec<String>,
+    /// When the match was found.
+    pub matched_at: chrono::NaiveDateTime,
+
+    pub channel_id: String,
+    pub channel_name: String,
+    pub user_id: String,
+    pub msg_text: String,
+    pub ts: chrono::NaiveDateTime,
+    pub thread_ts: Option<chrono::NaiveDateTime>,
+    pub parent_user_id: Option<String>,
+
+    pub id: String,
+    pub name: String,
+    pub real_name: String,
+    pub display_name: String,
+    pub image_url: Option<String>,
+    pub email: String,
+    pub deleted: bool,
+    pub is_bot: bool,
+}
+
+/// Represents statistics of the searches for one query over a period.
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct DBSearchQueryStats {
+    /// The normalized query.
+    pub query: String,
+    pub searches: i64,
+    /// Distinct pseudonyms of the users who searched.
+    pub users: i64,
+    pub zero_result_searches: i64,
+    pub avg_hits: f64,
+    pub avg_latency_ms: f64,
+    pub max_latency_ms: f64,
+    pub last_searched_at: chrono::NaiveDateTime,
 }
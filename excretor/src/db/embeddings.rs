@@ -0,0 +1,90 @@
+//! Queries for the `message_embeddings` table, which only exists if tummy has
+//! pgvector. They are checked at runtime rather than at compile time for that
+//! reason.
+
+use super::dbmodels::DBEmbeddingSource;
+use super::tummy::Tummy;
+use sqlx::types::chrono::NaiveDateTime;
+use sqlx::{query, query_as, query_scalar};
+
+/// Most characters of a message that are embedded.
+const MAX_EMBEDDED_CHARS: i32 = 4000;
+
+impl Tummy {
+    /// Whether tummy can store embeddings.
+    pub async fn embeddings_available(&self) -> Result<bool, sqlx::Error> {
+        query_scalar::<_, bool>("SELECT to_regclass('message_embeddings') IS NOT NULL")
+            .fetch_one(&self.tummy_conn_pool)
+            .await
+    }
+
+    /// Sets up the embeddings table for `model`. Returns `true` if this is a
+    /// different model than before, whose embeddings were dropped.
+    pub async fn use_embedding_model(&self, model: &str, dimensions: i32) -> Result<bool, sqlx::Error> {
+        query_scalar::<_, bool>("SELECT use_embedding_model($1, $2)")
+            .bind(model)
+            .bind(dimensions)
+            .fetch_one(&self.tummy_conn_pool)
+            .await
+    }
+
+    /// Fetches up to `limit` messages with text but without an embedding, newest
+    /// first so that recent messages become searchable first. Only messages
+    /// sent at or before `until` are fetched, to continue from a previous batch.
+    pub async fn messages_without_embeddings(
+        &self,
+        until: Option<NaiveDateTime>,
+        limit: i64,
+    ) -> Result<Vec<DBEmbeddingSource>, sqlx::Error> {
+        query_as::<_, DBEmbeddingSource>(
+            r#"
+            SELECT m.channel_id, m.user_id, m.ts, plain.text
+            FROM messages m
+            CROSS JOIN LATERAL (
+                SELECT left(btrim(regexp_replace(m.msg_text, '<[^>]*>', ' ', 'g')), $1) AS text
+            ) AS plain
+            WHERE ($2::TIMESTAMP IS NULL OR m.ts <= $2)
+                AND plain.text <> ''
+                AND NOT EXISTS (
+                    SELECT 1 FROM message_embeddings e
+                    WHERE e.channel_id = m.channel_id AND e.user_id = m.user_id AND e.ts = m.ts
+                )
+            ORDER BY m.ts DESC
+            LIMIT $3
+            "#,
+        )
+            .bind(MAX_EMBEDDED_CHARS)
+            .bind(until)
+            .bind(limit)
+            .fetch_all(&self.tummy_conn_pool)
+            .await
+    }
+
+    /// Stores the embeddings of `messages`, given as pgvector literals in the
+    /// same order.
+    pub async fn insert_embeddings(
+        &self,
+        messages: &[DBEmbeddingSource],
+        embeddings: Vec<String>,
+    ) -> Result<(), sqlx::Error> {
+        let channel_ids: Vec<&str> = messages.iter().map(|message| message.channel_id.as_str()).collect();
+        let user_ids: Vec<&str> = messages.iter().map(|message| message.user_id.as_str()).collect();
+        let tss: Vec<NaiveDateTime> = messages.iter().map(|message| message.ts).collect();
+
+        query(
+            r#"
+            INSERT INTO message_embeddings (channel_id, user_id, ts, embedding)
+            SELECT channel_id, user_id, ts, embedding::vector
+            FROM UNNEST($1::TEXT[], $2::TEXT[], $3::TIMESTAMP[], $4::TEXT[]) AS t(channel_id, user_id, ts, embedding)
+            ON CONFLICT (channel_id, user_id, ts) DO UPDATE SET embedding = EXCLUDED.embedding
+            "#,
+        )
+            .bind(channel_ids)
+            .bind(user_ids)
+            .bind(tss)
+            .bind(embeddings)
+            .execute(&self.tummy_conn_pool)
+            .await?;
+        Ok(())
+    }
+}
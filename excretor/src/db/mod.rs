@@ -1,2 +1,14 @@
+pub(crate) mod api_tokens;
+pub(crate) mod audit;
 pub(crate) mod dbmodels;
-pub(crate) mod tummy;
\ No newline at end of file
+pub(crate) mod embeddings;
+pub(crate) mod roles;
+pub(crate) mod related;
+pub(crate) mod saved_searches;
+pub(crate) mod search;
+pub(crate) mod search_analytics;
+pub(crate) mod search_configs;
+pub(crate) mod sessions;
+pub(crate) mod suggest;
+pub(crate) mod tummy;
+pub(crate) mod vocabulary;
@@ -0,0 +1,204 @@
+//! Messages related to a given one ("more like this").
+//! Other messages are ranked by trigram similarity to the source message's text
+//! and by how many of its lexemes they share, and the two rankings are combined
+//! with Reciprocal Rank Fusion like search. Only the best message of each
+//! thread is returned, and the source's own thread is left out.
+
+use super::dbmodels::DBSearchResult;
+use super::search::{push_message_filters, push_plain_text, push_result_columns, push_result_joins};
+use super::tummy::{push_visible_channels, Tummy};
+use crate::search::{Highlighter, ParsedQuery, Ranking};
+use crate::types::SearchResult;
+use sqlx::types::chrono::NaiveDateTime;
+use sqlx::{Postgres, QueryBuilder};
+
+/// Most candidates taken from each ranking before fusion.
+const MAX_RELATED_CANDIDATES: i64 = 200;
+
+/// Pushes a ranked candidate set named `name` of the messages matching
+/// `condition` against the `source` CTE, ordered by `order`.
+fn push_related_candidates<'a>(
+    builder: &mut QueryBuilder<'a, Postgres>,
+    name: &str,
+    condition: &str,
+    order: &str,
+    filters: &'a ParsedQuery,
+    viewer: Option<&'a str>,
+) {
+    builder.push(format!(
+        r#"
+    {name} AS (
+        SELECT
+            m.channel_id,
+            m.user_id,
+            m.ts,
+            row_number() OVER (ORDER BY {order} DESC) AS rank_ix
+        FROM messages m
+        CROSS JOIN source s
+        WHERE {condition}
+            AND NOT (m.channel_id = s.channel_id AND COALESCE(m.thread_ts, m.ts) = s.thread_key)"#,
+    ));
+    push_message_filters(builder, "m.", filters, viewer);
+    builder.push(
+        r#"
+        ORDER BY rank_ix
+        LIMIT "#,
+    );
+    builder.push_bind(MAX_RELATED_CANDIDATES);
+    builder.push(
+        r#"
+    ),"#,
+    );
+}
+
+impl Tummy {
+    /// Finds the messages `viewer` may see that are most related to the message
+    /// sent at `ts` in `channel_id`, restricted by the channel and time filters
+    /// of `filters`. Returns `None` if there is no such message or `viewer` may
+    /// not see it.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn related_messages(
+        &self,
+        viewer: Option<&str>,
+        channel_id: &str,
+        ts: NaiveDateTime,
+        filters: &ParsedQuery,
+        ranking: &Ranking,
+        limit: i64,
+        highlighter: &Highlighter,
+    ) -> color_eyre::Result<Option<Vec<SearchResult>>> {
+        let mut builder: QueryBuilder<Postgres> =
+            QueryBuilder::new("SELECT EXISTS (SELECT 1 FROM messages WHERE channel_id = ");
+        builder.push_bind(channel_id);
+        builder.push(" AND ts = ");
+        builder.push_bind(ts);
+        push_visible_channels(&mut builder, "channel_id", viewer);
+        builder.push(")");
+        let visible = builder
+            .build_query_scalar::<bool>()
+            .fetch_one(&self.tummy_conn_pool)
+            .await?;
+        if !visible {
+            return Ok(None);
+        }
+
+        // The source's lexemes are OR-ed into a query, so that messages sharing
+        // more of them rank higher.
+        let mut builder: QueryBuilder<Postgres> = QueryBuilder::new(
+            r#"
+    WITH source AS (
+        SELECT
+            channel_id,
+            COALESCE(thread_ts, ts) AS thread_key,
+            msg_text,
+            (
+                SELECT string_agg(quote_literal(lexeme), ' | ')
+                FROM unnest(COALESCE(msg_tsv, to_tsvector(channel_search_config(channel_id), msg_text)))
+            )::tsquery AS q
+        FROM messages
+        WHERE channel_id = "#,
+        );
+        builder.push_bind(channel_id);
+        builder.push(" AND ts = ");
+        builder.push_bind(ts);
+        builder.push(
+            r#"
+        LIMIT 1
+    ),"#,
+        );
+        push_related_candidates(
+            &mut builder,
+            "fuzzy",
+            "m.msg_text % s.msg_text",
+            "similarity(m.msg_text, s.msg_text)",
+            filters,
+            viewer,
+        );
+        push_related_candidates(
+            &mut builder,
+            "full_text",
+            "m.msg_tsv @@ s.q",
+            "ts_rank_cd(m.msg_tsv, s.q)",
+            filters,
+            viewer,
+        );
+        builder.push(
+            r#"
+    fused AS (
+        SELECT
+            COALESCE(fuzzy.channel_id, full_text.channel_id) AS channel_id,
+            COALESCE(fuzzy.user_id, full_text.user_id) AS user_id,
+            COALESCE(fuzzy.ts, full_text.ts) AS ts,
+            fuzzy.rank_ix AS fuzzy_rank,
+            full_text.rank_ix AS full_text_rank,
+            COALESCE("#,
+        );
+        builder.push_bind(ranking.fuzzy);
+        builder.push(" / (");
+        builder.push_bind(ranking.k);
+        builder.push(" + fuzzy.rank_ix), 0.0) + COALESCE(");
+        builder.push_bind(ranking.full_text);
+        builder.push(" / (");
+        builder.push_bind(ranking.k);
+        builder.push(
+            r#" + full_text.rank_ix), 0.0) AS score
+        FROM fuzzy
+        FULL OUTER JOIN full_text
+            ON fuzzy.channel_id = full_text.channel_id AND fuzzy.user_id = full_text.user_id AND fuzzy.ts = full_text.ts
+    ),
+    -- The best message of each thread
+    page AS (
+        SELECT * FROM (
+            SELECT DISTINCT ON (fused.channel_id, COALESCE(tm.thread_ts, tm.ts)) fused.*
+            FROM fused
+            INNER JOIN messages tm
+                ON tm.channel_id = fused.channel_id AND tm.user_id = fused.user_id AND tm.ts = fused.ts
+            ORDER BY fused.channel_id, COALESCE(tm.thread_ts, tm.ts), fused.score DESC, fused.ts DESC
+        ) AS best
+        ORDER BY score DESC, ts DESC
+        LIMIT "#,
+        );
+        builder.push_bind(limit);
+        builder.push(
+            r#"
+    ),
+    highlight_query AS (
+        SELECT q FROM source
+    )"#,
+        );
+
+        push_result_columns(&mut builder);
+        builder.push(
+            r#"
+        ts_headline(plain.config, plain.msg_text, hq.q, "#,
+        );
+        builder.push_bind(Highlighter::headline_options());
+        builder.push(
+            r#") AS headline,
+        page.fuzzy_rank,
+        page.full_text_rank,
+        NULL::bigint AS prefix_rank,
+        NULL::bigint AS semantic_rank
+    FROM page
+        JOIN messages m ON m.channel_id = page.channel_id AND m.user_id = page.user_id AND m.ts = page.ts"#,
+        );
+        push_result_joins(&mut builder);
+        push_plain_text(&mut builder, "m");
+        builder.push(" ORDER BY page.score DESC, page.ts DESC");
+
+        let messages = builder
+            .build_query_as::<DBSearchResult>()
+            .fetch_all(&self.tummy_conn_pool)
+            .await?;
+
+        Ok(Some(
+            messages
+                .into_iter()
+                .map(|message| SearchResult {
+                    highlights: highlighter.fragments(message.headline.as_deref()),
+                    ..SearchResult::from(message)
+                })
+                .collect(),
+        ))
+    }
+}
@@ -0,0 +1,55 @@
+//! Queries for the `user_roles` table.
+
+use super::dbmodels::DBUserRole;
+use super::tummy::Tummy;
+use sqlx::{query, query_as, query_scalar, types::chrono::NaiveDateTime};
+
+impl Tummy {
+    pub async fn get_user_role(&self, user_id: &str) -> Result<Option<String>, sqlx::Error> {
+        query_scalar!("SELECT role FROM user_roles WHERE user_id = $1", user_id)
+            .fetch_optional(&self.tummy_conn_pool)
+            .await
+    }
+
+    pub async fn get_user_roles(&self) -> Result<Vec<DBUserRole>, sqlx::Error> {
+        query_as!(
+            DBUserRole,
+            "SELECT user_id, role, granted_by, granted_at FROM user_roles ORDER BY user_id"
+        )
+            .fetch_all(&self.tummy_conn_pool)
+            .await
+    }
+
+    pub async fn upsert_user_role(
+        &self,
+        user_id: &str,
+        role: &str,
+        granted_by: &str,
+        granted_at: NaiveDateTime,
+    ) -> Result<(), sqlx::Error> {
+        query!(
+            r#"
+            INSERT INTO user_roles (user_id, role, granted_by, granted_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id) DO UPDATE SET
+                role = EXCLUDED.role,
+                granted_by = EXCLUDED.granted_by,
+                granted_at = EXCLUDED.granted_at
+            "#,
+            user_id,
+            role,
+            granted_by,
+            granted_at
+        )
+            .execute(&self.tummy_conn_pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_user_role(&self, user_id: &str) -> Result<(), sqlx::Error> {
+        query!("DELETE FROM user_roles WHERE user_id = $1", user_id)
+            .execute(&self.tummy_conn_pool)
+            .await?;
+        Ok(())
+    }
+}
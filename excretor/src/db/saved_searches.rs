@@ -0,0 +1,347 @@
+//! Queries for saved searches and the search alerts they raise.
+
+use super::dbmodels::{DBSavedSearch, DBSearchAlertMatch};
+use super::tummy::Tummy;
+use crate::types::SearchResult;
+use sqlx::{query, query_as, query_scalar, types::chrono::NaiveDateTime};
+
+impl Tummy {
+    #[allow(clippy::too_many_arguments)]
+    pub async fn insert_saved_search(
+        &self,
+        user_id: &str,
+        name: &str,
+        search_query: &str,
+        alert: bool,
+        webhook_url: Option<&str>,
+        now: NaiveDateTime,
+    ) -> Result<DBSavedSearch, sqlx::Error> {
+        query_as!(
+            DBSavedSearch,
+            r#"
+            INSERT INTO saved_searches (user_id, name, query, alert, webhook_url, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $6)
+            RETURNING id, user_id, name, query, alert, webhook_url, created_at, updated_at
+            "#,
+            user_id,
+            name,
+            search_query,
+            alert,
+            webhook_url,
+            now
+        )
+            .fetch_one(&self.tummy_conn_pool)
+            .await
+    }
+
+    pub async fn get_saved_searches(&self, user_id: &str) -> Result<Vec<DBSavedSearch>, sqlx::Error> {
+        query_as!(
+            DBSavedSearch,
+            r#"
+            SELECT id, user_id, name, query, alert, webhook_url, created_at, updated_at
+            FROM saved_searches
+            WHERE user_id = $1
+            ORDER BY name ASC, id ASC
+            "#,
+            user_id
+        )
+            .fetch_all(&self.tummy_conn_pool)
+            .await
+    }
+
+    pub async fn count_saved_searches(&self, user_id: &str) -> Result<i64, sqlx::Error> {
+        query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM saved_searches WHERE user_id = $1"#,
+            user_id
+        )
+            .fetch_one(&self.tummy_conn_pool)
+            .await
+    }
+
+    /// Changes one of a user's saved searches.
+    ///
+    /// # Returns
+    /// The changed saved search, or `None` if the user has no such saved search.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn update_saved_search(
+        &self,
+        saved_search_id: i64,
+        user_id: &str,
+        name: &str,
+        search_query: &str,
+        alert: bool,
+        webhook_url: Option<&str>,
+        now: NaiveDateTime,
+    ) -> Result<Option<DBSavedSearch>, sqlx::Error> {
+        query_as!(
+            DBSavedSearch,
+            r#"
+            UPDATE saved_searches
+            SET name = $3, query = $4, alert = $5, webhook_url = $6, updated_at = $7
+            WHERE id = $1 AND user_id = $2
+            RETURNING id, user_id, name, query, alert, webhook_url, created_at, updated_at
+            "#,
+            saved_search_id,
+            user_id,
+            name,
+            search_query,
+            alert,
+            webhook_url,
+            now
+        )
+            .fetch_optional(&self.tummy_conn_pool)
+            .await
+    }
+
+    /// Deletes one of a user's saved searches, along with its alerts.
+    ///
+    /// # Returns
+    /// Whether a saved search was deleted.
+    pub async fn delete_saved_search(&self, saved_search_id: i64, user_id: &str) -> Result<bool, sqlx::Error> {
+        let result = query!(
+            "DELETE FROM saved_searches WHERE id = $1 AND user_id = $2",
+            saved_search_id,
+            user_id
+        )
+            .execute(&self.tummy_conn_pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Fetches the saved searches that are alerts, except those of deleted users.
+    pub async fn get_alert_saved_searches(&self) -> Result<Vec<DBSavedSearch>, sqlx::Error> {
+        query_as!(
+            DBSavedSearch,
+            r#"
+            SELECT s.id, s.user_id, s.name, s.query, s.alert, s.webhook_url, s.created_at, s.updated_at
+            FROM saved_searches AS s
+            INNER JOIN users AS u ON u.id = s.user_id
+            WHERE s.alert AND NOT u.deleted
+            ORDER BY s.id ASC
+            "#
+        )
+            .fetch_all(&self.tummy_conn_pool)
+            .await
+    }
+
+    /// Fetches the IDs of up to `limit` messages queued for search alerts, oldest first.
+    pub async fn get_queued_alert_messages(&self, limit: i64) -> Result<Vec<i64>, sqlx::Error> {
+        query_scalar!(
+            "SELECT id FROM search_alert_queue ORDER BY id ASC LIMIT $1",
+            limit
+        )
+            .fetch_all(&self.tummy_conn_pool)
+            .await
+    }
+
+    /// Removes checked messages from the search alert queue.
+    pub async fn dequeue_alert_messages(&self, queued: &[i64]) -> Result<u64, sqlx::Error> {
+        let result = query!("DELETE FROM search_alert_queue WHERE id = ANY($1)", queued)
+            .execute(&self.tummy_conn_pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Records the new messages matching a saved search, skipping the ones it
+    /// matched before. Returns the number of new matches.
+    pub async fn insert_search_alert_matches(
+        &self,
+        saved_search_id: i64,
+        results: &[SearchResult],
+        now: NaiveDateTime,
+    ) -> Result<u64, sqlx::Error> {
+        let mut inserted = 0;
+        for result in results {
+            inserted += query!(
+                r#"
+                INSERT INTO search_alert_matches (saved_search_id, channel_id, user_id, ts, highlights, matched_at)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                ON CONFLICT (saved_search_id, channel_id, user_id, ts) DO NOTHING
+                "#,
+                saved_search_id,
+                result.message.channel_id,
+                result.message.user_id,
+                result.message.timestamp,
+                &result.highlights,
+                now
+            )
+                .execute(&self.tummy_conn_pool)
+                .await?
+                .rows_affected();
+        }
+        Ok(inserted)
+    }
+
+    /// Fetches a page of a user's alert feed, newest first, leaving out
+    /// messages in channels they can no longer see.
+    ///
+    /// # Parameters
+    /// - `user_id`: The user whose saved searches matched.
+    /// - `saved_search_id`: Only the matches of this saved search, if given.
+    /// - `before`, `after`: Only matches with a lower or higher ID, if given.
+    /// - `limit`: The number of matches to fetch.
+    pub async fn get_search_alert_feed(
+        &self,
+        user_id: &str,
+        saved_search_id: Option<i64>,
+        before: Option<i64>,
+        after: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<DBSearchAlertMatch>, sqlx::Error> {
+        query_as!(
+            DBSearchAlertMatch,
+            r#"
+            SELECT
+                a.id AS match_id,
+                a.saved_search_id,
+                s.name AS saved_search_name,
+                a.highlights,
+                a.matched_at,
+                m.channel_id,
+                c.name AS channel_name,
+                m.user_id,
+                m.msg_text,
+                m.ts,
+                m.thread_ts,
+                m.parent_user_id,
+                u.id,
+                u.name,
+                u.real_name,
+                u.display_name,
+                u.image_url,
+                u.email,
+                u.deleted,
+                u.is_bot
+            FROM
+                search_alert_matches AS a
+            INNER JOIN saved_searches AS s ON s.id = a.saved_search_id
+            INNER JOIN messages AS m ON m.channel_id = a.channel_id AND m.user_id = a.user_id AND m.ts = a.ts
+            INNER JOIN users AS u ON u.id = m.user_id
+            INNER JOIN channels AS c ON c.id = m.channel_id
+            WHERE
+                s.user_id = $1
+                AND ($2::BIGINT IS NULL OR a.saved_search_id = $2)
+                AND ($3::BIGINT IS NULL OR a.id < $3)
+                AND ($4::BIGINT IS NULL OR a.id > $4)
+                AND (
+                    NOT c.is_private
+                    OR EXISTS (SELECT 1 FROM channel_members AS cm WHERE cm.channel_id = c.id AND cm.user_id = $1)
+                )
+            ORDER BY
+                a.id DESC
+            LIMIT $5
+            "#,
+            user_id,
+            saved_search_id,
+            before,
+            after,
+            limit
+        )
+            .fetch_all(&self.tummy_conn_pool)
+            .await
+    }
+
+    /// Fetches the saved searches with a webhook that have matches not yet delivered to it.
+    pub async fn get_undelivered_saved_searches(&self) -> Result<Vec<DBSavedSearch>, sqlx::Error> {
+        query_as!(
+            DBSavedSearch,
+            r#"
+            SELECT s.id, s.user_id, s.name, s.query, s.alert, s.webhook_url, s.created_at, s.updated_at
+            FROM saved_searches AS s
+            WHERE
+                s.alert AND s.webhook_url IS NOT NULL
+                AND EXISTS (SELECT 1 FROM search_alert_matches AS a WHERE a.saved_search_id = s.id AND a.delivered_at IS NULL)
+            ORDER BY s.id ASC
+            "#
+        )
+            .fetch_all(&self.tummy_conn_pool)
+            .await
+    }
+
+    /// Fetches up to `limit` matches of a saved search not yet delivered to its
+    /// webhook, oldest first. Matches in channels its owner can no longer see
+    /// are left out.
+    pub async fn get_undelivered_search_alert_matches(
+        &self,
+        saved_search_id: i64,
+        limit: i64,
+    ) -> Result<Vec<DBSearchAlertMatch>, sqlx::Error> {
+        query_as!(
+            DBSearchAlertMatch,
+            r#"
+            SELECT
+                a.id AS match_id,
+                a.saved_search_id,
+                s.name AS saved_search_name,
+                a.highlights,
+                a.matched_at,
+                m.channel_id,
+                c.name AS channel_name,
+                m.user_id,
+                m.msg_text,
+                m.ts,
+                m.thread_ts,
+                m.parent_user_id,
+                u.id,
+                u.name,
+                u.real_name,
+                u.display_name,
+                u.image_url,
+                u.email,
+                u.deleted,
+                u.is_bot
+            FROM
+                search_alert_matches AS a
+            INNER JOIN saved_searches AS s ON s.id = a.saved_search_id
+            INNER JOIN messages AS m ON m.channel_id = a.channel_id AND m.user_id = a.user_id AND m.ts = a.ts
+            INNER JOIN users AS u ON u.id = m.user_id
+            INNER JOIN channels AS c ON c.id = m.channel_id
+            WHERE
+                a.saved_search_id = $1 AND a.delivered_at IS NULL
+                AND (
+                    NOT c.is_private
+                    OR EXISTS (SELECT 1 FROM channel_members AS cm WHERE cm.channel_id = c.id AND cm.user_id = s.user_id)
+                )
+            ORDER BY
+                a.id ASC
+            LIMIT $2
+            "#,
+            saved_search_id,
+            limit
+        )
+            .fetch_all(&self.tummy_conn_pool)
+            .await
+    }
+
+    /// Marks the matches of a saved search up to and including `last_match_id`
+    /// as delivered to its webhook, including those left out because their
+    /// channel is no longer visible.
+    pub async fn mark_search_alert_matches_delivered(
+        &self,
+        saved_search_id: i64,
+        last_match_id: i64,
+        now: NaiveDateTime,
+    ) -> Result<u64, sqlx::Error> {
+        let result = query!(
+            r#"
+            UPDATE search_alert_matches
+            SET delivered_at = $3
+            WHERE saved_search_id = $1 AND id <= $2 AND delivered_at IS NULL
+            "#,
+            saved_search_id,
+            last_match_id,
+            now
+        )
+            .execute(&self.tummy_conn_pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Deletes search alert matches found before `cutoff`, returning how many were deleted.
+    pub async fn delete_search_alert_matches_before(&self, cutoff: NaiveDateTime) -> Result<u64, sqlx::Error> {
+        let result = query!("DELETE FROM search_alert_matches WHERE matched_at < $1", cutoff)
+            .execute(&self.tummy_conn_pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
@@ -0,0 +1,977 @@
+//! Full-text search over messages.
+//! A parsed query is compiled into three ranked candidate sets (trigram
+//! similarity, full-text and prefix matches) that are combined with Reciprocal
+//! Rank Fusion. Filters from the query apply to every candidate set. Code
+//! filters match the code spans in `msg_code` with its trigram index.
+//!
+//! Messages are indexed with the text search configuration (language) of their
+//! channel, recorded in `msg_tsv_config`. Full-text queries are parsed with
+//! every configuration in use to find candidates with the index, then with each
+//! row's own configuration to match and rank it.
+//! With semantic search enabled, the messages whose embeddings are nearest to
+//! the query's make up a fourth candidate set.
+//!
+//! In thread mode, threads are matched as a whole on their maintained combined
+//! tsvector in `thread_documents`, and ranked by full-text rank alone.
+//!
+//! Pages are taken from the fused ranking, so each candidate set has to be deep
+//! enough that no message belonging on the page was cut off before fusion; see
+//! [`candidate_limit`].
+
+use super::dbmodels::{DBReplyExcerpt, DBSearchFacet, DBSearchResult};
+use super::tummy::{escape_like, push_visible_channels, Tummy};
+use crate::search::{Highlighter, ParsedQuery, Ranking, SearchMode, SearchOptions, SearchSort};
+use crate::types::{FacetCount, RankExplanation, ReplyExcerpt, SearchFacets, SearchResult};
+use sqlx::types::chrono::NaiveDateTime;
+use sqlx::{Postgres, QueryBuilder};
+
+/// Most candidates taken from each ranked set, however lopsided the weights.
+const MAX_CANDIDATES: i64 = 20_000;
+
+/// Most excerpts of matching replies returned per thread.
+const MAX_REPLY_EXCERPTS: usize = 3;
+
+/// How deep into the results search can page, and the point at which the
+/// total hit count stops being exact.
+pub const MAX_SEARCH_WINDOW: i64 = 1000;
+
+/// Number of candidates each ranked set must contribute so that the first
+/// `window` fused results are exact.
+///
+/// A message ranked below `m` in every set scores less than `W / (k + m)`, with
+/// `W` the sum of the weights. Each of the top `window` messages of a set with
+/// weight `w` scores at least `w / (k + window)`. With
+/// `m = W / w_min * (k + window) - k`, where `w_min` is the smallest weight
+/// above 0 and `W / w_min` the [`Ranking::weight_spread`], such a message can therefore never make it into the first `window`
+/// results.
+fn candidate_limit(ranking: &Ranking, window: i64) -> i64 {
+    let limit = (ranking.weight_spread() * (ranking.k + window as f64) - ranking.k).ceil();
+    (limit as i64).clamp(window, MAX_CANDIDATES)
+}
+
+/// Pushes the query's filters as `AND` conditions on the `messages` columns
+/// prefixed with `prefix` (e.g. `m.`), including channel visibility for `viewer`.
+fn push_search_filters<'a>(
+    builder: &mut QueryBuilder<'a, Postgres>,
+    prefix: &str,
+    query: &'a ParsedQuery,
+    viewer: Option<&'a str>,
+) {
+    push_message_filters(builder, prefix, query, viewer);
+    push_text_filters(
+        builder,
+        &format!("{}msg_tsv", prefix),
+        &format!("{}msg_tsv_config", prefix),
+        query,
+    );
+}
+
+/// Pushes the query's filters on anything but the text, as `AND` conditions on
+/// the `messages` columns prefixed with `prefix`.
+pub(super) fn push_message_filters<'a>(
+    builder: &mut QueryBuilder<'a, Postgres>,
+    prefix: &str,
+    query: &'a ParsedQuery,
+    viewer: Option<&'a str>,
+) {
+    push_visible_channels(builder, &format!("{}channel_id", prefix), viewer);
+
+    if let Some(channel_id) = &query.channel_id {
+        builder.push(format!(" AND {}channel_id = ", prefix));
+        builder.push_bind(channel_id);
+    }
+    if let Some(user_id) = &query.user_id {
+        builder.push(format!(" AND {}user_id = ", prefix));
+        builder.push_bind(user_id);
+    }
+
+    for (users, negated) in [(&query.from_users, false), (&query.excluded_users, true)] {
+        if users.is_empty() {
+            continue;
+        }
+        builder.push(format!(
+            " AND {}user_id {} (SELECT id FROM users WHERE lower(id) = ANY(",
+            prefix,
+            if negated { "NOT IN" } else { "IN" }
+        ));
+        builder.push_bind(users);
+        builder.push(") OR lower(name) = ANY(");
+        builder.push_bind(users);
+        builder.push(") OR lower(display_name) = ANY(");
+        builder.push_bind(users);
+        builder.push("))");
+    }
+
+    for (channels, negated) in [(&query.in_channels, false), (&query.excluded_channels, true)] {
+        if channels.is_empty() {
+            continue;
+        }
+        builder.push(format!(
+            " AND {}channel_id {} (SELECT id FROM channels WHERE lower(id) = ANY(",
+            prefix,
+            if negated { "NOT IN" } else { "IN" }
+        ));
+        builder.push_bind(channels);
+        builder.push(") OR lower(name) = ANY(");
+        builder.push_bind(channels);
+        builder.push("))");
+    }
+
+    if let Some(since) = query.since {
+        builder.push(format!(" AND {}ts >= ", prefix));
+        builder.push_bind(since);
+    }
+    if let Some(until) = query.until {
+        builder.push(format!(" AND {}ts < ", prefix));
+        builder.push_bind(until);
+    }
+
+    if let Some(has_link) = query.has_link {
+        builder.push(format!(
+            " AND {}({}msg_text ~* 'https?://')",
+            if has_link { "" } else { "NOT " },
+            prefix
+        ));
+    }
+    if let Some(has_code) = query.has_code {
+        builder.push(format!(
+            " AND {}msg_code IS {}NULL",
+            prefix,
+            if has_code { "NOT " } else { "" }
+        ));
+    }
+    for (snippets, negated) in [(&query.code, false), (&query.excluded_code, true)] {
+        for snippet in snippets {
+            builder.push(format!(
+                " AND {}COALESCE({}msg_code ILIKE ",
+                if negated { "NOT " } else { "" },
+                prefix
+            ));
+            builder.push_bind(format!("%{}%", escape_like(snippet)));
+            builder.push(", false)");
+        }
+    }
+    if let Some(is_thread) = query.is_thread {
+        builder.push(format!(
+            " AND {}thread_ts IS {}NULL",
+            prefix,
+            if is_thread { "NOT " } else { "" }
+        ));
+    }
+    if let Some(queued) = &query.queued {
+        builder.push(format!(
+            " AND ({0}channel_id, {0}user_id, {0}ts) IN (SELECT channel_id, user_id, ts FROM search_alert_queue WHERE id = ANY(",
+            prefix
+        ));
+        builder.push_bind(queued);
+        builder.push("))");
+    }
+}
+
+/// Pushes the query's phrases and exclusions as `AND` conditions on the
+/// tsvector column `tsv_column`, indexed with the configuration in `config_column`.
+fn push_text_filters<'a>(
+    builder: &mut QueryBuilder<'a, Postgres>,
+    tsv_column: &str,
+    config_column: &str,
+    query: &'a ParsedQuery,
+) {
+    for phrase in &query.phrases {
+        builder.push(format!(" AND {} @@ phraseto_tsquery({}, ", tsv_column, config_column));
+        builder.push_bind(phrase);
+        builder.push(")");
+    }
+    for excluded in &query.excluded {
+        builder.push(format!(
+            " AND NOT COALESCE({} @@ phraseto_tsquery({}, ",
+            tsv_column, config_column
+        ));
+        builder.push_bind(excluded);
+        builder.push("), false)");
+    }
+}
+
+/// Pushes a condition that the tsvector column `tsv_column`, indexed with the
+/// configuration in `config_column`, matches the `websearch_to_tsquery` query `text`.
+fn push_full_text_match<'a>(
+    builder: &mut QueryBuilder<'a, Postgres>,
+    tsv_column: &str,
+    config_column: &str,
+    text: String,
+) {
+    builder.push(format!("({} @@ websearch_to_tsquery_any(", tsv_column));
+    builder.push_bind(text.clone());
+    builder.push(format!(
+        ") AND {} @@ websearch_to_tsquery({}, ",
+        tsv_column, config_column
+    ));
+    builder.push_bind(text);
+    builder.push("))");
+}
+
+/// Pushes the `ts_rank_cd` of the tsvector column `tsv_column`, indexed with
+/// the configuration in `config_column`, for the `websearch_to_tsquery` query `text`.
+fn push_full_text_rank<'a>(
+    builder: &mut QueryBuilder<'a, Postgres>,
+    tsv_column: &str,
+    config_column: &str,
+    text: String,
+) {
+    builder.push(format!(
+        "ts_rank_cd({}, websearch_to_tsquery({}, ",
+        tsv_column, config_column
+    ));
+    builder.push_bind(text);
+    builder.push("))");
+}
+
+/// Pushes the ranked candidate sets `fuzzy`, `full_text`, `partial_search` and
+/// `semantic` as CTEs of at most `limit` messages each, ordered by their
+/// `rank_ix`. The query must have text.
+fn push_candidates<'a>(
+    builder: &mut QueryBuilder<'a, Postgres>,
+    query: &'a ParsedQuery,
+    viewer: Option<&'a str>,
+    limit: i64,
+) {
+    let fuzzy_text = query.fuzzy_text();
+    let websearch_text = query.websearch_text();
+
+    // Fuzzy
+    builder.push(r#"
+    fuzzy AS (
+        SELECT
+            channel_id,
+            user_id,
+            ts,
+            similarity(msg_text, "#);
+    builder.push_bind(fuzzy_text.clone());
+    builder.push(r#") as sim_score,
+            row_number() OVER (ORDER BY similarity(msg_text, "#);
+    builder.push_bind(fuzzy_text.clone());
+    builder.push(r#") DESC) as rank_ix
+        FROM messages
+        WHERE msg_text %> "#);
+    builder.push_bind(fuzzy_text);
+    push_search_filters(builder, "", query, viewer);
+    builder.push(r#"
+        ORDER BY rank_ix
+        LIMIT "#);
+    builder.push_bind(limit);
+    builder.push(r#"
+    ),
+"#);
+
+    // Full text, including phrases and exclusions
+    builder.push(r#"
+    full_text AS (
+        SELECT
+            channel_id,
+            user_id,
+            ts,
+            "#);
+    push_full_text_rank(builder, "msg_tsv", "msg_tsv_config", websearch_text.clone());
+    builder.push(r#" as rank_score,
+            row_number() OVER (ORDER BY "#);
+    push_full_text_rank(builder, "msg_tsv", "msg_tsv_config", websearch_text.clone());
+    builder.push(r#" DESC) as rank_ix
+        FROM messages
+        WHERE "#);
+    push_full_text_match(builder, "msg_tsv", "msg_tsv_config", websearch_text);
+    push_search_filters(builder, "", query, viewer);
+    builder.push(r#"
+        ORDER BY rank_ix
+        LIMIT "#);
+    builder.push_bind(limit);
+    builder.push(r#"
+    ),
+"#);
+
+    // Prefix search, only when there are words (not just phrases)
+    builder.push(r#"
+    partial_search AS (
+        SELECT
+            channel_id,
+            user_id,
+            ts,
+            "#);
+    match query.prefix_query() {
+        Some(prefix_query) => {
+            builder.push(r#"ts_rank_cd(msg_tsv, to_tsquery('simple', "#);
+            builder.push_bind(prefix_query.clone());
+            builder.push(r#")) as rank_score,
+            row_number() OVER (ORDER BY ts_rank_cd(msg_tsv, to_tsquery('simple', "#);
+            builder.push_bind(prefix_query.clone());
+            builder.push(r#")) DESC) as rank_ix
+        FROM messages
+        WHERE msg_tsv @@ to_tsquery('simple', "#);
+            builder.push_bind(prefix_query);
+            builder.push(r#")"#);
+        }
+        None => {
+            builder.push(r#"0::real as rank_score,
+            0::bigint as rank_ix
+        FROM messages
+        WHERE false"#);
+        }
+    }
+    push_search_filters(builder, "", query, viewer);
+    builder.push(r#"
+        ORDER BY rank_ix
+        LIMIT "#);
+    builder.push_bind(limit);
+    builder.push(r#"
+    ),
+"#);
+
+    // Nearest embeddings, only when the query was embedded
+    builder.push(r#"
+    semantic AS (
+        SELECT
+            "#);
+    match &query.semantic {
+        Some(semantic) => {
+            builder.push(r#"m.channel_id,
+            m.user_id,
+            m.ts,
+            row_number() OVER (ORDER BY e.embedding <=> "#);
+            builder.push_bind(&semantic.embedding);
+            builder.push(r#"::vector) as rank_ix
+        FROM message_embeddings e
+        INNER JOIN messages m ON m.channel_id = e.channel_id AND m.user_id = e.user_id AND m.ts = e.ts
+        WHERE (e.embedding <=> "#);
+            builder.push_bind(&semantic.embedding);
+            builder.push("::vector) < ");
+            builder.push_bind(semantic.max_distance);
+            push_search_filters(builder, "m.", query, viewer);
+        }
+        None => {
+            builder.push(r#"channel_id,
+            user_id,
+            ts,
+            0::bigint as rank_ix
+        FROM messages
+        WHERE false"#);
+        }
+    }
+    builder.push(r#"
+        ORDER BY rank_ix
+        LIMIT "#);
+    builder.push_bind(limit);
+    builder.push(r#"
+    )
+"#);
+}
+
+/// Pushes a `UNION` of the messages whose embeddings are within the query's
+/// maximum distance, nearest first and at most [`MAX_SEARCH_WINDOW`] of them.
+fn push_semantic_matches<'a>(
+    builder: &mut QueryBuilder<'a, Postgres>,
+    query: &'a ParsedQuery,
+    viewer: Option<&'a str>,
+) {
+    let Some(semantic) = &query.semantic else {
+        return;
+    };
+    builder.push(
+        r#" UNION (SELECT m.channel_id, m.user_id, m.ts FROM message_embeddings e
+        INNER JOIN messages m ON m.channel_id = e.channel_id AND m.user_id = e.user_id AND m.ts = e.ts
+        WHERE (e.embedding <=> "#,
+    );
+    builder.push_bind(&semantic.embedding);
+    builder.push("::vector) < ");
+    builder.push_bind(semantic.max_distance);
+    push_search_filters(builder, "m.", query, viewer);
+    builder.push(" ORDER BY e.embedding <=> ");
+    builder.push_bind(&semantic.embedding);
+    builder.push("::vector LIMIT ");
+    builder.push_bind(MAX_SEARCH_WINDOW);
+    builder.push(")");
+}
+
+/// Pushes a query selecting the `channel_id`, `user_id` and `ts` of every
+/// message matching `query`, without ranking or limits: the messages matched by
+/// any of the candidate sets, the top-level messages of matching threads, or
+/// every top-level message for queries without text. Semantic matches are
+/// limited to the nearest ones, as they would otherwise include most messages.
+fn push_matches<'a>(
+    builder: &mut QueryBuilder<'a, Postgres>,
+    query: &'a ParsedQuery,
+    viewer: Option<&'a str>,
+    mode: SearchMode,
+) {
+    if mode == SearchMode::Threads && query.has_text() {
+        push_thread_matches(builder, query, viewer);
+        return;
+    }
+
+    if !query.has_text() {
+        builder.push(
+            "SELECT channel_id, user_id, ts FROM messages WHERE (parent_user_id IS NULL OR parent_user_id = '')",
+        );
+        push_search_filters(builder, "", query, viewer);
+        return;
+    }
+
+    builder.push("SELECT channel_id, user_id, ts FROM messages WHERE msg_text %> ");
+    builder.push_bind(query.fuzzy_text());
+    push_search_filters(builder, "", query, viewer);
+
+    builder.push(" UNION SELECT channel_id, user_id, ts FROM messages WHERE ");
+    push_full_text_match(builder, "msg_tsv", "msg_tsv_config", query.websearch_text());
+    push_search_filters(builder, "", query, viewer);
+
+    if let Some(prefix_query) = query.prefix_query() {
+        builder.push(
+            " UNION SELECT channel_id, user_id, ts FROM messages WHERE msg_tsv @@ to_tsquery('simple', ",
+        );
+        builder.push_bind(prefix_query);
+        builder.push(")");
+        push_search_filters(builder, "", query, viewer);
+    }
+
+    push_semantic_matches(builder, query, viewer);
+}
+
+/// Pushes a query selecting the `channel_id`, `user_id` and `ts` of the
+/// top-level message of every thread whose combined text matches `query`, with
+/// the thread's full-text `rank_score`. The other filters must hold for at least
+/// one message of the thread. The query must have text.
+fn push_thread_matches<'a>(
+    builder: &mut QueryBuilder<'a, Postgres>,
+    query: &'a ParsedQuery,
+    viewer: Option<&'a str>,
+) {
+    builder.push(
+        r#"
+        SELECT
+            m.channel_id,
+            m.user_id,
+            m.ts,
+            "#,
+    );
+    push_full_text_rank(builder, "t.doc_tsv", "t.doc_tsv_config", query.websearch_text());
+    builder.push(
+        r#" AS rank_score
+        FROM thread_documents t
+        INNER JOIN messages m
+            ON m.channel_id = t.channel_id AND m.ts = t.thread_ts
+            AND (m.parent_user_id IS NULL OR m.parent_user_id = '')
+        WHERE ("#,
+    );
+    push_full_text_match(builder, "t.doc_tsv", "t.doc_tsv_config", query.websearch_text());
+    if let Some(prefix_query) = query.prefix_query() {
+        builder.push(" OR t.doc_tsv @@ to_tsquery('simple', ");
+        builder.push_bind(prefix_query);
+        builder.push(")");
+    }
+    builder.push(")");
+    push_text_filters(builder, "t.doc_tsv", "t.doc_tsv_config", query);
+    builder.push(
+        r#"
+            AND EXISTS (
+                SELECT 1 FROM messages tm
+                WHERE tm.channel_id = t.channel_id AND COALESCE(tm.thread_ts, tm.ts) = t.thread_ts"#,
+    );
+    push_message_filters(builder, "tm.", query, viewer);
+    builder.push(
+        r#"
+            )"#,
+    );
+}
+
+/// Pushes the `highlight_query` CTE with the query `q` to highlight: any of
+/// the words and phrases, and the prefix matches. Words similar to the query's
+/// words are added per message by [`push_headline`].
+fn push_highlight_query<'a>(builder: &mut QueryBuilder<'a, Postgres>, query: &'a ParsedQuery) {
+    builder.push(
+        r#"
+    highlight_query AS (
+        SELECT websearch_to_tsquery_any("#,
+    );
+    builder.push_bind(query.websearch_any_text());
+    builder.push(")");
+    if let Some(prefix_query) = query.prefix_query() {
+        builder.push(" || to_tsquery('simple', ");
+        builder.push_bind(prefix_query);
+        builder.push(")");
+    }
+    builder.push(
+        r#" AS q
+    )
+"#,
+    );
+}
+
+/// Pushes the `headline` column: the `ts_headline` of `plain.msg_text` for
+/// `hq.q` in the message's configuration `plain.config`, as joined by
+/// [`push_plain_text`].
+fn push_headline<'a>(builder: &mut QueryBuilder<'a, Postgres>, query: &'a ParsedQuery) {
+    builder.push(
+        r#"
+        ts_headline(
+            plain.config,
+            plain.msg_text,
+            COALESCE(
+                hq.q || (
+                    SELECT to_tsquery(plain.config, string_agg(DISTINCT quote_literal(word), ' | '))
+                    FROM regexp_split_to_table(lower(plain.msg_text), '\W+') AS word
+                    WHERE word <> '' AND word % ANY("#,
+    );
+    builder.push_bind(&query.terms);
+    builder.push(
+        r#")
+                ),
+                hq.q
+            ),
+            "#,
+    );
+    builder.push_bind(Highlighter::headline_options());
+    builder.push(
+        r#"
+        ) AS headline"#,
+    );
+}
+
+/// Pushes `SELECT` and the columns of a [`DBSearchResult`] for the message
+/// aliased `m`, up to the headline and ranks, as joined by [`push_result_joins`].
+pub(super) fn push_result_columns(builder: &mut QueryBuilder<'_, Postgres>) {
+    builder.push(
+        r#"
+    SELECT
+        m.channel_id, channel.name AS channel_name, m.user_id, m.msg_text, m.ts, m.thread_ts, m.parent_user_id,
+        u.id, u.name, u.real_name, u.display_name, u.image_url, u.email, u.deleted, u.is_bot,
+        c.cnt,
+        CASE WHEN parent_u.id IS NOT NULL THEN parent_m.msg_text ELSE NULL END as "parent_msg_text",
+        CASE WHEN parent_u.is_bot IS NOT NULL THEN parent_u.name ELSE NULL END as "parent_name",
+        CASE WHEN parent_u.id IS NOT NULL THEN parent_u.real_name ELSE NULL END as "parent_real_name",
+        CASE WHEN parent_u.id IS NOT NULL THEN parent_u.display_name ELSE NULL END as "parent_display_name",
+        CASE WHEN parent_u.id IS NOT NULL THEN parent_u.image_url ELSE NULL END as "parent_image_url",
+        CASE WHEN parent_u.id IS NOT NULL THEN parent_u.email ELSE NULL END as "parent_email",
+        CASE WHEN parent_u.id IS NOT NULL THEN parent_u.deleted ELSE NULL END as "parent_deleted",
+        CASE WHEN parent_u.id IS NOT NULL THEN parent_u.is_bot ELSE NULL END as "parent_is_bot","#,
+    );
+}
+
+/// Pushes the joins of the author, channel, reply count and parent message of
+/// the message aliased `m`, for [`push_result_columns`].
+pub(super) fn push_result_joins(builder: &mut QueryBuilder<'_, Postgres>) {
+    builder.push(
+        r#"
+        INNER JOIN users AS u ON u.id = m.user_id
+        INNER JOIN channels AS channel ON channel.id = m.channel_id
+        LEFT JOIN (
+            SELECT COUNT(*) as cnt, channel_id, thread_ts FROM messages WHERE parent_user_id != '' GROUP BY channel_id, thread_ts
+        ) AS c ON m.channel_id = c.channel_id AND m.thread_ts = c.thread_ts
+        LEFT JOIN messages AS parent_m
+            ON m.channel_id = parent_m.channel_id AND m.thread_ts = parent_m.ts AND parent_m.parent_user_id = ''
+        LEFT JOIN users AS parent_u ON parent_m.user_id = parent_u.id"#,
+    );
+}
+
+/// Pushes joins of `highlight_query` as `hq`, and of the text of the message
+/// aliased `alias` without HTML tags and highlight sentinels as `plain.msg_text`
+/// with its text search configuration as `plain.config`.
+pub(super) fn push_plain_text(builder: &mut QueryBuilder<'_, Postgres>, alias: &str) {
+    builder.push(format!(
+        r#"
+        CROSS JOIN highlight_query AS hq
+        CROSS JOIN LATERAL (
+            SELECT translate(regexp_replace({}.msg_text, '<[^>]*>', ' ', 'g'), "#,
+        alias
+    ));
+    builder.push_bind(Highlighter::sentinels());
+    builder.push(format!(
+        r#", '') AS msg_text,
+                COALESCE({}.msg_tsv_config, channel_search_config({}.channel_id)) AS config
+        ) AS plain
+"#,
+        alias, alias
+    ));
+}
+
+impl Tummy {
+    /// Searches the messages `viewer` may see and returns a page of results
+    /// with their matches highlighted, as set by `options`.
+    /// Queries without words or phrases list the matching messages by date.
+    pub async fn search_messages(
+        &self,
+        viewer: Option<&str>,
+        query: &ParsedQuery,
+        options: &SearchOptions<'_>,
+    ) -> color_eyre::Result<Vec<SearchResult>> {
+        tracing::debug!("RRF search for {:?}", query);
+        let SearchOptions {
+            mode,
+            sort,
+            offset,
+            limit,
+            ..
+        } = *options;
+
+        if !query.has_text() {
+            let mut builder: QueryBuilder<Postgres> = QueryBuilder::new(
+                r#"
+            SELECT
+                m.channel_id,
+                c.name AS channel_name,
+                m.user_id,
+                m.msg_text,
+                m.ts,
+                m.thread_ts,
+                m.parent_user_id,
+                u.id,
+                u.name,
+                u.real_name,
+                u.display_name,
+                u.image_url,
+                u.email,
+                u.deleted,
+                u.is_bot,
+                (SELECT COUNT(*) FROM messages WHERE channel_id = m.channel_id AND thread_ts = m.ts) as cnt,
+                NULL as parent_msg_text,
+                NULL as parent_name,
+                NULL as parent_real_name,
+                NULL as parent_display_name,
+                NULL as parent_image_url,
+                NULL as parent_email,
+                NULL as parent_deleted,
+                NULL as parent_is_bot,
+                NULL as headline,
+                NULL::bigint as fuzzy_rank,
+                NULL::bigint as full_text_rank,
+                NULL::bigint as prefix_rank,
+                NULL::bigint as semantic_rank
+            FROM
+                messages m
+            INNER JOIN
+                users u ON u.id = m.user_id
+            INNER JOIN
+                channels c ON c.id = m.channel_id
+            "#,
+            );
+            builder.push(" WHERE (m.parent_user_id IS NULL OR m.parent_user_id = '')");
+            push_search_filters(&mut builder, "m.", query, viewer);
+
+            builder.push(format!(" ORDER BY m.ts {} LIMIT ", sort.ts_direction()));
+            builder.push_bind(limit);
+            builder.push(" OFFSET ");
+            builder.push_bind(offset);
+
+            let recent_messages = builder
+                .build_query_as::<DBSearchResult>()
+                .fetch_all(&self.tummy_conn_pool)
+                .await?;
+
+            return Ok(recent_messages.into_iter().map(SearchResult::from).collect());
+        }
+
+        // All searches but relevance sorted message searches page through the
+        // matches in a single order
+        let fused = options.fuses_ranks();
+
+        let mut builder: QueryBuilder<Postgres> = QueryBuilder::new("WITH ");
+        if fused {
+            push_candidates(
+                &mut builder,
+                query,
+                viewer,
+                candidate_limit(&options.ranking, offset + limit),
+            );
+        } else {
+            let order = if sort == SearchSort::Relevance {
+                "rank_score DESC, ts DESC".to_owned()
+            } else {
+                format!("ts {}", sort.ts_direction())
+            };
+            builder.push(format!(
+                "page AS (SELECT channel_id, user_id, ts, row_number() OVER (ORDER BY {}, channel_id, user_id) AS page_ix FROM (",
+                order
+            ));
+            push_matches(&mut builder, query, viewer, mode);
+            builder.push(") AS matches ORDER BY page_ix LIMIT ");
+            builder.push_bind(limit);
+            builder.push(" OFFSET ");
+            builder.push_bind(offset);
+            builder.push(")");
+        }
+        builder.push(",");
+        push_highlight_query(&mut builder, query);
+
+        // Results
+        push_result_columns(&mut builder);
+        push_headline(&mut builder, query);
+        builder.push(",");
+        if fused {
+            builder.push(r#"
+        fuzzy.rank_ix AS fuzzy_rank,
+        full_text.rank_ix AS full_text_rank,
+        partial_search.rank_ix AS prefix_rank,
+        semantic.rank_ix AS semantic_rank
+    FROM
+        fuzzy
+        FULL OUTER JOIN full_text
+            ON fuzzy.channel_id = full_text.channel_id AND fuzzy.user_id = full_text.user_id AND fuzzy.ts = full_text.ts
+        FULL OUTER JOIN partial_search
+            ON COALESCE(fuzzy.channel_id, full_text.channel_id) = partial_search.channel_id
+            AND COALESCE(fuzzy.user_id, full_text.user_id) = partial_search.user_id
+            AND COALESCE(fuzzy.ts, full_text.ts) = partial_search.ts
+        FULL OUTER JOIN semantic
+            ON COALESCE(fuzzy.channel_id, full_text.channel_id, partial_search.channel_id) = semantic.channel_id
+            AND COALESCE(fuzzy.user_id, full_text.user_id, partial_search.user_id) = semantic.user_id
+            AND COALESCE(fuzzy.ts, full_text.ts, partial_search.ts) = semantic.ts
+        JOIN messages m
+            ON m.channel_id = COALESCE(fuzzy.channel_id, full_text.channel_id, partial_search.channel_id, semantic.channel_id)
+            AND m.user_id = COALESCE(fuzzy.user_id, full_text.user_id, partial_search.user_id, semantic.user_id)
+            AND m.ts = COALESCE(fuzzy.ts, full_text.ts, partial_search.ts, semantic.ts)"#);
+        } else {
+            builder.push(r#"
+        NULL::bigint AS fuzzy_rank,
+        NULL::bigint AS full_text_rank,
+        NULL::bigint AS prefix_rank,
+        NULL::bigint AS semantic_rank
+    FROM
+        page
+        JOIN messages m ON m.channel_id = page.channel_id AND m.user_id = page.user_id AND m.ts = page.ts"#);
+        }
+        push_result_joins(&mut builder);
+        push_plain_text(&mut builder, "m");
+        if !fused {
+            builder.push(" ORDER BY page.page_ix");
+            let mut results = self.fetch_search_results(builder, options).await?;
+            if mode == SearchMode::Threads {
+                self.add_reply_excerpts(query, &mut results, options.highlighter)
+                    .await?;
+            }
+            return Ok(results);
+        }
+
+        // Reciprocal Rank Fusion (RRF)
+        let ranking = &options.ranking;
+        builder.push("\n    ORDER BY\n        COALESCE(");
+        builder.push_bind(ranking.fuzzy);
+        builder.push(" / (");
+        builder.push_bind(ranking.k);
+        builder.push(" + fuzzy.rank_ix), 0.0) +\n        COALESCE(");
+        builder.push_bind(ranking.full_text);
+        builder.push(" / (");
+        builder.push_bind(ranking.k);
+        builder.push(" + full_text.rank_ix), 0.0) +\n        COALESCE(");
+        builder.push_bind(ranking.prefix);
+        builder.push(" / (");
+        builder.push_bind(ranking.k);
+        builder.push(" + partial_search.rank_ix), 0.0) +\n        COALESCE(");
+        builder.push_bind(ranking.semantic);
+        builder.push(" / (");
+        builder.push_bind(ranking.k);
+        builder.push(
+            r#" + semantic.rank_ix), 0.0)
+        DESC,
+        -- Ties are broken by time so that pages do not overlap
+        m.ts DESC
+"#,
+        );
+
+        builder.push(" LIMIT ");
+        builder.push_bind(limit);
+        builder.push(" OFFSET ");
+        builder.push_bind(offset);
+
+        self.fetch_search_results(builder, options).await
+    }
+
+    /// Runs a search built by `search_messages`, then highlights and, if asked
+    /// to, explains the results.
+    async fn fetch_search_results(
+        &self,
+        mut builder: QueryBuilder<'_, Postgres>,
+        options: &SearchOptions<'_>,
+    ) -> color_eyre::Result<Vec<SearchResult>> {
+        let messages = builder
+            .build_query_as::<DBSearchResult>()
+            .fetch_all(&self.tummy_conn_pool)
+            .await?;
+
+        Ok(messages
+            .into_iter()
+            .map(|message| {
+                let highlights = options.highlighter.fragments(message.headline.as_deref());
+                let explain = (options.explain && options.fuses_ranks()).then(|| {
+                    RankExplanation::new(
+                        &options.ranking,
+                        [
+                            message.fuzzy_rank,
+                            message.full_text_rank,
+                            message.prefix_rank,
+                            message.semantic_rank,
+                        ],
+                    )
+                });
+                SearchResult {
+                    highlights,
+                    explain,
+                    ..SearchResult::from(message)
+                }
+            })
+            .collect())
+    }
+
+    /// Adds excerpts of the replies matching `query` to thread search results.
+    async fn add_reply_excerpts(
+        &self,
+        query: &ParsedQuery,
+        results: &mut [SearchResult],
+        highlighter: &Highlighter,
+    ) -> color_eyre::Result<()> {
+        let channel_ids: Vec<String> = results
+            .iter()
+            .map(|result| result.message.channel_id.clone())
+            .collect();
+        let thread_tss: Vec<NaiveDateTime> = results
+            .iter()
+            .map(|result| result.message.timestamp)
+            .collect();
+
+        let mut builder: QueryBuilder<Postgres> = QueryBuilder::new("WITH ");
+        push_highlight_query(&mut builder, query);
+        builder.push(
+            r#"
+    SELECT
+        r.channel_id,
+        r.thread_ts,
+        r.ts,
+        r.user_id,
+        COALESCE(NULLIF(u.display_name, ''), u.name) AS user_name,"#,
+        );
+        push_headline(&mut builder, query);
+        builder.push(
+            r#"
+    FROM messages r
+        INNER JOIN users AS u ON u.id = r.user_id"#,
+        );
+        push_plain_text(&mut builder, "r");
+        builder.push(" WHERE (r.channel_id, r.thread_ts) IN (SELECT * FROM UNNEST(");
+        builder.push_bind(channel_ids);
+        builder.push("::text[], ");
+        builder.push_bind(thread_tss);
+        builder.push(
+            r#"::timestamp[]))
+        AND r.ts <> r.thread_ts
+        AND COALESCE(r.msg_tsv, to_tsvector(plain.config, r.msg_text)) @@ hq.q
+    ORDER BY r.ts
+"#,
+        );
+
+        let replies = builder
+            .build_query_as::<DBReplyExcerpt>()
+            .fetch_all(&self.tummy_conn_pool)
+            .await?;
+
+        for reply in replies {
+            let Some(result) = results.iter_mut().find(|result| {
+                result.message.channel_id == reply.channel_id
+                    && result.message.timestamp == reply.thread_ts
+            }) else {
+                continue;
+            };
+            if result.reply_excerpts.len() >= MAX_REPLY_EXCERPTS {
+                continue;
+            }
+            let highlights = highlighter.fragments(reply.headline.as_deref());
+            if !highlights.is_empty() {
+                result.reply_excerpts.push(ReplyExcerpt {
+                    highlights,
+                    ..ReplyExcerpt::from(reply)
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts the messages (or in thread mode, the threads) `viewer` may see
+    /// that match `query`, counting no further than `cap`.
+    pub async fn count_search_matches(
+        &self,
+        viewer: Option<&str>,
+        query: &ParsedQuery,
+        mode: SearchMode,
+        cap: i64,
+    ) -> color_eyre::Result<i64> {
+        let mut builder: QueryBuilder<Postgres> = QueryBuilder::new("SELECT COUNT(*) FROM (");
+        push_matches(&mut builder, query, viewer, mode);
+        builder.push(" LIMIT ");
+        builder.push_bind(cap);
+        builder.push(") AS matches");
+
+        Ok(builder
+            .build_query_scalar::<i64>()
+            .fetch_one(&self.tummy_conn_pool)
+            .await?)
+    }
+
+    /// Counts all messages `viewer` may see that match `query` by channel, by
+    /// user and by month. Channels and users are limited to the `limit` with the
+    /// most hits; months are all returned, newest first.
+    pub async fn search_facets(
+        &self,
+        viewer: Option<&str>,
+        query: &ParsedQuery,
+        mode: SearchMode,
+        limit: i64,
+    ) -> color_eyre::Result<SearchFacets> {
+        let mut builder: QueryBuilder<Postgres> = QueryBuilder::new("WITH matches AS (");
+        push_matches(&mut builder, query, viewer, mode);
+        builder.push(
+            r#"
+    )
+    (
+        SELECT 'channel' AS facet, c.id AS value, c.name AS label, COUNT(*) AS count
+        FROM matches
+        INNER JOIN channels c ON c.id = matches.channel_id
+        GROUP BY c.id, c.name
+        ORDER BY count DESC, c.name
+        LIMIT "#,
+        );
+        builder.push_bind(limit);
+        builder.push(
+            r#"
+    )
+    UNION ALL
+    (
+        SELECT 'user', u.id, COALESCE(NULLIF(u.display_name, ''), u.name), COUNT(*)
+        FROM matches
+        INNER JOIN users u ON u.id = matches.user_id
+        GROUP BY u.id
+        ORDER BY COUNT(*) DESC, u.name
+        LIMIT "#,
+        );
+        builder.push_bind(limit);
+        builder.push(
+            r#"
+    )
+    UNION ALL
+    (
+        SELECT 'month', to_char(date_trunc('month', ts), 'YYYY-MM'), NULL, COUNT(*)
+        FROM matches
+        GROUP BY 2
+        ORDER BY 2 DESC
+    )
+"#,
+        );
+
+        let rows = builder
+            .build_query_as::<DBSearchFacet>()
+            .fetch_all(&self.tummy_conn_pool)
+            .await?;
+
+        let mut facets = SearchFacets::default();
+        for row in rows {
+            match row.facet.as_str() {
+                "channel" => facets.channels.push(FacetCount::from(row)),
+                "user" => facets.users.push(FacetCount::from(row)),
+                _ => facets.months.push(FacetCount::from(row)),
+            }
+        }
+        Ok(facets)
+    }
+}
@@ -0,0 +1,94 @@
+//! Queries for the `search_query_log` table, which backs search analytics.
+
+use super::dbmodels::DBSearchQueryStats;
+use super::tummy::Tummy;
+use crate::search::analytics::SearchQueryReport;
+use sqlx::{query, types::chrono::NaiveDateTime, Postgres, QueryBuilder};
+
+impl Tummy {
+    pub async fn insert_search_query(
+        &self,
+        occurred_at: NaiveDateTime,
+        search_query: &str,
+        user_pseudonym: Option<&str>,
+        mode: &str,
+        total_hits: i64,
+        latency_ms: f64,
+    ) -> Result<(), sqlx::Error> {
+        query!(
+            r#"
+            INSERT INTO search_query_log (occurred_at, query, user_pseudonym, mode, total_hits, latency_ms)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            occurred_at,
+            search_query,
+            user_pseudonym,
+            mode,
+            total_hits,
+            latency_ms
+        )
+            .execute(&self.tummy_conn_pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Aggregates the searches logged between `since` and `until` by query,
+    /// filtered and ordered as `report` asks, up to `limit` queries.
+    pub async fn search_query_stats(
+        &self,
+        report: SearchQueryReport,
+        since: NaiveDateTime,
+        until: NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<DBSearchQueryStats>, sqlx::Error> {
+        let mut builder: QueryBuilder<Postgres> = QueryBuilder::new(
+            r#"
+            SELECT
+                query,
+                COUNT(*) AS searches,
+                COUNT(DISTINCT user_pseudonym) AS users,
+                COUNT(*) FILTER (WHERE total_hits = 0) AS zero_result_searches,
+                AVG(total_hits)::DOUBLE PRECISION AS avg_hits,
+                AVG(latency_ms) AS avg_latency_ms,
+                MAX(latency_ms) AS max_latency_ms,
+                MAX(occurred_at) AS last_searched_at
+            FROM search_query_log
+            WHERE occurred_at >= "#,
+        );
+        builder.push_bind(since);
+        builder.push(" AND occurred_at < ");
+        builder.push_bind(until);
+        builder.push(" GROUP BY query");
+
+        match report {
+            SearchQueryReport::Top => {
+                builder.push(" ORDER BY searches DESC, last_searched_at DESC");
+            }
+            SearchQueryReport::ZeroResults => {
+                builder.push(
+                    " HAVING COUNT(*) FILTER (WHERE total_hits = 0) > 0 ORDER BY zero_result_searches DESC, last_searched_at DESC",
+                );
+            }
+            SearchQueryReport::Slow { min_latency_ms } => {
+                builder.push(" HAVING AVG(latency_ms) >= ");
+                builder.push_bind(min_latency_ms);
+                builder.push(" ORDER BY avg_latency_ms DESC, searches DESC");
+            }
+        }
+        builder.push(" LIMIT ");
+        builder.push_bind(limit);
+
+        builder
+            .build_query_as::<DBSearchQueryStats>()
+            .fetch_all(&self.tummy_conn_pool)
+            .await
+    }
+
+    /// Deletes logged searches older than `cutoff`, returning how many were removed.
+    pub async fn delete_search_queries_before(&self, cutoff: NaiveDateTime) -> Result<u64, sqlx::Error> {
+        let result = query!("DELETE FROM search_query_log WHERE occurred_at < $1", cutoff)
+            .execute(&self.tummy_conn_pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
@@ -0,0 +1,58 @@
+//! Queries for the text search configurations messages are indexed with.
+
+use super::tummy::Tummy;
+use sqlx::{query, query_scalar};
+
+impl Tummy {
+    /// Whether Postgres has a text search configuration named `config`.
+    pub async fn search_config_exists(&self, config: &str) -> Result<bool, sqlx::Error> {
+        query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM pg_ts_config WHERE cfgname = $1) AS "exists!""#,
+            config
+        )
+            .fetch_one(&self.tummy_conn_pool)
+            .await
+    }
+
+    /// Sets the configuration of channels without one of their own. Returns
+    /// `true` if it changed, in which case messages have to be reindexed.
+    pub async fn set_default_search_config(&self, config: &str) -> Result<bool, sqlx::Error> {
+        let result = query!(
+            r#"
+            UPDATE search_settings
+            SET default_config = $1::text::regconfig
+            WHERE default_config <> $1::text::regconfig
+            "#,
+            config
+        )
+            .execute(&self.tummy_conn_pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Sets the configuration of a channel, or with `None` makes it use the
+    /// default one. Returns `false` if there is no such channel.
+    pub async fn set_channel_search_config(
+        &self,
+        channel_id: &str,
+        config: Option<&str>,
+    ) -> Result<bool, sqlx::Error> {
+        let result = query!(
+            "UPDATE channels SET search_config = $2 WHERE id = $1",
+            channel_id,
+            config
+        )
+            .execute(&self.tummy_conn_pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Rebuilds the tsvectors of the messages and threads of `channel_id`, or of
+    /// all channels, that are not indexed with their channel's configuration.
+    /// Returns the number of reindexed messages.
+    pub async fn reindex_search(&self, channel_id: Option<&str>) -> Result<i32, sqlx::Error> {
+        query_scalar!(r#"SELECT reindex_search($1) AS "reindexed!""#, channel_id)
+            .fetch_one(&self.tummy_conn_pool)
+            .await
+    }
+}
@@ -0,0 +1,82 @@
+//! Queries for the `sessions` table, used by the Postgres session store.
+
+use super::dbmodels::DBSession;
+use super::tummy::Tummy;
+use sqlx::{query, query_as, types::chrono::NaiveDateTime};
+
+impl Tummy {
+    pub async fn get_session(&self, session_id: &str) -> Result<Option<DBSession>, sqlx::Error> {
+        query_as!(
+            DBSession,
+            "SELECT id, user_id, team_id, access_token, created_at, validated_at, expires_at FROM sessions WHERE id = $1",
+            session_id
+        )
+            .fetch_optional(&self.tummy_conn_pool)
+            .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub async fn upsert_session(
+        &self,
+        session_id: &str,
+        user_id: &str,
+        team_id: Option<&str>,
+        access_token: Option<&str>,
+        created_at: NaiveDateTime,
+        validated_at: NaiveDateTime,
+        expires_at: NaiveDateTime,
+    ) -> Result<(), sqlx::Error> {
+        query!(
+            r#"
+            INSERT INTO sessions (id, user_id, team_id, access_token, created_at, validated_at, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (id) DO UPDATE SET
+                user_id = EXCLUDED.user_id,
+                team_id = EXCLUDED.team_id,
+                access_token = EXCLUDED.access_token,
+                validated_at = EXCLUDED.validated_at,
+                expires_at = EXCLUDED.expires_at
+            "#,
+            session_id,
+            user_id,
+            team_id,
+            access_token,
+            created_at,
+            validated_at,
+            expires_at
+        )
+            .execute(&self.tummy_conn_pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Returns whether the session exists.
+    pub async fn set_session_validated_at(
+        &self,
+        session_id: &str,
+        validated_at: NaiveDateTime,
+    ) -> Result<bool, sqlx::Error> {
+        let result = query!(
+            "UPDATE sessions SET validated_at = $2 WHERE id = $1",
+            session_id,
+            validated_at
+        )
+            .execute(&self.tummy_conn_pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn delete_session(&self, session_id: &str) -> Result<(), sqlx::Error> {
+        query!("DELETE FROM sessions WHERE id = $1", session_id)
+            .execute(&self.tummy_conn_pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_expired_sessions(&self, now: NaiveDateTime) -> Result<u64, sqlx::Error> {
+        let result = query!("DELETE FROM sessions WHERE expires_at <= $1", now)
+            .execute(&self.tummy_conn_pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
@@ -0,0 +1,147 @@
+//! Autocompletion of users, channels and search terms for the search bar.
+
+use super::dbmodels::DBSuggestion;
+use super::tummy::{escape_like, push_visible_channels, Tummy};
+use crate::types::SuggestionKind;
+use sqlx::{Postgres, QueryBuilder};
+use std::time::Duration;
+
+impl Tummy {
+    /// Suggests up to `limit` users, channels and terms of the given `kinds`
+    /// that start with or are similar to `text`, best first.
+    /// Names that start with `text` rank above ones that only resemble it.
+    /// Only channels `viewer` may see and terms from public channels are
+    /// suggested. The query is cancelled after `timeout`.
+    pub async fn suggest(
+        &self,
+        viewer: Option<&str>,
+        text: &str,
+        kinds: &[SuggestionKind],
+        limit: i64,
+        timeout: Duration,
+    ) -> Result<Vec<DBSuggestion>, sqlx::Error> {
+        let text = text.to_lowercase();
+        let prefix = format!("{}%", escape_like(&text));
+
+        let mut builder: QueryBuilder<Postgres> =
+            QueryBuilder::new("SELECT kind, value, label, detail, score FROM (");
+        let mut separated = false;
+        for kind in kinds {
+            if separated {
+                builder.push(" UNION ALL ");
+            }
+            separated = true;
+
+            match kind {
+                SuggestionKind::User => {
+                    builder.push(
+                        r#"
+                        (SELECT 'user' AS kind, u.id AS value, u.name AS label,
+                            COALESCE(NULLIF(u.display_name, ''), NULLIF(u.real_name, '')) AS detail,
+                            (CASE WHEN lower(u.name) LIKE "#,
+                    );
+                    builder.push_bind(prefix.clone());
+                    builder.push(" OR lower(u.display_name) LIKE ");
+                    builder.push_bind(prefix.clone());
+                    builder.push(" OR lower(u.real_name) LIKE ");
+                    builder.push_bind(prefix.clone());
+                    builder.push(" THEN 1.0 ELSE 0.0 END + GREATEST(word_similarity(");
+                    builder.push_bind(text.clone());
+                    builder.push(", lower(u.name)), word_similarity(");
+                    builder.push_bind(text.clone());
+                    builder.push(", lower(u.display_name)), word_similarity(");
+                    builder.push_bind(text.clone());
+                    builder.push(
+                        r#", lower(u.real_name))))::float8 AS score
+                        FROM users u
+                        WHERE lower(u.name) LIKE "#,
+                    );
+                    builder.push_bind(prefix.clone());
+                    builder.push(" OR lower(u.display_name) LIKE ");
+                    builder.push_bind(prefix.clone());
+                    builder.push(" OR lower(u.real_name) LIKE ");
+                    builder.push_bind(prefix.clone());
+                    builder.push(" OR ");
+                    builder.push_bind(text.clone());
+                    builder.push(" <% lower(u.name) OR ");
+                    builder.push_bind(text.clone());
+                    builder.push(" <% lower(u.display_name) OR ");
+                    builder.push_bind(text.clone());
+                    builder.push(" <% lower(u.real_name) ORDER BY score DESC, label LIMIT ");
+                    builder.push_bind(limit);
+                    builder.push(")");
+                }
+                SuggestionKind::Channel => {
+                    // Topics and purposes are longer texts that match more
+                    // easily, so they count for half as much as the name.
+                    builder.push(
+                        r#"
+                        (SELECT 'channel' AS kind, c.id AS value, c.name AS label,
+                            COALESCE(NULLIF(c.topic, ''), NULLIF(c.purpose, '')) AS detail,
+                            (CASE WHEN lower(c.name) LIKE "#,
+                    );
+                    builder.push_bind(prefix.clone());
+                    builder.push(" THEN 1.0 ELSE 0.0 END + GREATEST(word_similarity(");
+                    builder.push_bind(text.clone());
+                    builder.push(", lower(c.name)), word_similarity(");
+                    builder.push_bind(text.clone());
+                    builder.push(", lower(COALESCE(c.topic, ''))) / 2, word_similarity(");
+                    builder.push_bind(text.clone());
+                    builder.push(
+                        r#", lower(COALESCE(c.purpose, ''))) / 2))::float8 AS score
+                        FROM channels c
+                        WHERE (lower(c.name) LIKE "#,
+                    );
+                    builder.push_bind(prefix.clone());
+                    builder.push(" OR ");
+                    builder.push_bind(text.clone());
+                    builder.push(" <% lower(c.name) OR ");
+                    builder.push_bind(text.clone());
+                    builder.push(" <% lower(c.topic) OR ");
+                    builder.push_bind(text.clone());
+                    builder.push(" <% lower(c.purpose))");
+                    push_visible_channels(&mut builder, "c.id", viewer);
+                    builder.push(" ORDER BY score DESC, label LIMIT ");
+                    builder.push_bind(limit);
+                    builder.push(")");
+                }
+                SuggestionKind::Term => {
+                    // Frequent terms win ties.
+                    builder.push(
+                        r#"
+                        (SELECT 'term' AS kind, v.spelling AS value, v.spelling AS label, NULL AS detail,
+                            (CASE WHEN v.spelling LIKE "#,
+                    );
+                    builder.push_bind(prefix.clone());
+                    builder.push(" THEN 1.0 ELSE 0.0 END + similarity(v.spelling, ");
+                    builder.push_bind(text.clone());
+                    builder.push(
+                        r#"))::float8 AS score
+                        FROM search_vocabulary v
+                        WHERE v.spelling IS NOT NULL AND (v.spelling LIKE "#,
+                    );
+                    builder.push_bind(prefix.clone());
+                    builder.push(" OR v.spelling % ");
+                    builder.push_bind(text.clone());
+                    builder.push(") ORDER BY score DESC, v.ndoc DESC, label LIMIT ");
+                    builder.push_bind(limit);
+                    builder.push(")");
+                }
+            }
+        }
+        builder.push(") AS suggestions ORDER BY score DESC, label LIMIT ");
+        builder.push_bind(limit);
+
+        let mut transaction = self.tummy_conn_pool.begin().await?;
+        sqlx::query("SELECT set_config('statement_timeout', $1, true)")
+            .bind(format!("{}ms", timeout.as_millis()))
+            .execute(&mut *transaction)
+            .await?;
+        let suggestions = builder
+            .build_query_as::<DBSuggestion>()
+            .fetch_all(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+        Ok(suggestions)
+    }
+}
@@ -1,17 +1,17 @@
-use super::dbmodels::{DBChannel, DBParentMessage, DBReply, DBUser, DBSearchResult};
+use super::dbmodels::{DBChannel, DBParentMessage, DBReply, DBUser};
 use crate::env::EnvVars;
-use crate::types::{Channel, Message, User, SearchResult};
+use crate::types::{Channel, Message, User};
 use sqlx::{
     postgres::PgPoolOptions,
     query_as,
     types::chrono::{self, NaiveDateTime},
-    PgPool,
+    PgPool, Postgres, QueryBuilder,
 };
 use std::time::Duration;
 
 #[derive(Clone)]
 pub struct Tummy {
-    tummy_conn_pool: PgPool,
+    pub(super) tummy_conn_pool: PgPool,
 }
 
 pub(crate) trait SlackDateTime {
@@ -30,6 +30,26 @@ impl SlackDateTime for NaiveDateTime {
     }
 }
 
+/// Escapes the `LIKE` wildcards in `text`, which are common in user names and code.
+pub(super) fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+/// Restricts `column` to the channels `viewer` may see: public channels, plus
+/// the private channels and DMs they are a member of.
+pub(super) fn push_visible_channels<'a>(
+    builder: &mut QueryBuilder<'a, Postgres>,
+    column: &str,
+    viewer: Option<&'a str>,
+) {
+    builder.push(format!(
+        " AND {} IN (SELECT id FROM channels WHERE NOT is_private OR id IN (SELECT channel_id FROM channel_members WHERE user_id = ",
+        column
+    ));
+    builder.push_bind(viewer);
+    builder.push("))");
+}
+
 impl Tummy {
     pub async fn init(env_vars: &EnvVars) -> Self {
         let tummy_conn_string = format!(
@@ -56,8 +76,20 @@ impl Tummy {
         Self { tummy_conn_pool }
     }
 
-    pub async fn get_all_channels(&self) -> color_eyre::Result<Vec<Channel>> {
-        let db_channels = query_as!(DBChannel, "SELECT * FROM channels ORDER BY name ASC")
+    /// Fetches the channels `viewer` may see: all public channels, plus the
+    /// private channels and DMs they are a member of.
+    pub async fn get_all_channels(&self, viewer: Option<&str>) -> color_eyre::Result<Vec<Channel>> {
+        let db_channels = query_as!(
+            DBChannel,
+            r#"
+            SELECT c.* FROM channels AS c
+            WHERE
+                NOT c.is_private
+                OR EXISTS (SELECT 1 FROM channel_members AS cm WHERE cm.channel_id = c.id AND cm.user_id = $1)
+            ORDER BY c.name ASC
+            "#,
+            viewer
+        )
             .fetch_all(&self.tummy_conn_pool)
             .await?;
 
@@ -72,275 +104,36 @@ impl Tummy {
         Ok(db_channels.into_iter().map(User::from).collect())
     }
 
-    pub async fn get_channel_info(&self, channel_id: &str) -> Result<Channel, sqlx::Error> {
+    pub async fn get_channel_info(
+        &self,
+        channel_id: &str,
+        viewer: Option<&str>,
+    ) -> Result<Channel, sqlx::Error> {
         let channel = query_as!(
             DBChannel,
-            "SELECT * FROM channels WHERE id = $1",
-            channel_id
+            r#"
+            SELECT c.* FROM channels AS c
+            WHERE
+                c.id = $1
+                AND (
+                    NOT c.is_private
+                    OR EXISTS (SELECT 1 FROM channel_members AS cm WHERE cm.channel_id = c.id AND cm.user_id = $2)
+                )
+            "#,
+            channel_id,
+            viewer
         )
             .fetch_one(&self.tummy_conn_pool)
             .await?;
         Ok(channel.into())
     }
 
-    pub async fn search_msg_text(
-        &self,
-        query_text: &str,
-        channel_id: Option<&str>,
-        user_id: Option<&str>,
-        limit: i64,
-        before: Option<NaiveDateTime>,
-        after: Option<NaiveDateTime>,
-    ) -> color_eyre::Result<Vec<SearchResult>> {
-        println!("RRF Search with User: {:?}, Channel: {:?}", user_id, channel_id);
-
-        let is_text_search = !query_text.trim().is_empty();
-        if !is_text_search {
-            let mut builder: sqlx::QueryBuilder<sqlx::Postgres> = sqlx::QueryBuilder::new(
-                r#"
-            SELECT
-                m.channel_id,
-                c.name AS channel_name,
-                m.user_id,
-                m.msg_text,
-                m.ts,
-                m.thread_ts,
-                m.parent_user_id,
-                u.id,
-                u.name,
-                u.real_name,
-                u.display_name,
-                u.image_url,
-                u.email,
-                u.deleted,
-                u.is_bot,
-                (SELECT COUNT(*) FROM messages WHERE thread_ts = m.ts) as cnt,
-                NULL as parent_msg_text,
-                NULL as parent_name,
-                NULL as parent_real_name,
-                NULL as parent_display_name,
-                NULL as parent_image_url,
-                NULL as parent_email,
-                NULL as parent_deleted,
-                NULL as parent_is_bot
-            FROM
-                messages m
-            INNER JOIN
-                users u ON u.id = m.user_id
-            INNER JOIN
-                channels c ON c.id = m.channel_id
-            "#,
-            );
-            builder.push(" WHERE m.parent_user_id IS NULL OR m.parent_user_id = ''");
-
-            if let Some(cid) = channel_id {
-                builder.push(" AND m.channel_id = ");
-                builder.push_bind(cid);
-            }
-            if let Some(uid) = user_id {
-                builder.push(" AND m.user_id = ");
-                builder.push_bind(uid);
-            }
-            if let Some(bef) = before {
-                builder.push(" AND m.ts < ");
-                builder.push_bind(bef);
-            }
-            if let Some(after) = after {
-                builder.push(" AND m.ts > ");
-                builder.push_bind(after);
-            }
-
-            builder.push(" ORDER BY m.ts DESC LIMIT ");
-            builder.push_bind(limit);
-
-            let query = builder.build_query_as::<crate::db::dbmodels::DBSearchResult>();
-            let recent_messages = query.fetch_all(&self.tummy_conn_pool).await?;
-
-            return Ok(recent_messages.into_iter().map(SearchResult::from).collect());
-        }
-
-        // sanitize for tsquery
-        let sanitized_query_text = query_text
-            .replace(":", " ")
-            .replace("&", " ")
-            .replace("|", " ")
-            .replace("!", " ")
-            .replace("(", " ")
-            .replace(")", " ");
-
-        // split words
-        let search_terms: Vec<&str> = sanitized_query_text.split_whitespace().collect();
-        let full_text_query = search_terms.join(" & ");
-
-        // applies the prefix search operator only to the last term
-        let partial_text_query = if search_terms.len() > 1 {
-            let mut partial_query = search_terms[0..search_terms.len() - 1].join(" & ");
-            partial_query.push_str(&format!(" & {}:*", search_terms.last().unwrap()));
-            partial_query
-        } else {
-            format!("{}:*", search_terms.first().unwrap_or(&""))
-        };
-
-        let mut builder: sqlx::QueryBuilder<sqlx::Postgres> = sqlx::QueryBuilder::new("WITH ");
-
-        // Fuzzy
-        builder.push(r#"
-    fuzzy AS (
-        SELECT
-            ts,
-            similarity(msg_text, "#);
-        builder.push_bind(query_text);
-        builder.push(r#") as sim_score,
-            row_number() OVER (ORDER BY similarity(msg_text, "#);
-        builder.push_bind(query_text);
-        builder.push(r#") DESC) as rank_ix
-        FROM messages
-        WHERE msg_text %> "#);
-        builder.push_bind(query_text);
-        if let Some(cid) = channel_id {
-            builder.push(" AND channel_id = ");
-            builder.push_bind(cid);
-        }
-        if let Some(uid) = user_id {
-            builder.push(" AND user_id = ");
-            builder.push_bind(uid);
-        }
-        if let Some(bef) = before {
-            builder.push(" AND ts < ");
-            builder.push_bind(bef);
-        }
-        if let Some(after) = after {
-            builder.push(" AND ts > ");
-            builder.push_bind(after);
-        }
-        builder.push(r#"
-        ORDER BY rank_ix
-        LIMIT 30
-    ),
-"#);
-
-        // Full text
-        builder.push(r#"
-    full_text AS (
-        SELECT
-            ts,
-            ts_rank_cd(msg_tsv, to_tsquery('english', "#);
-        builder.push_bind(&full_text_query);
-        builder.push(r#")) as rank_score,
-            row_number() OVER (ORDER BY ts_rank_cd(msg_tsv, to_tsquery('english', "#);
-        builder.push_bind(&full_text_query);
-        builder.push(r#")) DESC) as rank_ix
-        FROM messages
-        WHERE msg_tsv @@ to_tsquery('english', "#);
-        builder.push_bind(&full_text_query);
-        builder.push(r#")"#);
-        if let Some(cid) = channel_id {
-            builder.push(" AND channel_id = ");
-            builder.push_bind(cid);
-        }
-        if let Some(uid) = user_id {
-            builder.push(" AND user_id = ");
-            builder.push_bind(uid);
-        }
-        if let Some(bef) = before {
-            builder.push(" AND ts < ");
-            builder.push_bind(bef);
-        }
-        if let Some(after) = after {
-            builder.push(" AND ts > ");
-            builder.push_bind(after);
-        }
-        builder.push(r#"
-        ORDER BY rank_ix
-        LIMIT 30
-    ),
-"#);
-
-        // Prefix search
-        builder.push(r#"
-    partial_search AS (
-        SELECT
-            ts,
-            ts_rank_cd(msg_tsv, to_tsquery('simple', "#);
-        builder.push_bind(&partial_text_query);
-        builder.push(r#")) as rank_score,
-            row_number() OVER (ORDER BY ts_rank_cd(msg_tsv, to_tsquery('simple', "#);
-        builder.push_bind(&partial_text_query);
-        builder.push(r#")) DESC) as rank_ix
-        FROM messages
-        WHERE msg_tsv @@ to_tsquery('simple', "#);
-        builder.push_bind(&partial_text_query);
-        builder.push(r#")"#);
-        if let Some(cid) = channel_id {
-            builder.push(" AND channel_id = ");
-            builder.push_bind(cid);
-        }
-        if let Some(uid) = user_id {
-            builder.push(" AND user_id = ");
-            builder.push_bind(uid);
-        }
-        if let Some(bef) = before {
-            builder.push(" AND ts < ");
-            builder.push_bind(bef);
-        }
-        if let Some(after) = after {
-            builder.push(" AND ts > ");
-            builder.push_bind(after);
-        }
-        builder.push(r#"
-        LIMIT 30
-    )
-"#);
-
-        // RRF
-        builder.push(r#"
-    SELECT
-        m.channel_id, channel.name AS channel_name, m.user_id, m.msg_text, m.ts, m.thread_ts, m.parent_user_id,
-        u.id, u.name, u.real_name, u.display_name, u.image_url, u.email, u.deleted, u.is_bot,
-        c.cnt,
-        CASE WHEN parent_u.id IS NOT NULL THEN parent_m.msg_text ELSE NULL END as "parent_msg_text",
-        CASE WHEN parent_u.is_bot IS NOT NULL THEN parent_u.name ELSE NULL END as "parent_name",
-        CASE WHEN parent_u.id IS NOT NULL THEN parent_u.real_name ELSE NULL END as "parent_real_name",
-        CASE WHEN parent_u.id IS NOT NULL THEN parent_u.display_name ELSE NULL END as "parent_display_name",
-        CASE WHEN parent_u.id IS NOT NULL THEN parent_u.image_url ELSE NULL END as "parent_image_url",
-        CASE WHEN parent_u.id IS NOT NULL THEN parent_u.email ELSE NULL END as "parent_email",
-        CASE WHEN parent_u.id IS NOT NULL THEN parent_u.deleted ELSE NULL END as "parent_deleted",
-        CASE WHEN parent_u.id IS NOT NULL THEN parent_u.is_bot ELSE NULL END as "parent_is_bot"
-    FROM
-        fuzzy
-        FULL OUTER JOIN full_text ON fuzzy.ts = full_text.ts
-        FULL OUTER JOIN partial_search ON COALESCE(fuzzy.ts, full_text.ts) = partial_search.ts
-        JOIN messages m ON COALESCE(fuzzy.ts, full_text.ts, partial_search.ts) = m.ts
-        INNER JOIN users AS u ON u.id = m.user_id
-        INNER JOIN channels AS channel ON channel.id = m.channel_id
-        LEFT JOIN (SELECT COUNT(*) as cnt, thread_ts FROM messages WHERE parent_user_id != '' GROUP BY thread_ts) AS c ON m.thread_ts = c.thread_ts
-        LEFT JOIN messages AS parent_m ON m.thread_ts = parent_m.ts AND parent_m.parent_user_id = ''
-        LEFT JOIN users AS parent_u ON parent_m.user_id = parent_u.id
-    ORDER BY
-        -- Reciprocal Rank Fusion (RRF)
-        -- The k value (e.g., 60) and weights can be tuned.
-        COALESCE(1.0 / (60 + fuzzy.rank_ix), 0.0) * 1.0 +
-        COALESCE(1.0 / (60 + full_text.rank_ix), 0.0) * 1.0 +
-        COALESCE(1.0 / (60 + partial_search.rank_ix), 0.0) * 1.0
-        DESC
-"#);
-
-        builder.push(" LIMIT ");
-        builder.push_bind(limit);
-
-        let query = builder.build_query_as::<DBSearchResult>();
-        let messages = query.fetch_all(&self.tummy_conn_pool).await?;
-
-        Ok(messages.into_iter().map(SearchResult::from).collect())
-    }
-
-
-
     pub async fn fetch_replies(
         &self,
         message_ts: &str,
         channel_id: &str,
         user_id: &str,
+        viewer: Option<&str>,
     ) -> Result<Vec<Message>, sqlx::Error> {
         let replies = query_as!(
             DBReply,
@@ -367,12 +160,17 @@ impl Tummy {
             INNER JOIN channels AS c ON c.id = m.channel_id
             WHERE
                 m.thread_ts = $1 AND m.channel_id = $2 AND m.parent_user_id = $3
+                AND (
+                    NOT c.is_private
+                    OR EXISTS (SELECT 1 FROM channel_members AS cm WHERE cm.channel_id = c.id AND cm.user_id = $4)
+                )
             ORDER BY
                 m.ts ASC
             "#,
             chrono::NaiveDateTime::from_pg_ts(message_ts),
             channel_id,
-            user_id
+            user_id,
+            viewer
         )
             .fetch_all(&self.tummy_conn_pool)
             .await?;
@@ -384,6 +182,7 @@ impl Tummy {
         channel_id: &str,
         before_msg_timestamp: &Option<chrono::NaiveDateTime>,
         msgs_per_page: &u32,
+        viewer: Option<&str>,
     ) -> Result<Vec<Message>, sqlx::Error> {
         let fetched_messages = if let Some(timestamp) = before_msg_timestamp {
             // This is the backward pagination case.
@@ -429,13 +228,18 @@ impl Tummy {
             ) AS c ON m.ts = c.join_ts AND m.user_id = c.parent_user_id
             WHERE
                 m.channel_id = $1 AND m.ts < $2 AND m.parent_user_id = ''
+                AND (
+                    NOT ch.is_private
+                    OR EXISTS (SELECT 1 FROM channel_members AS cm WHERE cm.channel_id = ch.id AND cm.user_id = $4)
+                )
             ORDER BY
                 ts DESC
             LIMIT $3
             "#,
             channel_id,
             timestamp,
-            *msgs_per_page as i64
+            *msgs_per_page as i64,
+            viewer
         )
                 .fetch_all(&self.tummy_conn_pool)
                 .await?
@@ -482,12 +286,17 @@ impl Tummy {
             ) AS c ON m.ts = c.join_ts AND m.user_id = c.parent_user_id
             WHERE
                 m.channel_id = $1 AND m.parent_user_id = ''
+                AND (
+                    NOT ch.is_private
+                    OR EXISTS (SELECT 1 FROM channel_members AS cm WHERE cm.channel_id = ch.id AND cm.user_id = $3)
+                )
             ORDER BY
                 m.ts DESC
             LIMIT $2
          ",
             channel_id,
-            *msgs_per_page as i64
+            *msgs_per_page as i64,
+            viewer
         )
                 .fetch_all(&self.tummy_conn_pool)
                 .await?
@@ -504,4 +313,15 @@ impl Tummy {
             .await?;
         Ok(user.into())
     }
+
+    pub async fn get_user_by_email(&self, email: &str) -> Result<User, sqlx::Error> {
+        let user = query_as!(
+            DBUser,
+            "SELECT * FROM users WHERE lower(email) = lower($1) ORDER BY deleted ASC, is_bot ASC LIMIT 1",
+            email
+        )
+            .fetch_one(&self.tummy_conn_pool)
+            .await?;
+        Ok(user.into())
+    }
 }
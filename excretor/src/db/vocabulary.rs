@@ -0,0 +1,97 @@
+//! Queries for the `search_vocabulary` table, which backs spelling suggestions.
+
+use super::dbmodels::DBSpellingCorrection;
+use super::tummy::Tummy;
+use crate::search::{self, ParsedQuery};
+use sqlx::{query_as, query_scalar};
+
+impl Tummy {
+    /// Indexes the messages imported without a tsvector and rebuilds the
+    /// vocabulary if there were any. Returns the number of newly indexed messages.
+    pub async fn refresh_search_vocabulary(&self) -> Result<i32, sqlx::Error> {
+        query_scalar!(r#"SELECT refresh_search_vocabulary() AS "indexed!""#)
+            .fetch_one(&self.tummy_conn_pool)
+            .await
+    }
+
+    /// Finds up to `per_term` words similar to each of `terms` that is not in
+    /// the vocabulary, best first. Corrections are spelled as they most often
+    /// appear in the archive rather than stemmed.
+    pub async fn spelling_corrections(
+        &self,
+        terms: &[String],
+        per_term: i64,
+    ) -> Result<Vec<DBSpellingCorrection>, sqlx::Error> {
+        query_as!(
+            DBSpellingCorrection,
+            r#"
+            SELECT t.term AS "term!", c.word AS "word!"
+            FROM UNNEST($1::TEXT[]) WITH ORDINALITY AS t(term, ix)
+            CROSS JOIN LATERAL (
+                SELECT tsvector_to_array(to_tsvector(default_search_config(), t.term)) AS lexemes
+            ) AS l
+            CROSS JOIN LATERAL (
+                SELECT COALESCE(v.spelling, v.word) AS word, v.ndoc, similarity(v.word, l.lexemes[1]) AS score
+                FROM search_vocabulary v
+                WHERE v.word % l.lexemes[1]
+                ORDER BY score DESC, v.ndoc DESC
+                LIMIT $2
+            ) AS c
+            WHERE cardinality(l.lexemes) = 1
+                AND NOT EXISTS (SELECT 1 FROM search_vocabulary v WHERE v.word = l.lexemes[1])
+            ORDER BY t.ix, c.score DESC, c.ndoc DESC
+            "#,
+            terms,
+            per_term
+        )
+            .fetch_all(&self.tummy_conn_pool)
+            .await
+    }
+
+    /// Up to `limit` versions of `input`, parsed as `query`, with its misspelled
+    /// words corrected. The first one uses the best correction of every word.
+    pub async fn suggest_queries(
+        &self,
+        input: &str,
+        query: &ParsedQuery,
+        limit: usize,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        // Only plain words can be misspelled; numbers, identifiers and URLs
+        // are searched as typed.
+        let terms: Vec<String> = query
+            .terms
+            .iter()
+            .filter(|term| term.chars().count() >= 3 && term.chars().all(char::is_alphabetic))
+            .cloned()
+            .collect();
+        if terms.is_empty() || limit == 0 {
+            return Ok(Vec::new());
+        }
+
+        let corrections = self.spelling_corrections(&terms, limit as i64).await?;
+        let mut candidates: Vec<(&str, Vec<&str>)> = Vec::new();
+        for correction in &corrections {
+            match candidates.iter_mut().find(|(term, _)| *term == correction.term) {
+                Some((_, words)) => {
+                    if !words.contains(&correction.word.as_str()) {
+                        words.push(&correction.word);
+                    }
+                }
+                None => candidates.push((&correction.term, vec![&correction.word])),
+            }
+        }
+
+        let mut suggestions = Vec::new();
+        for i in 0..limit {
+            let replacements: Vec<(&str, &str)> = candidates
+                .iter()
+                .map(|(term, words)| (*term, words[i.min(words.len() - 1)]))
+                .collect();
+            let suggestion = search::query::correct(input, &replacements);
+            if suggestion != input && !suggestions.contains(&suggestion) {
+                suggestions.push(suggestion);
+            }
+        }
+        Ok(suggestions)
+    }
+}
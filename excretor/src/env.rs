@@ -1,6 +1,49 @@
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::builder::{OsStringValueParser, TypedValueParser};
+use clap::{Parser, ValueEnum};
+
+use crate::search::Ranking;
+
+/// Where verified sessions are cached.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionBackend {
+    /// In-process cache, lost on restart.
+    Memory,
+    /// The `sessions` table in tummy, shared between excretor instances.
+    Postgres,
+}
+
+/// Which login provider to use.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuthProviderKind {
+    /// Slack OAuth v2.
+    Slack,
+    /// A generic OpenID Connect provider.
+    Oidc,
+}
+
+/// Which embedding provider computes vectors for semantic search.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmbeddingProviderKind {
+    /// Semantic search is disabled.
+    None,
+    /// An OpenAI-compatible `/embeddings` HTTP endpoint.
+    Http,
+    /// A local ONNX model run on the CPU. Requires the `onnx` feature.
+    Onnx,
+}
+
+/// Whether and how searches are logged for analytics.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchAnalyticsMode {
+    /// Searches are not logged.
+    Off,
+    /// Searches are logged without who ran them.
+    Anonymous,
+    /// Searches are logged with a salted hash of who ran them, to count users.
+    Pseudonymous,
+}
 
 #[derive(Parser, Clone)]
 #[clap(name = "tummy")]
@@ -23,6 +66,150 @@ pub struct EnvVars {
     pub slack_auth_enable: bool,
     #[arg(env, default_value = "30")]
     pub keep_logged_in_for_days: i64,
+    /// `kid:secret` pairs for signing session tokens, the active key first.
+    #[arg(env, default_value = "")]
+    pub session_signing_keys: String,
+    #[arg(env, value_enum, default_value = "slack")]
+    pub auth_provider: AuthProviderKind,
+    /// Comma-separated Slack team IDs whose members may log in. Empty allows all.
+    #[arg(env, default_value = "")]
+    pub allowed_slack_team_ids: String,
+    /// Comma-separated email domains whose users may log in. Empty allows all.
+    #[arg(env, default_value = "")]
+    pub allowed_email_domains: String,
+    #[arg(env, default_value = "")]
+    pub oidc_issuer_url: String,
+    #[arg(env, default_value = "")]
+    pub oidc_client_id: String,
+    #[arg(env)]
+    pub oidc_client_secret: Option<String>,
+    #[arg(env, default_value = "")]
+    pub oidc_redirect_uri: String,
+    #[arg(env, default_value = "openid email profile")]
+    pub oidc_scopes: String,
+    /// The ID token claim matched against `users.email`.
+    #[arg(env, default_value = "email")]
+    pub oidc_email_claim: String,
+    #[arg(env, default_value = "https://slack.com/api")]
+    pub slack_api_base_url: String,
+    #[arg(env, value_enum, default_value = "memory")]
+    pub session_backend: SessionBackend,
+    /// Seconds after which a cached session is re-checked with the login provider.
+    #[arg(env, default_value = "900")]
+    pub session_revalidate_secs: i64,
+    /// Days audit log entries are kept. 0 keeps them forever.
+    #[arg(env, default_value = "365")]
+    pub audit_retention_days: i64,
+    /// Take the client IP from `X-Forwarded-For`, when running behind a reverse proxy.
+    #[arg(env, default_value = "false", action = clap::ArgAction::Set)]
+    pub trust_proxy_headers: bool,
+    /// How many reverse proxies in front of excretor append to `X-Forwarded-For`.
+    #[arg(env, default_value = "1")]
+    pub trusted_proxy_hops: usize,
+    /// Searches a client may run per minute. 0 disables the limit.
+    #[arg(env, default_value = "30")]
+    pub rate_limit_search_per_minute: u32,
+    /// Searches a client may run in a burst.
+    #[arg(env, default_value = "10")]
+    pub rate_limit_search_burst: u32,
+    /// Channel, thread and message page loads a client may make per minute. 0 disables the limit.
+    #[arg(env, default_value = "300")]
+    pub rate_limit_pagination_per_minute: u32,
+    /// Channel, thread and message page loads a client may make in a burst.
+    #[arg(env, default_value = "60")]
+    pub rate_limit_pagination_burst: u32,
+    /// Autocomplete requests a client may make per minute. 0 disables the limit.
+    #[arg(env, default_value = "300")]
+    pub rate_limit_suggest_per_minute: u32,
+    /// Autocomplete requests a client may make in a burst.
+    #[arg(env, default_value = "60")]
+    pub rate_limit_suggest_burst: u32,
+    /// Login attempts a client may make per minute. 0 disables the limit.
+    #[arg(env, default_value = "10")]
+    pub rate_limit_auth_per_minute: u32,
+    /// Login attempts a client may make in a burst.
+    #[arg(env, default_value = "10")]
+    pub rate_limit_auth_burst: u32,
+    /// Text search configuration (language) of full-text search, e.g. `english`
+    /// or `german`, for channels without one of their own.
+    #[arg(env, default_value = "english")]
+    pub search_language: String,
+    /// The `k` of the Reciprocal Rank Fusion ranking search results.
+    #[arg(env, default_value = "60")]
+    pub search_rrf_k: f64,
+    /// Weight of trigram similarity matches in search ranking.
+    #[arg(env, default_value = "1.0")]
+    pub search_weight_fuzzy: f64,
+    /// Weight of full-text matches in search ranking.
+    #[arg(env, default_value = "1.0")]
+    pub search_weight_full_text: f64,
+    /// Weight of prefix matches in search ranking.
+    #[arg(env, default_value = "1.0")]
+    pub search_weight_prefix: f64,
+    /// Weight of semantic (embedding) matches in search ranking. Only used
+    /// with an embedding provider.
+    #[arg(env, default_value = "1.0")]
+    pub search_weight_semantic: f64,
+    /// Largest cosine distance between a query and a message for a semantic match.
+    #[arg(env, default_value = "0.5")]
+    pub search_semantic_max_distance: f64,
+    /// Computes embeddings for semantic search. `none` disables it.
+    #[arg(env, value_enum, default_value = "none")]
+    pub embedding_provider: EmbeddingProviderKind,
+    /// Name of the embedding model. Changing it re-embeds all messages.
+    #[arg(env, default_value = "all-MiniLM-L6-v2")]
+    pub embedding_model: String,
+    /// Number of dimensions of the model's embeddings.
+    #[arg(env, default_value = "384")]
+    pub embedding_dimensions: usize,
+    /// Base URL of the OpenAI-compatible embeddings API, without `/embeddings`.
+    #[arg(env, default_value = "http://localhost:8080/v1")]
+    pub embedding_api_url: String,
+    /// Bearer token for the embeddings API, if it needs one.
+    #[arg(env)]
+    pub embedding_api_key: Option<String>,
+    /// Path of the ONNX model file for the `onnx` provider.
+    #[arg(env, value_parser = OsStringValueParser::new().map(PathBuf::from))]
+    pub embedding_onnx_model_path: Option<PathBuf>,
+    /// Path of the model's `tokenizer.json` for the `onnx` provider.
+    #[arg(env, value_parser = OsStringValueParser::new().map(PathBuf::from))]
+    pub embedding_onnx_tokenizer_path: Option<PathBuf>,
+    /// Number of messages embedded at once.
+    #[arg(env, default_value = "32")]
+    pub embedding_batch_size: usize,
+    /// Seconds between checks for messages without embeddings.
+    #[arg(env, default_value = "300")]
+    pub embedding_backfill_interval_secs: u64,
+    /// Inserted before each match in search result highlights.
+    #[arg(env, default_value = "<mark>")]
+    pub search_highlight_start: String,
+    /// Inserted after each match in search result highlights.
+    #[arg(env, default_value = "</mark>")]
+    pub search_highlight_stop: String,
+    /// Seconds between runs of search alerts against newly indexed messages.
+    #[arg(env, default_value = "60")]
+    pub search_alert_interval_secs: u64,
+    /// Days search alert matches are kept in users' feeds. 0 keeps them forever.
+    #[arg(env, default_value = "30")]
+    pub search_alert_retention_days: i64,
+    /// Comma-separated hosts search alerts may be POSTed to. Empty disables webhooks.
+    #[arg(env, default_value = "")]
+    pub search_alert_webhook_hosts: String,
+    /// Whether and how search queries are logged for analytics.
+    #[arg(env, value_enum, default_value = "anonymous")]
+    pub search_analytics: SearchAnalyticsMode,
+    /// Secret mixed into the hashes of user IDs with `SEARCH_ANALYTICS=pseudonymous`.
+    #[arg(env, default_value = "")]
+    pub search_analytics_salt: String,
+    /// Days logged search queries are kept. 0 keeps them forever.
+    #[arg(env, default_value = "90")]
+    pub search_analytics_retention_days: i64,
+    /// Milliseconds an autocomplete query may take before it is cancelled.
+    #[arg(env, default_value = "200")]
+    pub suggest_timeout_ms: u64,
+    /// Comma-separated IDs of users who are always admins.
+    #[arg(env, default_value = "")]
+    pub admin_users: String,
     #[arg(env, default_value = "postgres://localhost/tummy")]
     pub database_url: String,
     #[arg(env, default_value = "assets/")]
@@ -43,6 +230,47 @@ impl EnvVars {
     /// Processes the environment variables after reading.
     pub fn process(mut self) -> Result<Self, Box<dyn std::error::Error>> {
         self.static_assets_dir = self.static_assets_dir.canonicalize()?;
+        self.slack_api_base_url = self.slack_api_base_url.trim_end_matches('/').to_owned();
+        if self.auth_provider == AuthProviderKind::Oidc
+            && (self.oidc_issuer_url.is_empty()
+                || self.oidc_client_id.is_empty()
+                || self.oidc_redirect_uri.is_empty())
+        {
+            return Err(
+                "OIDC_ISSUER_URL, OIDC_CLIENT_ID and OIDC_REDIRECT_URI are required when AUTH_PROVIDER=oidc."
+                    .into(),
+            );
+        }
+        self.embedding_api_url = self.embedding_api_url.trim_end_matches('/').to_owned();
+        // docker-compose passes unset variables as empty ones
+        self.embedding_api_key = self.embedding_api_key.filter(|key| !key.is_empty());
+        self.embedding_onnx_model_path =
+            self.embedding_onnx_model_path.filter(|path| !path.as_os_str().is_empty());
+        self.embedding_onnx_tokenizer_path =
+            self.embedding_onnx_tokenizer_path.filter(|path| !path.as_os_str().is_empty());
+        if self.embedding_provider == EmbeddingProviderKind::Onnx
+            && (self.embedding_onnx_model_path.is_none() || self.embedding_onnx_tokenizer_path.is_none())
+        {
+            return Err(
+                "EMBEDDING_ONNX_MODEL_PATH and EMBEDDING_ONNX_TOKENIZER_PATH are required when EMBEDDING_PROVIDER=onnx."
+                    .into(),
+            );
+        }
+        if self.embedding_dimensions == 0 || self.embedding_batch_size == 0 {
+            return Err("EMBEDDING_DIMENSIONS and EMBEDDING_BATCH_SIZE must be above 0.".into());
+        }
+        if self.search_analytics == SearchAnalyticsMode::Pseudonymous
+            && self.search_analytics_salt.len() < 32
+        {
+            return Err(
+                "SEARCH_ANALYTICS_SALT of at least 32 characters is required when SEARCH_ANALYTICS=pseudonymous."
+                    .into(),
+            );
+        }
+        if self.search_alert_interval_secs == 0 {
+            return Err("SEARCH_ALERT_INTERVAL_SECS must be above 0.".into());
+        }
+        Ranking::from_env(&self).validate()?;
         Ok(self)
     }
 }
@@ -1,14 +1,25 @@
 use clap::Parser;
+mod auth;
 mod env;
 mod db;
 mod api;
+mod audit;
+mod search;
 mod types;
 
+use std::fmt::Display;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Duration;
 use tracing::info;
 
 use tracing_subscriber::prelude::*;
 
+use audit::AuditLog;
+use auth::keys::SessionKeys;
+use auth::sessions::Sessions;
 use db::tummy::Tummy;
+use search::{Embeddings, SearchAlerts, SearchAnalytics};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -19,13 +30,121 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing_subscriber::registry().with(stdout_log).init();
 
     let db_connection = Tummy::init(&env_vars).await;
-    let app = api::routes::get_excretor_router(db_connection, env_vars.clone());
+    if !db_connection.search_config_exists(&env_vars.search_language).await? {
+        return Err(format!(
+            "SEARCH_LANGUAGE {} is not a text search configuration of tummy.",
+            env_vars.search_language
+        )
+        .into());
+    }
+    if db_connection
+        .set_default_search_config(&env_vars.search_language)
+        .await?
+    {
+        tracing::warn!(
+            "The search language changed to {}; reindex search for existing messages to use it.",
+            env_vars.search_language
+        );
+    }
+    let keys = SessionKeys::from_env(&env_vars)?;
+    let sessions = Sessions::from_env(&env_vars, &db_connection, keys.clone());
+
+    // Periodically drop expired sessions so the store does not grow unbounded.
+    let janitor_sessions = sessions.clone();
+    spawn_periodic(Duration::from_secs(60 * 60), "Purging expired sessions", move || {
+        let sessions = janitor_sessions.clone();
+        async move { sessions.purge_expired().await }
+    });
+
+    // Likewise drop audit log entries past their retention period.
+    let audit = AuditLog::from_env(&env_vars, db_connection.clone());
+    let janitor_audit = audit.clone();
+    spawn_periodic(Duration::from_secs(24 * 60 * 60), "Purging expired audit log entries", move || {
+        let audit = janitor_audit.clone();
+        async move { audit.purge_expired().await }
+    });
+
+    // Index messages imported since the last check and refresh the spelling
+    // vocabulary, in case the importer did not.
+    let vocabulary_tummy = db_connection.clone();
+    spawn_periodic(Duration::from_secs(60 * 60), "Indexing new messages for search", move || {
+        let tummy = vocabulary_tummy.clone();
+        async move { tummy.refresh_search_vocabulary().await }
+    });
+
+    // Embed messages for semantic search, if a provider is configured.
+    let embeddings = Embeddings::from_env(&env_vars)?;
+    if let Some(embeddings) = &embeddings {
+        embeddings.init(&db_connection).await?;
+        tokio::spawn(embeddings.clone().run_backfill(db_connection.clone()));
+    }
+
+    // Run search alerts against newly indexed messages, and drop old matches
+    // daily.
+    let search_alerts = SearchAlerts::from_env(&env_vars)?;
+    tokio::spawn(search_alerts.clone().run(db_connection.clone()));
+    let janitor_alerts = search_alerts.clone();
+    let janitor_alerts_tummy = db_connection.clone();
+    spawn_periodic(Duration::from_secs(24 * 60 * 60), "Purging expired search alert matches", move || {
+        let alerts = janitor_alerts.clone();
+        let tummy = janitor_alerts_tummy.clone();
+        async move { alerts.purge_expired(&tummy).await }
+    });
+
+    // Log searches for analytics, and drop old ones daily.
+    let search_analytics = SearchAnalytics::from_env(&env_vars, db_connection.clone());
+    let janitor_analytics = search_analytics.clone();
+    spawn_periodic(Duration::from_secs(24 * 60 * 60), "Purging expired search analytics entries", move || {
+        let analytics = janitor_analytics.clone();
+        async move { analytics.purge_expired().await }
+    });
+
+    let app = api::routes::get_excretor_router(
+        db_connection,
+        sessions,
+        keys,
+        audit,
+        embeddings,
+        search_alerts,
+        search_analytics,
+        env_vars.clone(),
+    );
 
     info!("Starting excretor on port {}.", env_vars.excretor_port);
     let listener =
         tokio::net::TcpListener::bind(format!("0.0.0.0:{}", env_vars.excretor_port)).await?;
     tracing::debug!("Excretor listening on {}.", listener.local_addr()?);
 
-    axum::serve(listener, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
     Ok(())
 }
+
+/// Runs a task in the background every `period`, starting right away. Failures
+/// are logged and the task runs again at the next tick.
+///
+/// # Parameters
+/// - `period`: How long to wait between runs.
+/// - `name`: What the task does, for the logs, e.g. "Purging expired sessions".
+/// - `task`: Starts a run, which resolves to how many rows it handled.
+fn spawn_periodic<F, Fut, T, E>(period: Duration, name: &'static str, task: F)
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<T, E>> + Send,
+    T: Display,
+    E: Display,
+{
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            match task().await {
+                Ok(count) => tracing::debug!("{}: {} done.", name, count),
+                Err(err) => tracing::warn!("{} failed: {}", name, err),
+            }
+        }
+    });
+}
@@ -0,0 +1,263 @@
+//! Search alerts.
+//! Saved searches that are alerts are run every `SEARCH_ALERT_INTERVAL_SECS`
+//! against the messages indexed since the last run, which tummy queues in
+//! `search_alert_queue`. New matches are added to the feed of the user who
+//! saved the search and, if the saved search has a webhook, POSTed to it as
+//! JSON. Deliveries that fail are retried on the next run.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use color_eyre::eyre::eyre;
+use reqwest::{header, redirect, Client, Url};
+use serde::Serialize;
+
+use crate::db::dbmodels::DBSavedSearch;
+use crate::db::tummy::Tummy;
+use crate::env::EnvVars;
+use crate::search::{self, Highlighter, Ranking, SearchMode, SearchOptions, SearchSort};
+use crate::types::{SavedSearch, SearchAlertMatch};
+
+/// Most queued messages checked at once.
+const BATCH_SIZE: i64 = 1000;
+
+/// Matches of a saved search fetched at once. A batch of messages is paged
+/// through until every match is recorded.
+const MATCHES_PAGE_SIZE: i64 = 100;
+
+/// Most matches POSTed to a webhook at once. Any more are POSTed on the next run.
+const MAX_DELIVERED_MATCHES: i64 = 100;
+
+/// How long a webhook may take to answer.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The JSON body POSTed to webhooks.
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    saved_search: &'a SavedSearch,
+    matches: &'a [SearchAlertMatch],
+}
+
+/// Runs search alerts and delivers their matches.
+#[derive(Clone)]
+pub struct SearchAlerts {
+    http: Client,
+    /// Hosts webhooks may be sent to, lowercased. Empty disables webhooks.
+    webhook_hosts: Arc<[String]>,
+    interval: Duration,
+    /// How long matches are kept. `None` keeps them forever.
+    retention: Option<chrono::Duration>,
+    ranking: Ranking,
+    highlighter: Highlighter,
+}
+
+impl SearchAlerts {
+    pub fn from_env(env_vars: &EnvVars) -> color_eyre::Result<Self> {
+        Ok(Self {
+            // Redirects could lead anywhere, past the allowed hosts.
+            http: Client::builder()
+                .timeout(WEBHOOK_TIMEOUT)
+                .redirect(redirect::Policy::none())
+                .build()?,
+            webhook_hosts: env_vars
+                .search_alert_webhook_hosts
+                .split(',')
+                .map(|host| host.trim().to_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect(),
+            interval: Duration::from_secs(env_vars.search_alert_interval_secs),
+            retention: (env_vars.search_alert_retention_days > 0)
+                .then(|| chrono::Duration::days(env_vars.search_alert_retention_days)),
+            ranking: Ranking::from_env(env_vars),
+            highlighter: Highlighter::from_env(env_vars),
+        })
+    }
+
+    /// Checks that alerts may be POSTed to `url`.
+    ///
+    /// # Returns
+    /// The reason for rejecting the URL, if it is not allowed.
+    pub fn check_webhook_url(&self, url: &str) -> Result<(), String> {
+        if self.webhook_hosts.is_empty() {
+            return Err("Webhooks are disabled.".to_owned());
+        }
+        let url = Url::parse(url).map_err(|_| "Invalid webhook URL.".to_owned())?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err("Webhook URLs must be http or https.".to_owned());
+        }
+        match url.host_str() {
+            Some(host) if self.webhook_hosts.contains(&host.to_lowercase()) => Ok(()),
+            _ => Err(format!(
+                "Webhooks may only be sent to {}.",
+                self.webhook_hosts.join(", ")
+            )),
+        }
+    }
+
+    /// Runs the alerts against the queued messages, batch by batch, and
+    /// records their new matches. An alert that fails is skipped for the
+    /// batch, so that it can't hold up the others. Returns the number of new
+    /// matches.
+    pub async fn check(&self, tummy: &Tummy) -> color_eyre::Result<u64> {
+        let mut matched = 0;
+        loop {
+            let queued = tummy.get_queued_alert_messages(BATCH_SIZE).await?;
+            if queued.is_empty() {
+                return Ok(matched);
+            }
+
+            for saved_search in tummy.get_alert_saved_searches().await? {
+                match self.check_saved_search(tummy, &saved_search, &queued).await {
+                    Ok(count) => matched += count,
+                    Err(err) => tracing::warn!(
+                        "Could not run the alert of saved search {}: {}",
+                        saved_search.id,
+                        err
+                    ),
+                }
+            }
+            tummy.dequeue_alert_messages(&queued).await?;
+        }
+    }
+
+    /// Runs one alert as its owner against the `queued` messages, newest
+    /// first, page by page.
+    async fn check_saved_search(
+        &self,
+        tummy: &Tummy,
+        saved_search: &DBSavedSearch,
+        queued: &[i64],
+    ) -> color_eyre::Result<u64> {
+        // Queries are checked when saved, so this only happens if the query
+        // language changed since.
+        let mut query = match search::parse(&saved_search.query) {
+            Ok(query) => query,
+            Err(error) => {
+                tracing::warn!(
+                    "Saved search {} has an invalid query: {}",
+                    saved_search.id,
+                    error.message
+                );
+                return Ok(0);
+            }
+        };
+        query.queued = Some(queued.to_vec());
+
+        let mut matched = 0;
+        let mut offset = 0;
+        loop {
+            let options = SearchOptions {
+                mode: SearchMode::Messages,
+                sort: SearchSort::Newest,
+                ranking: self.ranking,
+                explain: false,
+                offset,
+                limit: MATCHES_PAGE_SIZE,
+                highlighter: &self.highlighter,
+            };
+            let results = tummy
+                .search_messages(Some(&saved_search.user_id), &query, &options)
+                .await?;
+            matched += tummy
+                .insert_search_alert_matches(saved_search.id, &results, Utc::now().naive_utc())
+                .await?;
+            if (results.len() as i64) < MATCHES_PAGE_SIZE {
+                return Ok(matched);
+            }
+            offset += MATCHES_PAGE_SIZE;
+        }
+    }
+
+    /// POSTs the matches not yet delivered to the webhooks of their saved
+    /// searches. Returns the number of matches delivered.
+    pub async fn deliver(&self, tummy: &Tummy) -> color_eyre::Result<usize> {
+        let mut delivered = 0;
+        for saved_search in tummy.get_undelivered_saved_searches().await? {
+            let Some(webhook_url) = saved_search.webhook_url.clone() else {
+                continue;
+            };
+            // The allowed hosts may have changed since the search was saved.
+            if let Err(reason) = self.check_webhook_url(&webhook_url) {
+                tracing::warn!(
+                    "Not delivering the alerts of saved search {}: {}",
+                    saved_search.id,
+                    reason
+                );
+                continue;
+            }
+
+            let matches = tummy
+                .get_undelivered_search_alert_matches(saved_search.id, MAX_DELIVERED_MATCHES)
+                .await?;
+            // Without visible matches, the rest are skipped.
+            let last_match_id = matches.last().map_or(i64::MAX, |item| item.match_id);
+            let saved_search_id = saved_search.id;
+            if !matches.is_empty() {
+                let matches: Vec<SearchAlertMatch> = matches.into_iter().map(Into::into).collect();
+                let payload = WebhookPayload {
+                    saved_search: &saved_search.into(),
+                    matches: &matches,
+                };
+                if let Err(err) = self.post(&webhook_url, &payload).await {
+                    tracing::warn!(
+                        "Could not deliver the alerts of saved search {}: {}",
+                        saved_search_id,
+                        err
+                    );
+                    continue;
+                }
+                delivered += matches.len();
+            }
+            tummy
+                .mark_search_alert_matches_delivered(saved_search_id, last_match_id, Utc::now().naive_utc())
+                .await?;
+        }
+        Ok(delivered)
+    }
+
+    /// POSTs `payload` to a webhook as JSON.
+    async fn post(&self, url: &str, payload: &WebhookPayload<'_>) -> color_eyre::Result<()> {
+        let response = self
+            .http
+            .post(url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_string(payload)?)
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(eyre!("The webhook answered {}.", status));
+        }
+        Ok(())
+    }
+
+    /// Removes matches older than the retention period, returning how many were removed.
+    pub async fn purge_expired(&self, tummy: &Tummy) -> color_eyre::Result<u64> {
+        let Some(retention) = self.retention else {
+            return Ok(0);
+        };
+        Ok(tummy
+            .delete_search_alert_matches_before(Utc::now().naive_utc() - retention)
+            .await?)
+    }
+
+    /// Runs [`SearchAlerts::check`] and [`SearchAlerts::deliver`] forever,
+    /// every `SEARCH_ALERT_INTERVAL_SECS`.
+    pub async fn run(self, tummy: Tummy) {
+        let mut interval = tokio::time::interval(self.interval);
+        loop {
+            interval.tick().await;
+            match self.check(&tummy).await {
+                Ok(0) => {}
+                Ok(matched) => tracing::info!("Search alerts found {} new matches.", matched),
+                Err(err) => tracing::warn!("Could not check search alerts: {}", err),
+            }
+            match self.deliver(&tummy).await {
+                Ok(0) => {}
+                Ok(delivered) => tracing::info!("Delivered {} search alert matches to webhooks.", delivered),
+                Err(err) => tracing::warn!("Could not deliver search alerts: {}", err),
+            }
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! Search analytics.
+//! The first page of every search is logged in `search_query_log` with its
+//! hit count and latency, so that admins can see which queries are common,
+//! which find nothing and which are slow. Depending on `SEARCH_ANALYTICS`, who
+//! searched is left out or replaced by a salted hash of their user ID. Entries
+//! older than `search_analytics_retention_days` are purged periodically.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+
+use super::SearchMode;
+use crate::auth::AuthUser;
+use crate::db::tummy::Tummy;
+use crate::env::{EnvVars, SearchAnalyticsMode};
+
+/// How logged searches are aggregated by query.
+#[derive(Clone, Copy, Debug)]
+pub enum SearchQueryReport {
+    /// The most searched queries.
+    Top,
+    /// The queries most often searched without results.
+    ZeroResults,
+    /// The queries slowest on average, from `min_latency_ms` up.
+    Slow { min_latency_ms: f64 },
+}
+
+/// Normalizes a query for counting: lowercased, with runs of whitespace
+/// collapsed into single spaces.
+fn normalize(query: &str) -> String {
+    query.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// Logs searches and expires them.
+#[derive(Clone)]
+pub struct SearchAnalytics {
+    tummy: Tummy,
+    mode: SearchAnalyticsMode,
+    salt: Arc<str>,
+    /// How long searches are kept. `None` keeps them forever.
+    retention: Option<chrono::Duration>,
+}
+
+impl SearchAnalytics {
+    pub fn from_env(env_vars: &EnvVars, tummy: Tummy) -> Self {
+        Self {
+            tummy,
+            mode: env_vars.search_analytics,
+            salt: env_vars.search_analytics_salt.as_str().into(),
+            retention: (env_vars.search_analytics_retention_days > 0)
+                .then(|| chrono::Duration::days(env_vars.search_analytics_retention_days)),
+        }
+    }
+
+    /// The pseudonym a user's searches are logged under, if any.
+    fn pseudonym(&self, auth_user: Option<&AuthUser>) -> Option<String> {
+        if self.mode != SearchAnalyticsMode::Pseudonymous {
+            return None;
+        }
+        let auth_user = auth_user?;
+        let mut hasher = Sha256::new();
+        hasher.update(self.salt.as_bytes());
+        hasher.update(b":");
+        hasher.update(auth_user.user_id.as_bytes());
+        Some(hex::encode(hasher.finalize()))
+    }
+
+    /// Logs a search in the background. Searches go on if it cannot be
+    /// logged, unlike with the audit log.
+    ///
+    /// # Parameters
+    /// - `auth_user`: The authenticated user, if login is enabled.
+    /// - `query`: The query as typed.
+    /// - `mode`: Whether messages or threads were searched.
+    /// - `total_hits`: The number of matches.
+    /// - `latency`: How long the search took to answer.
+    pub fn record(
+        &self,
+        auth_user: Option<&AuthUser>,
+        query: &str,
+        mode: SearchMode,
+        total_hits: i64,
+        latency: Duration,
+    ) {
+        if self.mode == SearchAnalyticsMode::Off {
+            return;
+        }
+
+        let tummy = self.tummy.clone();
+        let query = normalize(query);
+        let pseudonym = self.pseudonym(auth_user);
+        tokio::spawn(async move {
+            if let Err(err) = tummy
+                .insert_search_query(
+                    Utc::now().naive_utc(),
+                    &query,
+                    pseudonym.as_deref(),
+                    mode.as_str(),
+                    total_hits,
+                    latency.as_secs_f64() * 1000.0,
+                )
+                .await
+            {
+                tracing::warn!("Could not log a search for analytics: {}", err);
+            }
+        });
+    }
+
+    /// Removes searches older than the retention period, returning how many were removed.
+    pub async fn purge_expired(&self) -> color_eyre::Result<u64> {
+        let Some(retention) = self.retention else {
+            return Ok(0);
+        };
+        Ok(self
+            .tummy
+            .delete_search_queries_before(Utc::now().naive_utc() - retention)
+            .await?)
+    }
+}
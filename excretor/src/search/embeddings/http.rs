@@ -0,0 +1,84 @@
+//! Embeddings from an OpenAI-compatible `/embeddings` endpoint.
+//! The base URL is configurable so that a local server or stub can stand in.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use color_eyre::eyre::eyre;
+use reqwest::{header, Client};
+use serde::{Deserialize, Serialize};
+
+use super::EmbeddingProvider;
+use crate::env::EnvVars;
+
+/// How long a batch may take to embed.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Serialize)]
+struct EmbeddingRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+pub struct HttpProvider {
+    http: Client,
+    api_url: String,
+    api_key: Option<String>,
+    model: String,
+}
+
+impl HttpProvider {
+    pub fn new(env_vars: &EnvVars) -> color_eyre::Result<Self> {
+        Ok(Self {
+            http: Client::builder().timeout(REQUEST_TIMEOUT).build()?,
+            api_url: env_vars.embedding_api_url.clone(),
+            api_key: env_vars.embedding_api_key.clone(),
+            model: env_vars.embedding_model.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for HttpProvider {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    async fn embed(&self, texts: &[String]) -> color_eyre::Result<Vec<Vec<f32>>> {
+        let body = serde_json::to_string(&EmbeddingRequest {
+            model: &self.model,
+            input: texts,
+        })?;
+        let mut request = self
+            .http
+            .post(format!("{}/embeddings", self.api_url))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body);
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            return Err(eyre!("The embeddings API answered {}: {}", status, body));
+        }
+
+        // The data is not guaranteed to be in input order.
+        let mut data = serde_json::from_str::<EmbeddingResponse>(&body)?.data;
+        data.sort_by_key(|data| data.index);
+        Ok(data.into_iter().map(|data| data.embedding).collect())
+    }
+}
@@ -0,0 +1,175 @@
+//! Embeddings of message texts for semantic search.
+//! A pluggable provider turns texts into vectors, which are stored with
+//! pgvector in `message_embeddings` and matched against the embedding of the
+//! search query as a fourth ranked candidate set.
+//! The provider in use is selected with the `EMBEDDING_PROVIDER` environment
+//! variable. Messages without an embedding, e.g. after an import, are embedded
+//! by a background job.
+
+mod http;
+#[cfg(feature = "onnx")]
+mod onnx;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use color_eyre::eyre::eyre;
+
+use crate::db::tummy::Tummy;
+use crate::env::{EmbeddingProviderKind, EnvVars};
+use crate::search::query::SemanticQuery;
+
+pub use http::HttpProvider;
+#[cfg(feature = "onnx")]
+pub use onnx::OnnxProvider;
+
+/// How long a search waits for its query to be embedded before going on
+/// without the semantic signal.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// An embedding model.
+#[async_trait]
+pub trait EmbeddingProvider: Send + Sync {
+    /// A short name for logs.
+    fn name(&self) -> &'static str;
+
+    /// Computes the embedding of each text, in order.
+    async fn embed(&self, texts: &[String]) -> color_eyre::Result<Vec<Vec<f32>>>;
+}
+
+/// Embeds search queries and messages with the configured provider.
+#[derive(Clone)]
+pub struct Embeddings {
+    provider: Arc<dyn EmbeddingProvider>,
+    model: Arc<str>,
+    dimensions: usize,
+    batch_size: usize,
+    max_distance: f64,
+    backfill_interval: Duration,
+}
+
+impl Embeddings {
+    /// Builds the configured provider, or `None` if semantic search is disabled.
+    pub fn from_env(env_vars: &EnvVars) -> color_eyre::Result<Option<Self>> {
+        let provider: Arc<dyn EmbeddingProvider> = match env_vars.embedding_provider {
+            EmbeddingProviderKind::None => return Ok(None),
+            EmbeddingProviderKind::Http => Arc::new(HttpProvider::new(env_vars)?),
+            #[cfg(feature = "onnx")]
+            EmbeddingProviderKind::Onnx => Arc::new(OnnxProvider::new(env_vars)?),
+            #[cfg(not(feature = "onnx"))]
+            EmbeddingProviderKind::Onnx => {
+                return Err(eyre!(
+                    "EMBEDDING_PROVIDER=onnx needs excretor to be built with the `onnx` feature."
+                ))
+            }
+        };
+
+        Ok(Some(Self {
+            provider,
+            model: env_vars.embedding_model.as_str().into(),
+            dimensions: env_vars.embedding_dimensions,
+            batch_size: env_vars.embedding_batch_size,
+            max_distance: env_vars.search_semantic_max_distance,
+            backfill_interval: Duration::from_secs(env_vars.embedding_backfill_interval_secs),
+        }))
+    }
+
+    /// Prepares tummy to store embeddings of this model, dropping the ones of
+    /// any other model. Fails if pgvector is not installed.
+    pub async fn init(&self, tummy: &Tummy) -> color_eyre::Result<()> {
+        if !tummy.embeddings_available().await? {
+            return Err(eyre!(
+                "Semantic search needs the pgvector extension to be available in tummy."
+            ));
+        }
+        if tummy
+            .use_embedding_model(&self.model, self.dimensions as i32)
+            .await?
+        {
+            tracing::info!(
+                "Switched to the {} embedding model, all messages will be embedded again.",
+                self.model
+            );
+        }
+        Ok(())
+    }
+
+    /// Embeds `texts`, checking that the provider returned what was asked for.
+    async fn embed(&self, texts: &[String]) -> color_eyre::Result<Vec<String>> {
+        let embeddings = self.provider.embed(texts).await?;
+        if embeddings.len() != texts.len() {
+            return Err(eyre!(
+                "The {} embedding provider returned {} embeddings for {} texts.",
+                self.provider.name(),
+                embeddings.len(),
+                texts.len()
+            ));
+        }
+        if let Some(embedding) = embeddings.iter().find(|embedding| embedding.len() != self.dimensions) {
+            return Err(eyre!(
+                "The {} embedding provider returned {} dimensions instead of {}.",
+                self.provider.name(),
+                embedding.len(),
+                self.dimensions
+            ));
+        }
+        Ok(embeddings.iter().map(|embedding| to_pgvector(embedding)).collect())
+    }
+
+    /// Embeds the text of a search query.
+    pub async fn semantic_query(&self, text: &str) -> color_eyre::Result<SemanticQuery> {
+        let embedding = tokio::time::timeout(QUERY_TIMEOUT, self.embed(&[text.to_owned()]))
+            .await
+            .map_err(|_| eyre!("Embedding the query took too long."))??
+            .pop()
+            .ok_or_else(|| eyre!("No embedding for the query."))?;
+
+        Ok(SemanticQuery {
+            embedding,
+            max_distance: self.max_distance,
+        })
+    }
+
+    /// Embeds the messages that have no embedding yet, batch by batch.
+    /// Returns the number of messages embedded.
+    pub async fn backfill(&self, tummy: &Tummy) -> color_eyre::Result<usize> {
+        let mut embedded = 0;
+        let mut until = None;
+        loop {
+            let messages = tummy
+                .messages_without_embeddings(until, self.batch_size as i64)
+                .await?;
+            let Some(oldest) = messages.last() else {
+                return Ok(embedded);
+            };
+            until = Some(oldest.ts);
+
+            let texts: Vec<String> = messages.iter().map(|message| message.text.clone()).collect();
+            let embeddings = self.embed(&texts).await?;
+            tummy.insert_embeddings(&messages, embeddings).await?;
+
+            embedded += messages.len();
+            tracing::debug!("Embedded {} messages for semantic search.", embedded);
+        }
+    }
+
+    /// Runs [`Embeddings::backfill`] forever, every `EMBEDDING_BACKFILL_INTERVAL_SECS`.
+    pub async fn run_backfill(self, tummy: Tummy) {
+        let mut interval = tokio::time::interval(self.backfill_interval);
+        loop {
+            interval.tick().await;
+            match self.backfill(&tummy).await {
+                Ok(0) => {}
+                Ok(embedded) => tracing::info!("Embedded {} messages for semantic search.", embedded),
+                Err(err) => tracing::warn!("Could not embed messages for semantic search: {}", err),
+            }
+        }
+    }
+}
+
+/// Formats an embedding as a pgvector literal.
+fn to_pgvector(embedding: &[f32]) -> String {
+    let values: Vec<String> = embedding.iter().map(|value| value.to_string()).collect();
+    format!("[{}]", values.join(","))
+}
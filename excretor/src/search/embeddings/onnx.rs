@@ -0,0 +1,135 @@
+//! Embeddings from a local sentence embedding model in ONNX format, run on the
+//! CPU with ONNX Runtime. The runtime library is loaded from `ORT_DYLIB_PATH`.
+//! Token embeddings are mean pooled over the attention mask and normalized, as
+//! sentence-transformers models expect.
+
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use color_eyre::eyre::eyre;
+use ort::session::Session;
+use ort::value::Tensor;
+use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
+
+use super::EmbeddingProvider;
+use crate::env::EnvVars;
+
+/// Most tokens of a text that are embedded; the rest is cut off.
+const MAX_TOKENS: usize = 256;
+
+pub struct OnnxProvider {
+    session: Arc<Mutex<Session>>,
+    tokenizer: Arc<Tokenizer>,
+}
+
+impl OnnxProvider {
+    pub fn new(env_vars: &EnvVars) -> color_eyre::Result<Self> {
+        let (Some(model_path), Some(tokenizer_path)) = (
+            &env_vars.embedding_onnx_model_path,
+            &env_vars.embedding_onnx_tokenizer_path,
+        ) else {
+            return Err(eyre!("The ONNX model and tokenizer paths are not set."));
+        };
+
+        let session = Session::builder()?.commit_from_file(model_path)?;
+        let mut tokenizer = Tokenizer::from_file(tokenizer_path).map_err(|err| eyre!(err))?;
+        tokenizer.with_padding(Some(PaddingParams::default()));
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: MAX_TOKENS,
+                ..Default::default()
+            }))
+            .map_err(|err| eyre!(err))?;
+
+        Ok(Self {
+            session: Arc::new(Mutex::new(session)),
+            tokenizer: Arc::new(tokenizer),
+        })
+    }
+}
+
+/// Runs the model on a batch of texts.
+fn embed_batch(session: &Mutex<Session>, tokenizer: &Tokenizer, texts: Vec<String>) -> color_eyre::Result<Vec<Vec<f32>>> {
+    let encodings = tokenizer.encode_batch(texts, true).map_err(|err| eyre!(err))?;
+    let batch = encodings.len();
+    let tokens = encodings.first().map_or(0, |encoding| encoding.len());
+    let shape = [batch as i64, tokens as i64];
+    let collect = |ids: fn(&tokenizers::Encoding) -> &[u32]| -> Vec<i64> {
+        encodings
+            .iter()
+            .flat_map(|encoding| ids(encoding).iter().map(|id| *id as i64))
+            .collect()
+    };
+    let attention_mask = collect(tokenizers::Encoding::get_attention_mask);
+
+    let mut session = session.lock().unwrap();
+    let has_token_types = session.inputs.iter().any(|input| input.name == "token_type_ids");
+    let mut inputs = ort::inputs![
+        "input_ids" => Tensor::from_array((shape, collect(tokenizers::Encoding::get_ids)))?,
+        "attention_mask" => Tensor::from_array((shape, attention_mask.clone()))?,
+    ];
+    if has_token_types {
+        inputs.push((
+            "token_type_ids".into(),
+            Tensor::from_array((shape, collect(tokenizers::Encoding::get_type_ids)))?.into(),
+        ));
+    }
+    let outputs = session.run(inputs)?;
+    let (output_shape, values) = outputs[0].try_extract_tensor::<f32>()?;
+
+    let embeddings: Vec<Vec<f32>> = match **output_shape {
+        // Already pooled sentence embeddings
+        [_, dimensions] => values
+            .chunks(dimensions as usize)
+            .map(|embedding| embedding.to_vec())
+            .collect(),
+        // Token embeddings, mean pooled over the tokens that are not padding
+        [_, _, dimensions] => {
+            let dimensions = dimensions as usize;
+            (0..batch)
+                .map(|text| {
+                    let mut embedding = vec![0.0; dimensions];
+                    let mut count = 0.0;
+                    for token in 0..tokens {
+                        if attention_mask[text * tokens + token] == 0 {
+                            continue;
+                        }
+                        let offset = (text * tokens + token) * dimensions;
+                        for (sum, value) in embedding.iter_mut().zip(&values[offset..offset + dimensions]) {
+                            *sum += value;
+                        }
+                        count += 1.0;
+                    }
+                    embedding.iter_mut().for_each(|sum| *sum /= f32::max(count, 1.0));
+                    embedding
+                })
+                .collect()
+        }
+        _ => return Err(eyre!("Unexpected output shape {:?} of the ONNX model.", output_shape)),
+    };
+
+    Ok(embeddings.into_iter().map(normalize).collect())
+}
+
+/// Scales an embedding to unit length.
+fn normalize(mut embedding: Vec<f32>) -> Vec<f32> {
+    let norm = embedding.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        embedding.iter_mut().for_each(|value| *value /= norm);
+    }
+    embedding
+}
+
+#[async_trait]
+impl EmbeddingProvider for OnnxProvider {
+    fn name(&self) -> &'static str {
+        "onnx"
+    }
+
+    async fn embed(&self, texts: &[String]) -> color_eyre::Result<Vec<Vec<f32>>> {
+        let session = self.session.clone();
+        let tokenizer = self.tokenizer.clone();
+        let texts = texts.to_vec();
+        tokio::task::spawn_blocking(move || embed_batch(&session, &tokenizer, texts)).await?
+    }
+}
@@ -0,0 +1,81 @@
+//! Highlighted fragments of matching messages.
+//! The database marks matches with private-use sentinel characters (see
+//! [`Highlighter::headline_options`]) in the message text stripped of its HTML
+//! tags. The sentinels are replaced with the configured markers only after the
+//! text has been escaped, so markers are the only markup in a fragment.
+
+use std::sync::Arc;
+
+use crate::env::EnvVars;
+
+/// Marks the start of a match in a headline.
+pub const START_SENTINEL: char = '\u{E000}';
+/// Marks the end of a match in a headline.
+pub const STOP_SENTINEL: char = '\u{E001}';
+/// Separates the fragments of a headline.
+pub const FRAGMENT_SENTINEL: char = '\u{E002}';
+
+/// Most fragments returned per message.
+const MAX_FRAGMENTS: usize = 3;
+
+#[derive(Clone)]
+pub struct Highlighter {
+    start: Arc<str>,
+    stop: Arc<str>,
+}
+
+impl Highlighter {
+    pub fn from_env(env_vars: &EnvVars) -> Self {
+        Self {
+            start: env_vars.search_highlight_start.as_str().into(),
+            stop: env_vars.search_highlight_stop.as_str().into(),
+        }
+    }
+
+    /// The sentinel characters, which are removed from the text before it is highlighted.
+    pub fn sentinels() -> String {
+        [START_SENTINEL, STOP_SENTINEL, FRAGMENT_SENTINEL].iter().collect()
+    }
+
+    /// Options for `ts_headline` that mark matches and separate fragments with the sentinels.
+    pub fn headline_options() -> String {
+        format!(
+            "StartSel={}, StopSel={}, MaxFragments={}, MinWords=5, MaxWords=20, FragmentDelimiter={}",
+            START_SENTINEL, STOP_SENTINEL, MAX_FRAGMENTS, FRAGMENT_SENTINEL
+        )
+    }
+
+    /// Splits a headline from `ts_headline` into fragments with the configured
+    /// markers around matches. Fragments without a match are dropped.
+    pub fn fragments(&self, headline: Option<&str>) -> Vec<String> {
+        let Some(headline) = headline else {
+            return Vec::new();
+        };
+
+        headline
+            .split(FRAGMENT_SENTINEL)
+            .filter(|fragment| fragment.contains(START_SENTINEL))
+            .take(MAX_FRAGMENTS)
+            .map(|fragment| {
+                // Stripping the tags leaves runs of whitespace behind.
+                let text = fragment.split_whitespace().collect::<Vec<_>>().join(" ");
+
+                // The text keeps the digester's entities, but anything that could
+                // still open a tag or an attribute is escaped.
+                let mut highlighted = String::with_capacity(text.len());
+                for c in text.chars() {
+                    match c {
+                        START_SENTINEL => highlighted.push_str(&self.start),
+                        STOP_SENTINEL => highlighted.push_str(&self.stop),
+                        '<' => highlighted.push_str("&lt;"),
+                        '>' => highlighted.push_str("&gt;"),
+                        '"' => highlighted.push_str("&quot;"),
+                        '\'' => highlighted.push_str("&#39;"),
+                        c => highlighted.push(c),
+                    }
+                }
+                highlighted
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,26 @@
+//! Message search.
+//! Parses the search query language into words, phrases and filters, which the
+//! database layer compiles into full-text, prefix, trigram and, optionally,
+//! embedding queries combined with Reciprocal Rank Fusion, and highlights why
+//! each result matched. Saved searches can alert their users to new matches,
+//! and searches are logged for analytics.
+
+pub mod alerts;
+pub mod analytics;
+pub mod embeddings;
+pub mod highlight;
+pub mod mode;
+pub mod options;
+pub mod query;
+pub mod ranking;
+pub mod sort;
+
+pub use alerts::SearchAlerts;
+pub use analytics::SearchAnalytics;
+pub use embeddings::Embeddings;
+pub use highlight::Highlighter;
+pub use mode::SearchMode;
+pub use options::SearchOptions;
+pub use query::{parse, ParsedQuery, QueryError};
+pub use ranking::Ranking;
+pub use sort::SearchSort;
@@ -0,0 +1,25 @@
+//! What a search returns as its results.
+
+use serde::{Deserialize, Serialize};
+
+/// Whether a search matches single messages or whole threads.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    /// Each message is matched on its own.
+    #[default]
+    Messages,
+    /// Each thread is matched as one document of all its messages, and
+    /// returned as its top-level message with excerpts of the matching replies.
+    Threads,
+}
+
+impl SearchMode {
+    /// The name of the mode, as in requests.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SearchMode::Messages => "messages",
+            SearchMode::Threads => "threads",
+        }
+    }
+}
@@ -0,0 +1,26 @@
+//! Options of a search beyond the query itself.
+
+use super::{Highlighter, Ranking, SearchMode, SearchSort};
+
+/// How a search is ordered, paginated and presented.
+pub struct SearchOptions<'a> {
+    pub mode: SearchMode,
+    pub sort: SearchSort,
+    /// Ranking parameters for relevance sorting.
+    pub ranking: Ranking,
+    /// Whether to explain how each result was ranked.
+    pub explain: bool,
+    /// Number of results to skip.
+    pub offset: i64,
+    /// Number of results to return.
+    pub limit: i64,
+    pub highlighter: &'a Highlighter,
+}
+
+impl SearchOptions<'_> {
+    /// Whether results are ranked by fusing the fuzzy, full-text and prefix
+    /// rankings, which is how message searches are sorted by relevance.
+    pub fn fuses_ranks(&self) -> bool {
+        self.mode == SearchMode::Messages && self.sort == SearchSort::Relevance
+    }
+}
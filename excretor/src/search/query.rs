@@ -0,0 +1,640 @@
+//! Parser for the search query language.
+//!
+//! A query is a list of whitespace-separated parts:
+//! - `word`: matches messages containing the word (stemmed).
+//! - `"exact phrase"`: matches messages containing the words in this order.
+//! - `-word`, `-"phrase"`: excludes messages containing the word or phrase.
+//! - `from:@name`: only messages by this user (by name, display name or ID).
+//! - `in:#channel`: only messages in this channel (by name or ID).
+//! - `before:2023-01-01`, `after:2023-01-01`, `on:2023-01-01`: only messages
+//!   sent before, after or on this day.
+//! - `has:link`, `has:code`: only messages containing a link or code.
+//! - `code:get_user_info`, `code:"conn.close()"`: only messages whose code
+//!   blocks or inline code contain this text, verbatim but ignoring case.
+//! - `is:thread`: only messages that are part of a thread.
+//!
+//! `from:`, `in:`, `has:`, `is:` and `code:` can be negated with a leading `-`.
+//! `from:` and `in:` can be given several times to match any of them, `code:`
+//! to match all of them. Parts
+//! that look like operators but are not known (e.g. URLs) are searched as words.
+
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use serde::Serialize;
+
+/// What is wrong with a query.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryErrorKind {
+    /// A `"` without a closing `"`.
+    UnterminatedQuote,
+    /// An operator or `-` with nothing after it.
+    MissingValue,
+    /// A date that is not `YYYY-MM-DD`.
+    InvalidDate,
+    /// An operator value that is not supported, e.g. `has:image`.
+    UnsupportedValue,
+    /// An operator that may only be given once, given twice.
+    DuplicateOperator,
+    /// An operator that cannot be negated, e.g. `-before:`.
+    UnsupportedNegation,
+    /// Date operators that no message can satisfy together.
+    ConflictingDates,
+}
+
+/// A structured error pointing at the offending part of the query.
+#[derive(Clone, Debug, Serialize)]
+pub struct QueryError {
+    pub kind: QueryErrorKind,
+    /// A human readable explanation.
+    pub message: String,
+    /// Character offset where the offending part starts.
+    pub start: usize,
+    /// Character offset where the offending part ends.
+    pub end: usize,
+}
+
+impl QueryError {
+    /// An invalid timestamp in a search parameter other than the query, e.g.
+    /// `before`. The span is empty, as it is not part of the query.
+    pub fn invalid_timestamp(parameter: &str, value: &str) -> Self {
+        Self {
+            kind: QueryErrorKind::InvalidDate,
+            message: format!(
+                "`{}` is not a timestamp like 2023-01-31T12:00:00 in `{}`.",
+                value, parameter
+            ),
+            start: 0,
+            end: 0,
+        }
+    }
+}
+
+/// The embedding of a query's text, for matching messages by meaning.
+#[derive(Clone, Debug)]
+pub struct SemanticQuery {
+    /// The embedding as a pgvector literal, e.g. `[0.1,0.2]`.
+    pub embedding: String,
+    /// Largest cosine distance of a matching message.
+    pub max_distance: f64,
+}
+
+/// A parsed search query.
+#[derive(Clone, Debug, Default)]
+pub struct ParsedQuery {
+    /// Words that must occur.
+    pub terms: Vec<String>,
+    /// Phrases that must occur.
+    pub phrases: Vec<String>,
+    /// Words and phrases that must not occur.
+    pub excluded: Vec<String>,
+    /// Lowercased names or IDs of users, one of whom must have sent the message.
+    pub from_users: Vec<String>,
+    /// Lowercased names or IDs of users who must not have sent the message.
+    pub excluded_users: Vec<String>,
+    /// Lowercased names or IDs of channels, one of which must contain the message.
+    pub in_channels: Vec<String>,
+    /// Lowercased names or IDs of channels that must not contain the message.
+    pub excluded_channels: Vec<String>,
+    /// Only this channel, from the search form.
+    pub channel_id: Option<String>,
+    /// Only this user, from the search form.
+    pub user_id: Option<String>,
+    /// Messages sent at or after this time.
+    pub since: Option<NaiveDateTime>,
+    /// Messages sent before this time.
+    pub until: Option<NaiveDateTime>,
+    /// Whether messages must (or must not) contain a link.
+    pub has_link: Option<bool>,
+    /// Whether messages must (or must not) contain code.
+    pub has_code: Option<bool>,
+    /// Text that must occur in the message's code, ignoring case.
+    pub code: Vec<String>,
+    /// Text that must not occur in the message's code, ignoring case.
+    pub excluded_code: Vec<String>,
+    /// Whether messages must (or must not) be part of a thread.
+    pub is_thread: Option<bool>,
+    /// The embedding of the words and phrases, when semantic search is enabled.
+    pub semantic: Option<SemanticQuery>,
+    /// Only the messages with these IDs in `search_alert_queue`, when checking
+    /// search alerts.
+    pub queued: Option<Vec<i64>>,
+}
+
+/// A part of the query with its byte span.
+struct Part<'a> {
+    text: &'a str,
+    negated: bool,
+    start: usize,
+    end: usize,
+}
+
+impl ParsedQuery {
+    /// Whether the query has words or phrases to search for.
+    /// Queries with only filters list the most recent matching messages.
+    pub fn has_text(&self) -> bool {
+        !self.terms.is_empty() || !self.phrases.is_empty()
+    }
+
+    /// The query for Postgres' `websearch_to_tsquery`.
+    pub fn websearch_text(&self) -> String {
+        let quoted = |phrase: &String| format!("\"{}\"", phrase);
+        self.terms
+            .iter()
+            .cloned()
+            .chain(self.phrases.iter().map(quoted))
+            .chain(self.excluded.iter().map(|excluded| {
+                if excluded.contains(char::is_whitespace) {
+                    format!("-{}", quoted(excluded))
+                } else {
+                    format!("-{}", excluded)
+                }
+            }))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// A `websearch_to_tsquery` query matching any of the words and phrases,
+    /// for finding what to highlight.
+    pub fn websearch_any_text(&self) -> String {
+        self.terms
+            .iter()
+            .cloned()
+            .chain(self.phrases.iter().map(|phrase| format!("\"{}\"", phrase)))
+            .collect::<Vec<_>>()
+            .join(" or ")
+    }
+
+    /// The words and phrases as plain text, for trigram similarity.
+    pub fn fuzzy_text(&self) -> String {
+        self.terms
+            .iter()
+            .chain(self.phrases.iter())
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// A `to_tsquery` query matching all words, the last one as a prefix, so
+    /// that results show up while the last word is still being typed.
+    /// `None` if the query has no words.
+    pub fn prefix_query(&self) -> Option<String> {
+        let words: Vec<&str> = self
+            .terms
+            .iter()
+            .flat_map(|term| term.split(|c: char| !c.is_alphanumeric() && c != '_'))
+            .filter(|word| !word.is_empty())
+            .collect();
+        let (last, rest) = words.split_last()?;
+
+        let mut query = rest.join(" & ");
+        if !query.is_empty() {
+            query.push_str(" & ");
+        }
+        query.push_str(&format!("{}:*", last));
+        Some(query)
+    }
+
+    /// Restricts the query to messages sent at or after `since`.
+    pub fn limit_since(&mut self, since: NaiveDateTime) {
+        self.since = Some(self.since.map_or(since, |current| current.max(since)));
+    }
+
+    /// Restricts the query to messages sent before `until`.
+    pub fn limit_until(&mut self, until: NaiveDateTime) {
+        self.until = Some(self.until.map_or(until, |current| current.min(until)));
+    }
+}
+
+/// Converts a byte offset into `input` to a character offset.
+fn char_offset(input: &str, byte: usize) -> usize {
+    input[..byte].chars().count()
+}
+
+fn error(input: &str, kind: QueryErrorKind, start: usize, end: usize, message: String) -> QueryError {
+    QueryError {
+        kind,
+        message,
+        start: char_offset(input, start),
+        end: char_offset(input, end),
+    }
+}
+
+/// Splits the query into parts at whitespace outside of quotes.
+fn split_parts(input: &str) -> Result<Vec<Part<'_>>, QueryError> {
+    let mut parts = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let negated = c == '-';
+        if negated {
+            chars.next();
+        }
+        let text_start = chars.peek().map_or(input.len(), |&(i, _)| i);
+
+        let mut in_quote = false;
+        let mut quote_start = text_start;
+        let mut end = input.len();
+        while let Some(&(i, c)) = chars.peek() {
+            if c.is_whitespace() && !in_quote {
+                end = i;
+                break;
+            }
+            if c == '"' {
+                in_quote = !in_quote;
+                quote_start = i;
+            }
+            chars.next();
+        }
+
+        if in_quote {
+            return Err(error(
+                input,
+                QueryErrorKind::UnterminatedQuote,
+                quote_start,
+                input.len(),
+                "This quote is never closed.".to_owned(),
+            ));
+        }
+
+        let text = &input[text_start..end];
+        if negated && text.is_empty() {
+            return Err(error(
+                input,
+                QueryErrorKind::MissingValue,
+                start,
+                end,
+                "Nothing to exclude after `-`.".to_owned(),
+            ));
+        }
+        parts.push(Part {
+            text,
+            negated,
+            start,
+            end,
+        });
+    }
+
+    Ok(parts)
+}
+
+/// Removes the surrounding quotes of a value, if any.
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+fn parse_date(input: &str, part: &Part, value: &str) -> Result<NaiveDateTime, QueryError> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|date| date.and_hms_opt(0, 0, 0).unwrap())
+        .map_err(|_| {
+            error(
+                input,
+                QueryErrorKind::InvalidDate,
+                part.start,
+                part.end,
+                format!("`{}` is not a date like 2023-01-31.", value),
+            )
+        })
+}
+
+/// Parses a search query.
+pub fn parse(input: &str) -> Result<ParsedQuery, QueryError> {
+    let mut query = ParsedQuery::default();
+    let mut seen_dates: Vec<&str> = Vec::new();
+
+    for part in split_parts(input)? {
+        let operator = part
+            .text
+            .split_once(':')
+            .map(|(name, value)| (name.to_lowercase(), unquote(value).trim()))
+            .filter(|(name, _)| {
+                matches!(
+                    name.as_str(),
+                    "from" | "in" | "before" | "after" | "on" | "has" | "is" | "code"
+                )
+            });
+
+        let Some((name, value)) = operator else {
+            let text = unquote(part.text).trim();
+            let is_phrase = part.text.len() >= 2 && part.text.starts_with('"') && part.text.ends_with('"');
+            let text = if is_phrase { text.to_owned() } else { text.replace('"', "") };
+            if text.is_empty() {
+                continue;
+            }
+
+            if part.negated {
+                query.excluded.push(text);
+            } else if is_phrase && text.contains(char::is_whitespace) {
+                query.phrases.push(text);
+            } else {
+                query.terms.push(text);
+            }
+            continue;
+        };
+
+        if value.is_empty() {
+            return Err(error(
+                input,
+                QueryErrorKind::MissingValue,
+                part.start,
+                part.end,
+                format!("`{}:` needs a value.", name),
+            ));
+        }
+
+        let unsupported_negation = || {
+            error(
+                input,
+                QueryErrorKind::UnsupportedNegation,
+                part.start,
+                part.end,
+                format!("`{}:` cannot be negated.", name),
+            )
+        };
+
+        match name.as_str() {
+            "from" => {
+                let user = value.trim_start_matches('@').to_lowercase();
+                if part.negated {
+                    query.excluded_users.push(user);
+                } else {
+                    query.from_users.push(user);
+                }
+            }
+            "in" => {
+                let channel = value.trim_start_matches('#').to_lowercase();
+                if part.negated {
+                    query.excluded_channels.push(channel);
+                } else {
+                    query.in_channels.push(channel);
+                }
+            }
+            "code" => {
+                if part.negated {
+                    query.excluded_code.push(value.to_owned());
+                } else {
+                    query.code.push(value.to_owned());
+                }
+            }
+            "before" | "after" | "on" => {
+                if part.negated {
+                    return Err(unsupported_negation());
+                }
+                if seen_dates.contains(&name.as_str()) {
+                    return Err(error(
+                        input,
+                        QueryErrorKind::DuplicateOperator,
+                        part.start,
+                        part.end,
+                        format!("`{}:` can only be given once.", name),
+                    ));
+                }
+
+                let date = parse_date(input, &part, value)?;
+                match name.as_str() {
+                    "before" => {
+                        seen_dates.push("before");
+                        query.limit_until(date);
+                    }
+                    "after" => {
+                        seen_dates.push("after");
+                        query.limit_since(date + Duration::days(1));
+                    }
+                    _ => {
+                        seen_dates.push("on");
+                        query.limit_since(date);
+                        query.limit_until(date + Duration::days(1));
+                    }
+                }
+            }
+            "has" | "is" => {
+                let flag = match (name.as_str(), value.to_lowercase().as_str()) {
+                    ("has", "link") => &mut query.has_link,
+                    ("has", "code") => &mut query.has_code,
+                    ("is", "thread") => &mut query.is_thread,
+                    _ => {
+                        return Err(error(
+                            input,
+                            QueryErrorKind::UnsupportedValue,
+                            part.start,
+                            part.end,
+                            format!("`{}:{}` is not supported.", name, value),
+                        ));
+                    }
+                };
+                *flag = Some(!part.negated);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    if let (Some(since), Some(until)) = (query.since, query.until) {
+        if since >= until {
+            return Err(error(
+                input,
+                QueryErrorKind::ConflictingDates,
+                0,
+                input.len(),
+                "No message can match these dates.".to_owned(),
+            ));
+        }
+    }
+
+    Ok(query)
+}
+
+/// Rewrites `input` with the words in `replacements` replaced, keeping the
+/// rest of the query as typed. Excluded words are left alone.
+pub fn correct(input: &str, replacements: &[(&str, &str)]) -> String {
+    let Ok(parts) = split_parts(input) else {
+        return input.to_owned();
+    };
+
+    let mut corrected = String::with_capacity(input.len());
+    let mut copied = 0;
+    for part in parts.iter().filter(|part| !part.negated) {
+        let word = unquote(part.text).trim().replace('"', "");
+        if let Some((_, replacement)) = replacements.iter().find(|(term, _)| *term == word) {
+            corrected.push_str(&input[copied..part.start]);
+            corrected.push_str(replacement);
+            copied = part.end;
+        }
+    }
+    corrected.push_str(&input[copied..]);
+    corrected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks the fields a case is about.
+    type Check = fn(&ParsedQuery) -> bool;
+
+    fn date(value: &str) -> NaiveDateTime {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_words_phrases_and_filters() {
+        // (input, check)
+        let cases: &[(&str, Check)] = &[
+            ("hello world", |q| q.terms == ["hello", "world"]),
+            ("\"hello world\"", |q| {
+                q.phrases == ["hello world"] && q.terms.is_empty()
+            }),
+            ("\"hello\"", |q| {
+                q.terms == ["hello"] && q.phrases.is_empty()
+            }),
+            ("-spam -\"bad words\"", |q| {
+                q.excluded == ["spam", "bad words"]
+            }),
+            ("from:@Alice from:U2", |q| q.from_users == ["alice", "u2"]),
+            ("-from:bob", |q| q.excluded_users == ["bob"]),
+            ("in:#General -in:random", |q| {
+                q.in_channels == ["general"] && q.excluded_channels == ["random"]
+            }),
+            ("code:\"conn.close()\" -code:unwrap", |q| {
+                q.code == ["conn.close()"] && q.excluded_code == ["unwrap"]
+            }),
+            ("has:link -has:code is:thread", |q| {
+                q.has_link == Some(true) && q.has_code == Some(false) && q.is_thread == Some(true)
+            }),
+            ("before:2023-01-31", |q| {
+                q.until == Some(date("2023-01-31")) && q.since.is_none()
+            }),
+            ("after:2023-01-01", |q| {
+                q.since == Some(date("2023-01-02")) && q.until.is_none()
+            }),
+            ("on:2023-01-01", |q| {
+                q.since == Some(date("2023-01-01")) && q.until == Some(date("2023-01-02"))
+            }),
+            ("after:2023-01-01 before:2023-01-31", |q| {
+                q.since == Some(date("2023-01-02")) && q.until == Some(date("2023-01-31"))
+            }),
+            // Unknown operators and URLs are searched as words.
+            ("foo:bar", |q| q.terms == ["foo:bar"]),
+            ("https://example.com", |q| {
+                q.terms == ["https://example.com"]
+            }),
+            ("", |q| !q.has_text()),
+            ("\"\"", |q| !q.has_text() && q.excluded.is_empty()),
+        ];
+
+        for (input, check) in cases {
+            let query = parse(input).unwrap_or_else(|err| panic!("{:?} failed: {:?}", input, err));
+            assert!(
+                check(&query),
+                "unexpected result for {:?}: {:?}",
+                input,
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_queries() {
+        // (input, kind, start, end)
+        let cases = [
+            ("-", QueryErrorKind::MissingValue, 0, 1),
+            ("hello - world", QueryErrorKind::MissingValue, 6, 7),
+            ("-before:", QueryErrorKind::MissingValue, 0, 8),
+            ("from:", QueryErrorKind::MissingValue, 0, 5),
+            (
+                "-before:2023-01-01",
+                QueryErrorKind::UnsupportedNegation,
+                0,
+                18,
+            ),
+            ("-on:2023-01-01", QueryErrorKind::UnsupportedNegation, 0, 14),
+            (
+                "before:2023-01-01 before:2023-02-01",
+                QueryErrorKind::DuplicateOperator,
+                18,
+                35,
+            ),
+            (
+                "on:2023-01-01 on:2023-01-01",
+                QueryErrorKind::DuplicateOperator,
+                14,
+                27,
+            ),
+            ("\"hello world", QueryErrorKind::UnterminatedQuote, 0, 12),
+            ("hello \"world", QueryErrorKind::UnterminatedQuote, 6, 12),
+            ("code:\"a", QueryErrorKind::UnterminatedQuote, 5, 7),
+            ("has:image", QueryErrorKind::UnsupportedValue, 0, 9),
+            ("is:pinned", QueryErrorKind::UnsupportedValue, 0, 9),
+            ("before:yesterday", QueryErrorKind::InvalidDate, 0, 16),
+            ("on:2023-02-30", QueryErrorKind::InvalidDate, 0, 13),
+            (
+                "after:2023-01-31 before:2023-01-01",
+                QueryErrorKind::ConflictingDates,
+                0,
+                34,
+            ),
+            (
+                "on:2023-01-01 after:2023-01-01",
+                QueryErrorKind::ConflictingDates,
+                0,
+                30,
+            ),
+            // Offsets are in characters, not bytes.
+            ("héllo -", QueryErrorKind::MissingValue, 6, 7),
+        ];
+
+        for (input, kind, start, end) in cases {
+            let err = parse(input).expect_err(input);
+            assert_eq!(
+                (err.kind, err.start, err.end),
+                (kind, start, end),
+                "for {:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn builds_text_queries() {
+        let query = parse("deploy \"release notes\" -draft -\"old plan\"").unwrap();
+        assert_eq!(
+            query.websearch_text(),
+            "deploy \"release notes\" -draft -\"old plan\""
+        );
+        assert_eq!(query.websearch_any_text(), "deploy or \"release notes\"");
+        assert_eq!(query.fuzzy_text(), "deploy release notes");
+        assert_eq!(query.prefix_query().as_deref(), Some("deploy:*"));
+
+        let query = parse("fix conn.clo").unwrap();
+        assert_eq!(query.prefix_query().as_deref(), Some("fix & conn & clo:*"));
+        assert_eq!(parse("\"only a phrase\"").unwrap().prefix_query(), None);
+    }
+
+    #[test]
+    fn corrects_words_only() {
+        let cases = [
+            ("helo wrld", "hello wrld"),
+            ("\"helo\" -helo", "hello -helo"),
+            ("from:helo helo", "from:helo hello"),
+            ("\"unclosed helo", "\"unclosed helo"),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(
+                correct(input, &[("helo", "hello")]),
+                expected,
+                "for {:?}",
+                input
+            );
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Parameters of the Reciprocal Rank Fusion that ranks search results.
+//! Each ranked candidate set contributes `weight / (k + rank)` to a message's
+//! score. Defaults come from the environment and can be overridden per search,
+//! within limits that keep the candidate sets of the search shallow.
+
+use serde::{Deserialize, Serialize};
+
+use crate::env::{EmbeddingProviderKind, EnvVars};
+
+/// Largest accepted weight of a candidate set.
+const MAX_WEIGHT: f64 = 100.0;
+
+/// Largest accepted `k`.
+const MAX_K: f64 = 10_000.0;
+
+/// Largest `k` a search may ask for, unless the default is larger. Candidate
+/// sets deepen with `k`.
+const MAX_SEARCH_K: f64 = 200.0;
+
+/// Largest weight spread a search may ask for, unless the default is larger.
+/// Candidate sets deepen with the spread; see [`Ranking::weight_spread`].
+const MAX_SEARCH_WEIGHT_SPREAD: f64 = 6.0;
+
+/// The `k` constant and per-signal weights of Reciprocal Rank Fusion.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Ranking {
+    /// Dampens the difference between high and low ranks.
+    pub k: f64,
+    /// Weight of trigram similarity matches.
+    pub fuzzy: f64,
+    /// Weight of full-text matches.
+    pub full_text: f64,
+    /// Weight of prefix matches.
+    pub prefix: f64,
+    /// Weight of semantic matches. Always 0 without an embedding provider.
+    pub semantic: f64,
+}
+
+impl Ranking {
+    pub fn from_env(env_vars: &EnvVars) -> Self {
+        Self {
+            k: env_vars.search_rrf_k,
+            fuzzy: env_vars.search_weight_fuzzy,
+            full_text: env_vars.search_weight_full_text,
+            prefix: env_vars.search_weight_prefix,
+            semantic: if env_vars.embedding_provider == EmbeddingProviderKind::None {
+                0.0
+            } else {
+                env_vars.search_weight_semantic
+            },
+        }
+    }
+
+    /// Returns these parameters with the given ones replaced. The semantic
+    /// weight cannot be turned on without an embedding provider, and `k` and
+    /// the weight spread can only be raised up to `MAX_SEARCH_K` and
+    /// `MAX_SEARCH_WEIGHT_SPREAD`, so that a search cannot make the database
+    /// rank many more candidates than it returns.
+    pub fn with_overrides(
+        self,
+        k: Option<f64>,
+        fuzzy: Option<f64>,
+        full_text: Option<f64>,
+        prefix: Option<f64>,
+        semantic: Option<f64>,
+        semantic_available: bool,
+    ) -> Result<Self, String> {
+        let ranking = Self {
+            k: k.unwrap_or(self.k),
+            fuzzy: fuzzy.unwrap_or(self.fuzzy),
+            full_text: full_text.unwrap_or(self.full_text),
+            prefix: prefix.unwrap_or(self.prefix),
+            semantic: semantic.filter(|_| semantic_available).unwrap_or(self.semantic),
+        };
+        ranking.validate()?;
+
+        if k.is_some() && ranking.k > MAX_SEARCH_K.max(self.k) {
+            return Err(format!(
+                "The RRF k of a search can be at most {}.",
+                MAX_SEARCH_K.max(self.k)
+            ));
+        }
+        let max_spread = MAX_SEARCH_WEIGHT_SPREAD.max(self.weight_spread());
+        if ranking.weight_spread() > max_spread {
+            return Err(format!(
+                "The sum of the ranking weights of a search can be at most {} times the smallest weight above 0.",
+                max_spread
+            ));
+        }
+        Ok(ranking)
+    }
+
+    /// Checks that `k` is positive, every weight is between 0 and `MAX_WEIGHT`
+    /// and at least one weight is not 0.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.k >= 1.0 && self.k <= MAX_K) {
+            return Err(format!("The RRF k must be between 1 and {}.", MAX_K));
+        }
+        if !self
+            .weights()
+            .iter()
+            .all(|weight| (0.0..=MAX_WEIGHT).contains(weight))
+        {
+            return Err(format!("Ranking weights must be between 0 and {}.", MAX_WEIGHT));
+        }
+        if self.weights().iter().all(|weight| *weight == 0.0) {
+            return Err("At least one ranking weight must be above 0.".to_owned());
+        }
+        Ok(())
+    }
+
+    /// The weights of the fuzzy, full-text, prefix and semantic candidate sets.
+    pub fn weights(&self) -> [f64; 4] {
+        [self.fuzzy, self.full_text, self.prefix, self.semantic]
+    }
+
+    /// The sum of the weights over the smallest weight above 0. Each candidate
+    /// set must be this many times deeper than the results for the fused
+    /// ranking to be exact.
+    pub fn weight_spread(&self) -> f64 {
+        let weights = self.weights();
+        let min_weight = weights
+            .iter()
+            .copied()
+            .filter(|weight| *weight > 0.0)
+            .fold(f64::INFINITY, f64::min);
+        weights.iter().sum::<f64>() / min_weight
+    }
+
+    /// What a candidate set with `weight` contributes for a message at `rank`.
+    pub fn contribution(&self, weight: f64, rank: Option<i64>) -> f64 {
+        rank.map_or(0.0, |rank| weight / (self.k + rank as f64))
+    }
+}
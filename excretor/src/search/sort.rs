@@ -0,0 +1,26 @@
+//! Orders in which search results can be returned.
+
+use serde::{Deserialize, Serialize};
+
+/// How search results are ordered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchSort {
+    /// Best matches first. Queries without text fall back to newest first.
+    #[default]
+    Relevance,
+    /// Newest matches first.
+    Newest,
+    /// Oldest matches first.
+    Oldest,
+}
+
+impl SearchSort {
+    /// The SQL sort direction on the message timestamp for date sorts.
+    pub fn ts_direction(&self) -> &'static str {
+        match self {
+            SearchSort::Oldest => "ASC",
+            SearchSort::Relevance | SearchSort::Newest => "DESC",
+        }
+    }
+}
@@ -1,3 +1,7 @@
+#[allow(clippy::module_inception)]
 mod types;
 
-pub use self::types::{Channel, Message, User, SearchResult};
\ No newline at end of file
+pub use self::types::{
+    ApiToken, AuditEntry, Channel, FacetCount, Message, RankExplanation, ReplyExcerpt, SavedSearch, SearchAlertMatch, SearchFacets,
+    SearchQueryStats, SearchResult, Suggestion, SuggestionKind, User, UserRole,
+};
\ No newline at end of file
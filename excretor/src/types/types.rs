@@ -1,6 +1,11 @@
 use serde::{Deserialize, Serialize};
 use crate::{
-    db::dbmodels::{DBChannel, DBParentMessage, DBReply, DBUser, DBSearchResult},
+    auth::{roles::Role, tokens::Scope},
+    search::Ranking,
+    db::dbmodels::{
+        DBApiToken, DBAuditEntry, DBChannel, DBParentMessage, DBReply, DBReplyExcerpt, DBSavedSearch, DBSearchAlertMatch, DBSearchFacet,
+        DBSearchQueryStats, DBSearchResult, DBSuggestion, DBUser, DBUserRole,
+    },
 };
 use sqlx::types::chrono;
 use crate::db::tummy::SlackDateTime;
@@ -8,6 +13,7 @@ use crate::db::tummy::SlackDateTime;
 
 // This private helper centralizes the logic for creating a User struct.
 // It now accepts references to avoid unnecessary cloning.
+#[allow(clippy::too_many_arguments)]
 fn build_user(
     id: &str,
     name: &str,
@@ -65,8 +71,163 @@ pub struct SearchResult {
     #[serde(flatten)]
     pub message: Message,
     pub parent_message: Option<Box<Message>>,
+    /// Fragments of the message text with the matches marked.
+    #[serde(default)]
+    pub highlights: Vec<String>,
+    /// How the result was ranked, if requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explain: Option<RankExplanation>,
+    /// In thread searches, excerpts of the replies that match.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reply_excerpts: Vec<ReplyExcerpt>,
 }
 
+/// Represents a reply that matched a thread search.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ReplyExcerpt {
+    /// The timestamp of the reply.
+    pub timestamp: chrono::NaiveDateTime,
+    /// The ID of the user who posted the reply.
+    pub user_id: String,
+    /// The name of the user who posted the reply.
+    pub user_name: String,
+    /// Fragments of the reply text with the matches marked.
+    pub highlights: Vec<String>,
+}
+
+/// Converts a `DBReplyExcerpt` database model into a `ReplyExcerpt`, without highlights.
+impl From<DBReplyExcerpt> for ReplyExcerpt {
+    fn from(item: DBReplyExcerpt) -> Self {
+        ReplyExcerpt {
+            timestamp: item.ts,
+            user_id: item.user_id,
+            user_name: item.user_name,
+            highlights: Vec::new(),
+        }
+    }
+}
+
+/// Represents a result's rank in one of the fused candidate sets.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SignalRank {
+    /// The 1-based rank, `None` if the set did not contain the result.
+    pub rank: Option<i64>,
+    /// What the set added to the result's score.
+    pub contribution: f64,
+}
+
+/// Represents how a search result's Reciprocal Rank Fusion score came about.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RankExplanation {
+    pub fuzzy: SignalRank,
+    pub full_text: SignalRank,
+    pub prefix: SignalRank,
+    pub semantic: SignalRank,
+    /// The sum of the contributions.
+    pub score: f64,
+}
+
+impl RankExplanation {
+    /// Explains the score of a result with the given fuzzy, full-text, prefix
+    /// and semantic ranks.
+    pub fn new(ranking: &Ranking, ranks: [Option<i64>; 4]) -> Self {
+        let [fuzzy, full_text, prefix, semantic] = [
+            (ranking.fuzzy, ranks[0]),
+            (ranking.full_text, ranks[1]),
+            (ranking.prefix, ranks[2]),
+            (ranking.semantic, ranks[3]),
+        ]
+        .map(|(weight, rank)| SignalRank {
+            rank,
+            contribution: ranking.contribution(weight, rank),
+        });
+        let score =
+            fuzzy.contribution + full_text.contribution + prefix.contribution + semantic.contribution;
+
+        RankExplanation {
+            fuzzy,
+            full_text,
+            prefix,
+            semantic,
+            score,
+        }
+    }
+}
+
+
+/// Represents the number of search hits for one value of a facet.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FacetCount {
+    /// The value to filter on: a channel ID, a user ID or a month as `YYYY-MM`.
+    pub value: String,
+    /// The channel or user name to show. `None` for months.
+    pub label: Option<String>,
+    /// The number of matching messages.
+    pub count: i64,
+}
+
+/// Represents hit counts over all matches of a search, for narrowing it down.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct SearchFacets {
+    pub channels: Vec<FacetCount>,
+    pub users: Vec<FacetCount>,
+    pub months: Vec<FacetCount>,
+}
+
+/// Converts a `DBSearchFacet` database model into a `FacetCount`.
+impl From<DBSearchFacet> for FacetCount {
+    fn from(item: DBSearchFacet) -> Self {
+        FacetCount {
+            value: item.value,
+            label: item.label,
+            count: item.count,
+        }
+    }
+}
+
+/// What an autocomplete suggestion completes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SuggestionKind {
+    User,
+    Channel,
+    Term,
+}
+
+/// Represents an autocomplete suggestion for the search bar.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Suggestion {
+    pub kind: SuggestionKind,
+    /// The user or channel ID, or the term.
+    pub value: String,
+    /// The user or channel name, or the term.
+    pub label: String,
+    /// The user's display name or the channel's topic, if any.
+    pub detail: Option<String>,
+    /// What to put in the search query, e.g. `from:@alice` or `in:#general`.
+    pub query: String,
+    /// How well the suggestion matches, higher is better.
+    pub score: f64,
+}
+
+/// Converts a `DBSuggestion` database model into a `Suggestion`.
+impl From<DBSuggestion> for Suggestion {
+    fn from(item: DBSuggestion) -> Self {
+        let (kind, query) = match item.kind.as_str() {
+            "user" => (SuggestionKind::User, format!("from:@{}", item.label)),
+            "channel" => (SuggestionKind::Channel, format!("in:#{}", item.label)),
+            _ => (SuggestionKind::Term, item.label.clone()),
+        };
+        Suggestion {
+            kind,
+            value: item.value,
+            label: item.label,
+            detail: item.detail,
+            query,
+            score: item.score,
+        }
+    }
+}
 
 /// Converts a `DBParentMessage` database model into a `Message`.
 impl From<DBParentMessage> for Message {
@@ -192,6 +353,9 @@ impl From<DBSearchResult> for SearchResult {
         SearchResult {
             message,
             parent_message,
+            highlights: Vec::new(),
+            explain: None,
+            reply_excerpts: Vec::new(),
         }
     }
 }
@@ -247,6 +411,11 @@ pub struct Channel {
     pub topic: String,
     /// The channel purpose.
     pub purpose: String,
+    /// Whether the channel is private (or a DM) and only visible to its members.
+    pub is_private: bool,
+    /// The text search configuration (language) of the channel's messages, if
+    /// not the default.
+    pub search_config: Option<String>,
 }
 
 /// Converts a `DBChannel` database model into a `Channel`.
@@ -257,6 +426,231 @@ impl From<DBChannel> for Channel {
             name: value.name,
             topic: value.topic.unwrap_or_default(),
             purpose: value.purpose.unwrap_or_default(),
+            is_private: value.is_private,
+            search_config: value.search_config,
+        }
+    }
+}
+
+/// Represents a personal API token. The token itself is never included.
+#[derive(Serialize, Deserialize)]
+pub struct ApiToken {
+    /// The unique token ID.
+    pub id: i64,
+    /// A name chosen by the user.
+    pub name: String,
+    /// The leading characters of the token.
+    pub token_prefix: String,
+    /// The scopes granted to the token.
+    pub scopes: Vec<Scope>,
+    /// When the token was created.
+    pub created_at: chrono::NaiveDateTime,
+    /// When the token expires, if ever.
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    /// When the token was last used, if ever.
+    pub last_used_at: Option<chrono::NaiveDateTime>,
+    /// When the token was revoked, if it was.
+    pub revoked_at: Option<chrono::NaiveDateTime>,
+}
+
+/// Converts a `DBApiToken` database model into an `ApiToken`.
+impl From<DBApiToken> for ApiToken {
+    fn from(value: DBApiToken) -> Self {
+        ApiToken {
+            id: value.id,
+            name: value.name,
+            token_prefix: value.token_prefix,
+            scopes: value
+                .scopes
+                .iter()
+                .filter_map(|scope| scope.parse().ok())
+                .collect(),
+            created_at: value.created_at,
+            expires_at: value.expires_at,
+            last_used_at: value.last_used_at,
+            revoked_at: value.revoked_at,
+        }
+    }
+}
+
+/// Represents a role held by a user.
+#[derive(Serialize, Deserialize)]
+pub struct UserRole {
+    /// The ID of the user.
+    pub user_id: String,
+    /// The user's role.
+    pub role: Role,
+    /// The ID of the admin who granted the role. `None` for roles from `ADMIN_USERS`.
+    pub granted_by: Option<String>,
+    /// When the role was granted. `None` for roles from `ADMIN_USERS`.
+    pub granted_at: Option<chrono::NaiveDateTime>,
+}
+
+/// Converts a `DBUserRole` database model into a `UserRole`.
+impl From<DBUserRole> for UserRole {
+    fn from(value: DBUserRole) -> Self {
+        UserRole {
+            user_id: value.user_id,
+            role: value.role.parse().unwrap_or(Role::Viewer),
+            granted_by: value.granted_by,
+            granted_at: Some(value.granted_at),
+        }
+    }
+}
+
+/// Represents an entry of the audit log.
+#[derive(Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// The unique entry ID.
+    pub id: i64,
+    /// When the access happened.
+    pub occurred_at: chrono::NaiveDateTime,
+    /// The ID of the user, if login is enabled.
+    pub user_id: Option<String>,
+    /// The API token used, if any.
+    pub token_id: Option<i64>,
+    /// The client's IP address, if known.
+    pub ip: Option<String>,
+    /// What was accessed.
+    pub action: String,
+    /// The parameters of the access.
+    pub params: serde_json::Value,
+}
+
+/// Converts a `DBAuditEntry` database model into an `AuditEntry`.
+impl From<DBAuditEntry> for AuditEntry {
+    fn from(value: DBAuditEntry) -> Self {
+        AuditEntry {
+            id: value.id,
+            occurred_at: value.occurred_at,
+            user_id: value.user_id,
+            token_id: value.token_id,
+            ip: value.ip,
+            action: value.action,
+            params: value.params,
+        }
+    }
+}
+
+/// Represents a search saved by a user.
+#[derive(Serialize, Deserialize)]
+pub struct SavedSearch {
+    /// The unique saved search ID.
+    pub id: i64,
+    /// A name chosen by the user.
+    pub name: String,
+    /// The query in the search query language.
+    pub query: String,
+    /// Whether new messages matching the query are added to the user's alert feed.
+    pub alert: bool,
+    /// Where new matches are POSTed, if anywhere.
+    pub webhook_url: Option<String>,
+    /// When the search was saved.
+    pub created_at: chrono::NaiveDateTime,
+    /// When the search was last changed.
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+/// Converts a `DBSavedSearch` database model into a `SavedSearch`.
+impl From<DBSavedSearch> for SavedSearch {
+    fn from(value: DBSavedSearch) -> Self {
+        SavedSearch {
+            id: value.id,
+            name: value.name,
+            query: value.query,
+            alert: value.alert,
+            webhook_url: value.webhook_url,
+            created_at: value.created_at,
+            updated_at: value.updated_at,
+        }
+    }
+}
+
+/// Represents a new message matching one of a user's search alerts.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SearchAlertMatch {
+    /// The unique match ID, increasing as matches are found.
+    pub id: i64,
+    /// The ID of the saved search that matched.
+    pub saved_search_id: i64,
+    /// The name of the saved search that matched.
+    pub saved_search_name: String,
+    /// When the match was found.
+    pub matched_at: chrono::NaiveDateTime,
+    /// Fragments of the message text with the matches marked.
+    pub highlights: Vec<String>,
+    /// The matching message.
+    pub message: Message,
+}
+
+/// Converts a `DBSearchAlertMatch` database model into a `SearchAlertMatch`.
+impl From<DBSearchAlertMatch> for SearchAlertMatch {
+    fn from(item: DBSearchAlertMatch) -> Self {
+        SearchAlertMatch {
+            id: item.match_id,
+            saved_search_id: item.saved_search_id,
+            saved_search_name: item.saved_search_name,
+            matched_at: item.matched_at,
+            highlights: item.highlights,
+            message: Message {
+                channel_id: item.channel_id,
+                channel_name: item.channel_name,
+                user_id: item.user_id.clone(),
+                text: item.msg_text,
+                timestamp: item.ts,
+                thread_timestamp: item.thread_ts,
+                parent_user_id: item.parent_user_id,
+                formatted_timestamp: item.ts.human_format(),
+                thread_count: 0, // Not counted for alerts
+                user: build_user(
+                    &item.user_id,
+                    &item.name,
+                    &item.real_name,
+                    &item.display_name,
+                    item.image_url.as_ref(),
+                    &item.email,
+                    item.deleted,
+                    item.is_bot,
+                ),
+            },
+        }
+    }
+}
+
+/// Represents how a search query fared over a period.
+#[derive(Serialize, Deserialize)]
+pub struct SearchQueryStats {
+    /// The query, lowercased and with whitespace collapsed.
+    pub query: String,
+    /// The number of times the query was searched.
+    pub searches: i64,
+    /// The number of distinct users who searched it. Always 0 when searches
+    /// are logged anonymously.
+    pub users: i64,
+    /// The number of searches without any result.
+    pub zero_result_searches: i64,
+    /// The average number of results.
+    pub avg_hits: f64,
+    /// The average time to answer the search, in milliseconds.
+    pub avg_latency_ms: f64,
+    /// The longest time to answer the search, in milliseconds.
+    pub max_latency_ms: f64,
+    /// When the query was last searched.
+    pub last_searched_at: chrono::NaiveDateTime,
+}
+
+/// Converts a `DBSearchQueryStats` database model into a `SearchQueryStats`.
+impl From<DBSearchQueryStats> for SearchQueryStats {
+    fn from(value: DBSearchQueryStats) -> Self {
+        SearchQueryStats {
+            query: value.query,
+            searches: value.searches,
+            users: value.users,
+            zero_result_searches: value.zero_result_searches,
+            avg_hits: value.avg_hits,
+            avg_latency_ms: value.avg_latency_ms,
+            max_latency_ms: value.max_latency_ms,
+            last_searched_at: value.last_searched_at,
         }
     }
 }
@@ -0,0 +1,17 @@
+-- Personal access tokens for scripts and bots, sent as `Authorization: Bearer <token>`
+CREATE TABLE IF NOT EXISTS api_tokens (
+    id BIGSERIAL PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    -- SHA-256 of the token; the token itself is only shown once on creation
+    token_hash TEXT NOT NULL UNIQUE,
+    -- The first few characters of the token, to tell tokens apart
+    token_prefix TEXT NOT NULL,
+    scopes TEXT[] NOT NULL,
+    created_at TIMESTAMP(6) NOT NULL,
+    expires_at TIMESTAMP(6),
+    last_used_at TIMESTAMP(6),
+    revoked_at TIMESTAMP(6)
+);
+
+CREATE INDEX IF NOT EXISTS api_tokens_user_id_idx ON api_tokens (user_id);
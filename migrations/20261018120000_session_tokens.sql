@@ -0,0 +1,6 @@
+-- Sessions are now referenced by a random ID in the signed session token and
+-- hold the login provider's access token encrypted. Sessions keyed by the old
+-- token digest can no longer be used.
+DELETE FROM sessions;
+
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS access_token TEXT;
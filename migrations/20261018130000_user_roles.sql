@@ -0,0 +1,7 @@
+-- Roles granted to users. Users without a row are viewers.
+CREATE TABLE IF NOT EXISTS user_roles (
+    user_id TEXT PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    role TEXT NOT NULL CHECK (role IN ('viewer', 'moderator', 'admin')),
+    granted_by TEXT REFERENCES users(id) ON DELETE SET NULL,
+    granted_at TIMESTAMP(6) NOT NULL
+);
@@ -0,0 +1,35 @@
+-- Append-only record of who accessed which part of the archive
+CREATE TABLE IF NOT EXISTS audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    occurred_at TIMESTAMP(6) NOT NULL,
+    -- No foreign keys, so that entries outlive the users and tokens they mention
+    user_id TEXT,
+    token_id BIGINT,
+    ip TEXT,
+    action TEXT NOT NULL,
+    params JSONB NOT NULL DEFAULT '{}'::jsonb
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_occurred_at_idx ON audit_log (occurred_at);
+CREATE INDEX IF NOT EXISTS audit_log_user_id_idx ON audit_log (user_id, occurred_at);
+
+-- Entries can never be changed, and can only be deleted by the retention job,
+-- which sets `opsa.audit_purge` for its transaction.
+CREATE OR REPLACE FUNCTION audit_log_protect() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'DELETE' AND current_setting('opsa.audit_purge', true) = 'on' THEN
+        RETURN OLD;
+    END IF;
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS audit_log_protect ON audit_log;
+CREATE TRIGGER audit_log_protect
+    BEFORE UPDATE OR DELETE ON audit_log
+    FOR EACH ROW EXECUTE FUNCTION audit_log_protect();
+
+DROP TRIGGER IF EXISTS audit_log_no_truncate ON audit_log;
+CREATE TRIGGER audit_log_no_truncate
+    BEFORE TRUNCATE ON audit_log
+    FOR EACH STATEMENT EXECUTE FUNCTION audit_log_protect();
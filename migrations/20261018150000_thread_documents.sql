@@ -0,0 +1,104 @@
+-- Combined full-text documents of whole threads, for searching threads as units.
+-- A thread is a top-level message and its replies, keyed by the top-level
+-- message's timestamp; messages without replies are threads of one.
+CREATE TABLE IF NOT EXISTS thread_documents (
+    channel_id TEXT NOT NULL REFERENCES channels(id) ON DELETE CASCADE,
+    thread_ts TIMESTAMP(6) NOT NULL,
+    doc_tsv tsvector NOT NULL,
+    message_count INTEGER NOT NULL,
+    last_ts TIMESTAMP(6) NOT NULL,
+    PRIMARY KEY (channel_id, thread_ts)
+);
+
+CREATE INDEX IF NOT EXISTS thread_documents_tsv_idx ON thread_documents USING GIN (doc_tsv);
+CREATE INDEX IF NOT EXISTS messages_thread_key_idx ON messages (channel_id, (COALESCE(thread_ts, ts)));
+
+-- Rebuilds the document of one thread from its messages, or drops it if the
+-- thread has no messages left. The text is capped to stay below the tsvector
+-- size limit on very long threads.
+CREATE OR REPLACE FUNCTION refresh_thread_document(p_channel_id TEXT, p_thread_ts TIMESTAMP) RETURNS void AS $$
+BEGIN
+    DELETE FROM thread_documents WHERE channel_id = p_channel_id AND thread_ts = p_thread_ts;
+    INSERT INTO thread_documents (channel_id, thread_ts, doc_tsv, message_count, last_ts)
+    SELECT
+        channel_id,
+        p_thread_ts,
+        to_tsvector('english', left(string_agg(msg_text, ' ' ORDER BY ts), 500000)),
+        COUNT(*),
+        MAX(ts)
+    FROM messages
+    WHERE channel_id = p_channel_id AND COALESCE(thread_ts, ts) = p_thread_ts
+    GROUP BY channel_id;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Refreshes each thread touched by a statement once, so that bulk imports do
+-- not rebuild a thread for every one of its messages. Updates only refresh
+-- the threads of rows whose thread key or text changed, since transition
+-- tables can't be combined with a column list.
+CREATE OR REPLACE FUNCTION messages_refresh_thread_documents() RETURNS trigger AS $$
+DECLARE
+    thread RECORD;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        FOR thread IN
+            SELECT DISTINCT channel_id, COALESCE(thread_ts, ts) AS thread_ts FROM new_rows
+        LOOP
+            PERFORM refresh_thread_document(thread.channel_id, thread.thread_ts);
+        END LOOP;
+    ELSIF TG_OP = 'DELETE' THEN
+        FOR thread IN
+            SELECT DISTINCT channel_id, COALESCE(thread_ts, ts) AS thread_ts FROM old_rows
+        LOOP
+            PERFORM refresh_thread_document(thread.channel_id, thread.thread_ts);
+        END LOOP;
+    ELSE
+        FOR thread IN
+            SELECT DISTINCT channel_id, COALESCE(thread_ts, ts) AS thread_ts
+            FROM (
+                (SELECT channel_id, user_id, ts, thread_ts, msg_text FROM old_rows
+                 EXCEPT ALL
+                 SELECT channel_id, user_id, ts, thread_ts, msg_text FROM new_rows)
+                UNION ALL
+                (SELECT channel_id, user_id, ts, thread_ts, msg_text FROM new_rows
+                 EXCEPT ALL
+                 SELECT channel_id, user_id, ts, thread_ts, msg_text FROM old_rows)
+            ) AS changed
+        LOOP
+            PERFORM refresh_thread_document(thread.channel_id, thread.thread_ts);
+        END LOOP;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Transition tables need one trigger per event
+DROP TRIGGER IF EXISTS messages_insert_refresh_thread_documents ON messages;
+CREATE TRIGGER messages_insert_refresh_thread_documents
+    AFTER INSERT ON messages
+    REFERENCING NEW TABLE AS new_rows
+    FOR EACH STATEMENT EXECUTE FUNCTION messages_refresh_thread_documents();
+
+DROP TRIGGER IF EXISTS messages_update_refresh_thread_documents ON messages;
+CREATE TRIGGER messages_update_refresh_thread_documents
+    AFTER UPDATE ON messages
+    REFERENCING OLD TABLE AS old_rows NEW TABLE AS new_rows
+    FOR EACH STATEMENT EXECUTE FUNCTION messages_refresh_thread_documents();
+
+DROP TRIGGER IF EXISTS messages_delete_refresh_thread_documents ON messages;
+CREATE TRIGGER messages_delete_refresh_thread_documents
+    AFTER DELETE ON messages
+    REFERENCING OLD TABLE AS old_rows
+    FOR EACH STATEMENT EXECUTE FUNCTION messages_refresh_thread_documents();
+
+-- Backfill the threads already in the archive
+INSERT INTO thread_documents (channel_id, thread_ts, doc_tsv, message_count, last_ts)
+SELECT
+    channel_id,
+    COALESCE(thread_ts, ts),
+    to_tsvector('english', left(string_agg(msg_text, ' ' ORDER BY ts), 500000)),
+    COUNT(*),
+    MAX(ts)
+FROM messages
+GROUP BY channel_id, COALESCE(thread_ts, ts)
+ON CONFLICT (channel_id, thread_ts) DO NOTHING;
@@ -0,0 +1,93 @@
+-- Lexemes of the archive with how often they occur, for suggesting corrections
+-- of misspelled search terms. Only public channels contribute, so suggestions
+-- never reveal words from private channels or DMs.
+CREATE TABLE IF NOT EXISTS search_vocabulary (
+    word TEXT PRIMARY KEY,
+    ndoc INTEGER NOT NULL,
+    nentry INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS search_vocabulary_trgm_idx ON search_vocabulary USING GIN (word gin_trgm_ops);
+
+-- Indexes up to p_limit messages imported without a tsvector, returning them.
+CREATE OR REPLACE FUNCTION index_new_messages(p_limit INTEGER) RETURNS messages[] AS $$
+    WITH newly_indexed AS (
+        UPDATE messages m
+        SET msg_tsv = to_tsvector('english', m.msg_text)
+        FROM (
+            SELECT channel_id, user_id, ts
+            FROM messages
+            WHERE msg_tsv IS NULL
+            LIMIT p_limit
+            FOR UPDATE SKIP LOCKED
+        ) AS batch
+        WHERE m.channel_id = batch.channel_id AND m.user_id = batch.user_id AND m.ts = batch.ts
+        RETURNING m
+    )
+    SELECT array_agg(m) FROM newly_indexed
+$$ LANGUAGE sql;
+
+-- Counts the lexemes of indexed messages into the vocabulary, skipping those
+-- of private channels. Numbers, single letters and oversized tokens make poor
+-- suggestions.
+CREATE OR REPLACE FUNCTION count_search_vocabulary(p_messages messages[]) RETURNS void AS $$
+    INSERT INTO search_vocabulary AS v (word, ndoc, nentry)
+    SELECT l.lexeme, COUNT(*), SUM(COALESCE(array_length(l.positions, 1), 1))
+    FROM unnest(p_messages) AS m
+    JOIN channels c ON c.id = m.channel_id
+    CROSS JOIN LATERAL unnest(m.msg_tsv) AS l
+    WHERE NOT c.is_private AND length(l.lexeme) BETWEEN 2 AND 40 AND l.lexeme !~ '^[0-9._-]+$'
+    GROUP BY l.lexeme
+    -- In a fixed order, so that concurrent refreshes do not deadlock.
+    ORDER BY l.lexeme
+    ON CONFLICT (word) DO UPDATE SET ndoc = v.ndoc + EXCLUDED.ndoc, nentry = v.nentry + EXCLUDED.nentry
+$$ LANGUAGE sql;
+
+-- Adds indexed messages to the vocabulary. Features built on the vocabulary
+-- replace this to update their own tables along with it.
+CREATE OR REPLACE FUNCTION add_to_search_vocabulary(p_messages messages[]) RETURNS void AS $$
+    SELECT count_search_vocabulary(p_messages)
+$$ LANGUAGE sql;
+
+-- Rebuilds the vocabulary from all indexed messages, in batches.
+CREATE OR REPLACE FUNCTION rebuild_search_vocabulary() RETURNS void AS $$
+DECLARE
+    message messages;
+    batch messages[] := '{}';
+BEGIN
+    DELETE FROM search_vocabulary;
+    FOR message IN SELECT * FROM messages WHERE msg_tsv IS NOT NULL LOOP
+        batch := batch || message;
+        IF cardinality(batch) >= 10000 THEN
+            PERFORM add_to_search_vocabulary(batch);
+            batch := '{}';
+        END IF;
+    END LOOP;
+    PERFORM add_to_search_vocabulary(batch);
+END;
+$$ LANGUAGE plpgsql;
+
+-- Indexes the messages imported without a tsvector and adds them to the
+-- vocabulary, or builds it if it is empty. Returns the number of newly
+-- indexed messages. Run after every import.
+CREATE OR REPLACE FUNCTION refresh_search_vocabulary() RETURNS INTEGER AS $$
+DECLARE
+    indexed INTEGER := 0;
+    batch messages[];
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM search_vocabulary) THEN
+        PERFORM rebuild_search_vocabulary();
+    END IF;
+
+    LOOP
+        batch := index_new_messages(10000);
+        EXIT WHEN batch IS NULL;
+        indexed := indexed + cardinality(batch);
+        PERFORM add_to_search_vocabulary(batch);
+    END LOOP;
+
+    RETURN indexed;
+END;
+$$ LANGUAGE plpgsql;
+
+SELECT refresh_search_vocabulary();
@@ -0,0 +1,64 @@
+-- Trigram indexes for autocompleting users and channels. They also serve
+-- prefix matches with LIKE.
+CREATE INDEX IF NOT EXISTS users_name_trgm_idx ON users USING GIN (lower(name) gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS users_real_name_trgm_idx ON users USING GIN (lower(real_name) gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS users_display_name_trgm_idx ON users USING GIN (lower(display_name) gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS channels_name_trgm_idx ON channels USING GIN (lower(name) gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS channels_topic_trgm_idx ON channels USING GIN (lower(topic) gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS channels_purpose_trgm_idx ON channels USING GIN (lower(purpose) gin_trgm_ops);
+
+-- How each lexeme of the vocabulary is most often spelled in the archive, so
+-- that terms can be suggested as people write them rather than stemmed.
+ALTER TABLE search_vocabulary ADD COLUMN IF NOT EXISTS spelling TEXT;
+CREATE INDEX IF NOT EXISTS search_vocabulary_spelling_trgm_idx ON search_vocabulary USING GIN (spelling gin_trgm_ops);
+
+-- How often each lexeme of the vocabulary is spelled each way.
+CREATE TABLE IF NOT EXISTS search_vocabulary_spellings (
+    word TEXT NOT NULL REFERENCES search_vocabulary(word) ON DELETE CASCADE,
+    spelling TEXT NOT NULL,
+    nentry INTEGER NOT NULL,
+    PRIMARY KEY (word, spelling)
+);
+
+-- Counts the unstemmed words of indexed messages under their lexemes, and
+-- updates the spellings of these lexemes. A message's words are parsed the
+-- same way as its tsvector, so each word shares its position with its lexeme.
+CREATE OR REPLACE FUNCTION count_search_vocabulary_spellings(p_messages messages[]) RETURNS void AS $$
+    INSERT INTO search_vocabulary_spellings AS s (word, spelling, nentry)
+    SELECT spelled.lexeme, spelled.word, COUNT(*)
+    FROM unnest(p_messages) AS m
+    JOIN channels c ON c.id = m.channel_id
+    CROSS JOIN LATERAL (
+        SELECT l.lexeme, w.lexeme AS word
+        FROM unnest(m.msg_tsv) AS l
+        CROSS JOIN LATERAL unnest(l.positions) AS lp(position)
+        JOIN (
+            SELECT w.lexeme, wp.position
+            FROM unnest(to_tsvector('simple', m.msg_text)) AS w
+            CROSS JOIN LATERAL unnest(w.positions) AS wp(position)
+        ) AS w ON w.position = lp.position
+    ) AS spelled
+    JOIN search_vocabulary v ON v.word = spelled.lexeme
+    WHERE NOT c.is_private AND length(spelled.word) BETWEEN 2 AND 40
+    GROUP BY spelled.lexeme, spelled.word
+    ORDER BY spelled.lexeme, spelled.word
+    ON CONFLICT (word, spelling) DO UPDATE SET nentry = s.nentry + EXCLUDED.nentry;
+
+    UPDATE search_vocabulary v
+    SET spelling = (
+        SELECT s.spelling
+        FROM search_vocabulary_spellings s
+        WHERE s.word = v.word
+        ORDER BY s.nentry DESC, s.spelling
+        LIMIT 1
+    )
+    WHERE v.word IN (SELECT l.lexeme FROM unnest(p_messages) AS m, unnest(m.msg_tsv) AS l);
+$$ LANGUAGE sql;
+
+CREATE OR REPLACE FUNCTION add_to_search_vocabulary(p_messages messages[]) RETURNS void AS $$
+    SELECT count_search_vocabulary(p_messages);
+    SELECT count_search_vocabulary_spellings(p_messages);
+$$ LANGUAGE sql;
+
+-- Rebuild the vocabulary with spellings
+SELECT rebuild_search_vocabulary();
@@ -0,0 +1,66 @@
+-- Embeddings of message texts for semantic search, stored with pgvector.
+-- Semantic search is optional: without the extension, nothing is created and
+-- excretor refuses to start with an embedding provider configured.
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_available_extensions WHERE name = 'vector') THEN
+        RAISE NOTICE 'pgvector is not available, semantic search is disabled.';
+        RETURN;
+    END IF;
+
+    CREATE EXTENSION IF NOT EXISTS vector;
+
+    -- The dimensions are set by use_embedding_model() for the model in use.
+    CREATE TABLE IF NOT EXISTS message_embeddings (
+        channel_id TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        ts TIMESTAMP(6) NOT NULL,
+        embedding vector NOT NULL,
+        PRIMARY KEY (channel_id, user_id, ts),
+        FOREIGN KEY (channel_id, user_id, ts) REFERENCES messages (channel_id, user_id, ts) ON DELETE CASCADE
+    );
+
+    -- The model whose embeddings are stored, a single row.
+    CREATE TABLE IF NOT EXISTS embedding_model (
+        id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+        model TEXT NOT NULL,
+        dimensions INTEGER NOT NULL
+    );
+
+    -- Edited messages are embedded again by the backfill job.
+    CREATE OR REPLACE FUNCTION messages_drop_embedding() RETURNS trigger AS $trigger$
+    BEGIN
+        DELETE FROM message_embeddings
+        WHERE channel_id = OLD.channel_id AND user_id = OLD.user_id AND ts = OLD.ts;
+        RETURN NULL;
+    END;
+    $trigger$ LANGUAGE plpgsql;
+
+    DROP TRIGGER IF EXISTS messages_drop_embedding ON messages;
+    CREATE TRIGGER messages_drop_embedding
+        AFTER UPDATE OF msg_text ON messages
+        FOR EACH ROW WHEN (OLD.msg_text IS DISTINCT FROM NEW.msg_text)
+        EXECUTE FUNCTION messages_drop_embedding();
+END $$;
+
+-- Switches the stored embeddings to another model, dropping the ones of the
+-- previous model. Returns whether anything changed.
+CREATE OR REPLACE FUNCTION use_embedding_model(p_model TEXT, p_dimensions INTEGER) RETURNS BOOLEAN AS $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM embedding_model WHERE model = p_model AND dimensions = p_dimensions) THEN
+        RETURN FALSE;
+    END IF;
+
+    DROP INDEX IF EXISTS message_embeddings_hnsw_idx;
+    TRUNCATE message_embeddings;
+    EXECUTE format('ALTER TABLE message_embeddings ALTER COLUMN embedding TYPE vector(%s)', p_dimensions);
+    CREATE INDEX message_embeddings_hnsw_idx ON message_embeddings USING hnsw (embedding vector_cosine_ops);
+
+    INSERT INTO embedding_model (id, model, dimensions) VALUES (TRUE, p_model, p_dimensions)
+    ON CONFLICT (id) DO UPDATE SET model = EXCLUDED.model, dimensions = EXCLUDED.dimensions;
+    RETURN TRUE;
+END;
+$$ LANGUAGE plpgsql;
+
+-- The backfill job embeds messages newest first.
+CREATE INDEX IF NOT EXISTS messages_ts_idx ON messages (ts);
@@ -0,0 +1,18 @@
+-- Code spans of messages, for code-aware search. The digester renders code
+-- blocks and inline code as `<code>` elements, whose HTML-escaped contents
+-- never contain a `<`. The English tsvector stems and splits identifiers like
+-- `get_user_info`, so code is matched verbatim with trigrams instead.
+CREATE OR REPLACE FUNCTION extract_code_spans(html TEXT) RETURNS TEXT AS $$
+    SELECT replace(replace(replace(replace(replace(
+        string_agg(span[1], E'\n'),
+        '&lt;', '<'), '&gt;', '>'), '&quot;', '"'), '&#39;', ''''), '&amp;', '&')
+    FROM regexp_matches(html, '<code[^>]*>([^<]*)</code>', 'g') AS span
+    WHERE span[1] <> ''
+$$ LANGUAGE sql IMMUTABLE STRICT;
+
+-- The code spans of each message joined by newlines, NULL without code.
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS msg_code TEXT
+    GENERATED ALWAYS AS (extract_code_spans(msg_text)) STORED;
+
+CREATE INDEX IF NOT EXISTS messages_code_trgm_idx ON messages USING GIN (msg_code gin_trgm_ops)
+    WHERE msg_code IS NOT NULL;
@@ -0,0 +1,141 @@
+-- Text search configurations (languages) for full-text search. Messages are
+-- indexed with their channel's configuration, or the default one set by
+-- excretor from SEARCH_LANGUAGE, and msg_tsv_config records which one so that
+-- queries can be parsed the same way. Changing a configuration only takes
+-- effect for existing messages after reindex_search().
+CREATE TABLE IF NOT EXISTS search_settings (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    default_config REGCONFIG NOT NULL
+);
+INSERT INTO search_settings (id, default_config) VALUES (TRUE, 'english') ON CONFLICT (id) DO NOTHING;
+
+-- The name of the channel's configuration, checked by excretor.
+ALTER TABLE channels ADD COLUMN IF NOT EXISTS search_config TEXT;
+
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS msg_tsv_config REGCONFIG;
+UPDATE messages SET msg_tsv_config = 'english' WHERE msg_tsv IS NOT NULL AND msg_tsv_config IS NULL;
+CREATE INDEX IF NOT EXISTS messages_tsv_config_idx ON messages (msg_tsv_config);
+
+ALTER TABLE thread_documents ADD COLUMN IF NOT EXISTS doc_tsv_config REGCONFIG;
+UPDATE thread_documents SET doc_tsv_config = 'english' WHERE doc_tsv_config IS NULL;
+ALTER TABLE thread_documents ALTER COLUMN doc_tsv_config SET NOT NULL;
+
+CREATE OR REPLACE FUNCTION default_search_config() RETURNS REGCONFIG AS $$
+    SELECT COALESCE((SELECT default_config FROM search_settings), 'english'::regconfig)
+$$ LANGUAGE sql STABLE;
+
+CREATE OR REPLACE FUNCTION channel_search_config(p_channel_id TEXT) RETURNS REGCONFIG AS $$
+    SELECT COALESCE((SELECT search_config::regconfig FROM channels WHERE id = p_channel_id), default_search_config())
+$$ LANGUAGE sql STABLE;
+
+-- Every configuration messages are or will be indexed with. The ones in use
+-- are found with a loose index scan, as there are only a few.
+CREATE OR REPLACE FUNCTION search_configs() RETURNS REGCONFIG[] AS $$
+    WITH RECURSIVE used AS (
+        (SELECT msg_tsv_config AS config FROM messages WHERE msg_tsv_config IS NOT NULL ORDER BY msg_tsv_config LIMIT 1)
+        UNION ALL
+        SELECT (
+            SELECT msg_tsv_config FROM messages WHERE msg_tsv_config > used.config ORDER BY msg_tsv_config LIMIT 1
+        )
+        FROM used
+        WHERE used.config IS NOT NULL
+    )
+    SELECT array_agg(DISTINCT config) FROM (
+        SELECT config FROM used WHERE config IS NOT NULL
+        UNION SELECT search_config::regconfig FROM channels WHERE search_config IS NOT NULL
+        UNION SELECT default_search_config()
+    ) AS configs
+$$ LANGUAGE sql STABLE;
+
+-- The query parsed with every configuration in use, OR-ed together. Matching
+-- it can use the full-text indexes whatever the configuration of each row;
+-- rows must then be checked against the query parsed with their own one.
+CREATE OR REPLACE FUNCTION websearch_to_tsquery_any(p_query TEXT) RETURNS tsquery AS $$
+DECLARE
+    config REGCONFIG;
+    result tsquery;
+BEGIN
+    FOREACH config IN ARRAY search_configs() LOOP
+        IF result IS NULL THEN
+            result := websearch_to_tsquery(config, p_query);
+        ELSE
+            result := result || websearch_to_tsquery(config, p_query);
+        END IF;
+    END LOOP;
+    RETURN result;
+END;
+$$ LANGUAGE plpgsql STABLE;
+
+-- Thread documents are indexed with the configuration of their channel.
+CREATE OR REPLACE FUNCTION refresh_thread_document(p_channel_id TEXT, p_thread_ts TIMESTAMP) RETURNS void AS $$
+DECLARE
+    config REGCONFIG := channel_search_config(p_channel_id);
+BEGIN
+    DELETE FROM thread_documents WHERE channel_id = p_channel_id AND thread_ts = p_thread_ts;
+    INSERT INTO thread_documents (channel_id, thread_ts, doc_tsv, doc_tsv_config, message_count, last_ts)
+    SELECT
+        channel_id,
+        p_thread_ts,
+        to_tsvector(config, left(string_agg(msg_text, ' ' ORDER BY ts), 500000)),
+        config,
+        COUNT(*),
+        MAX(ts)
+    FROM messages
+    WHERE channel_id = p_channel_id AND COALESCE(thread_ts, ts) = p_thread_ts
+    GROUP BY channel_id;
+END;
+$$ LANGUAGE plpgsql;
+
+-- As before, but imported messages are indexed with their channel's
+-- configuration.
+CREATE OR REPLACE FUNCTION index_new_messages(p_limit INTEGER) RETURNS messages[] AS $$
+    WITH newly_indexed AS (
+        UPDATE messages m
+        SET msg_tsv = to_tsvector(c.config, m.msg_text), msg_tsv_config = c.config
+        FROM (
+            SELECT channel_id, user_id, ts
+            FROM messages
+            WHERE msg_tsv IS NULL
+            LIMIT p_limit
+            FOR UPDATE SKIP LOCKED
+        ) AS batch,
+        (SELECT id, COALESCE(search_config::regconfig, default_search_config()) AS config FROM channels) AS c
+        WHERE m.channel_id = batch.channel_id AND m.user_id = batch.user_id AND m.ts = batch.ts
+            AND c.id = m.channel_id
+        RETURNING m
+    )
+    SELECT array_agg(m) FROM newly_indexed
+$$ LANGUAGE sql;
+
+-- Rebuilds the tsvectors of the messages and threads of one channel, or of
+-- all channels, that are not indexed with their channel's configuration, and
+-- then the vocabulary. Returns the number of reindexed messages.
+CREATE OR REPLACE FUNCTION reindex_search(p_channel_id TEXT) RETURNS INTEGER AS $$
+DECLARE
+    reindexed INTEGER;
+    doc RECORD;
+BEGIN
+    UPDATE messages m
+    SET msg_tsv = to_tsvector(c.config, m.msg_text), msg_tsv_config = c.config
+    FROM (SELECT id, COALESCE(search_config::regconfig, default_search_config()) AS config FROM channels) AS c
+    WHERE c.id = m.channel_id
+        AND (p_channel_id IS NULL OR m.channel_id = p_channel_id)
+        AND (m.msg_tsv IS NULL OR m.msg_tsv_config IS DISTINCT FROM c.config);
+    GET DIAGNOSTICS reindexed = ROW_COUNT;
+
+    FOR doc IN
+        SELECT t.channel_id, t.thread_ts
+        FROM thread_documents t
+        JOIN channels c ON c.id = t.channel_id
+        WHERE (p_channel_id IS NULL OR t.channel_id = p_channel_id)
+            AND t.doc_tsv_config <> COALESCE(c.search_config::regconfig, default_search_config())
+    LOOP
+        PERFORM refresh_thread_document(doc.channel_id, doc.thread_ts);
+    END LOOP;
+
+    IF reindexed > 0 THEN
+        PERFORM rebuild_search_vocabulary();
+    END IF;
+    RETURN reindexed;
+END;
+$$ LANGUAGE plpgsql;
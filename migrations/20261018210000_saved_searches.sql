@@ -0,0 +1,59 @@
+-- Searches users saved to run again. Saved searches that are alerts are run by
+-- excretor against newly indexed messages, and their matches make up the
+-- user's alert feed and are optionally POSTed to a webhook.
+CREATE TABLE IF NOT EXISTS saved_searches (
+    id BIGSERIAL PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    -- In the search query language
+    query TEXT NOT NULL,
+    alert BOOLEAN NOT NULL DEFAULT FALSE,
+    webhook_url TEXT,
+    created_at TIMESTAMP(6) NOT NULL,
+    updated_at TIMESTAMP(6) NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS saved_searches_user_id_idx ON saved_searches (user_id);
+CREATE INDEX IF NOT EXISTS saved_searches_alert_idx ON saved_searches (id) WHERE alert;
+
+-- Messages indexed since alerts were last checked. Rows committed while a
+-- check runs are picked up by the next one.
+CREATE TABLE IF NOT EXISTS search_alert_queue (
+    id BIGSERIAL PRIMARY KEY,
+    channel_id TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    ts TIMESTAMP(6) NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS search_alert_matches (
+    id BIGSERIAL PRIMARY KEY,
+    saved_search_id BIGINT NOT NULL REFERENCES saved_searches(id) ON DELETE CASCADE,
+    channel_id TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    ts TIMESTAMP(6) NOT NULL,
+    highlights TEXT[] NOT NULL,
+    matched_at TIMESTAMP(6) NOT NULL,
+    -- When the match was POSTed to the saved search's webhook
+    delivered_at TIMESTAMP(6),
+    UNIQUE (saved_search_id, channel_id, user_id, ts),
+    FOREIGN KEY (channel_id, user_id, ts) REFERENCES messages(channel_id, user_id, ts) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS search_alert_matches_undelivered_idx
+    ON search_alert_matches (saved_search_id, id) WHERE delivered_at IS NULL;
+
+-- Newly indexed messages are queued for alerts, if there are any.
+CREATE OR REPLACE FUNCTION messages_queue_search_alerts() RETURNS trigger AS $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM saved_searches WHERE alert) THEN
+        INSERT INTO search_alert_queue (channel_id, user_id, ts) VALUES (NEW.channel_id, NEW.user_id, NEW.ts);
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS messages_queue_search_alerts ON messages;
+CREATE TRIGGER messages_queue_search_alerts
+    AFTER UPDATE OF msg_tsv ON messages
+    FOR EACH ROW WHEN (OLD.msg_tsv IS NULL AND NEW.msg_tsv IS NOT NULL)
+    EXECUTE FUNCTION messages_queue_search_alerts();
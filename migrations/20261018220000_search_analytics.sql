@@ -0,0 +1,17 @@
+-- Searches run, for finding out what people look for and which searches fail
+-- them. Depending on SEARCH_ANALYTICS, who ran a search is either not recorded
+-- or only as a salted hash of their user ID, which tells users apart without
+-- revealing them. The audit log is separate and unaffected.
+CREATE TABLE IF NOT EXISTS search_query_log (
+    id BIGSERIAL PRIMARY KEY,
+    occurred_at TIMESTAMP(6) NOT NULL,
+    -- Lowercased with runs of whitespace collapsed, so that the same query
+    -- typed differently is counted together
+    query TEXT NOT NULL,
+    user_pseudonym TEXT,
+    mode TEXT NOT NULL,
+    total_hits BIGINT NOT NULL,
+    latency_ms DOUBLE PRECISION NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS search_query_log_occurred_at_idx ON search_query_log (occurred_at);
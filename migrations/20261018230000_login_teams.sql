@@ -0,0 +1,5 @@
+-- The Slack team a session was logged in with, and that API tokens created
+-- from it inherit, so that ALLOWED_SLACK_TEAM_IDS applies to them after login.
+-- Sessions and tokens from before are rejected while the allowlist is set.
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS team_id TEXT;
+ALTER TABLE api_tokens ADD COLUMN IF NOT EXISTS team_id TEXT;